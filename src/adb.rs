@@ -0,0 +1,266 @@
+use std::{borrow::Cow, ffi::OsStr, path::Path, time::Duration};
+
+use itertools::Itertools;
+
+use crate::{
+    constants::adb_path,
+    error::{Context, Error},
+    progress::{ProgressReporter, Task},
+    runner::{ToolCommand, ToolRunner},
+};
+
+/// Limit for quick queries, so a hanging adb server does not block forever
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An adb invocation
+pub fn adb_command<I, S>(args: I) -> ToolCommand
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    ToolCommand::new("adb", adb_path()).args(args)
+}
+
+/// An adb invocation on one device, for when several are connected
+pub fn device_command<I, S>(serial: &str, args: I) -> ToolCommand
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    adb_command(["-s", serial]).args(args)
+}
+
+/// Quotes an argument for the device shell, which `adb shell` joins its arguments into.
+/// Arguments without special characters stay as they are
+pub fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-.,:/=@%+".contains(c);
+    match !arg.is_empty() && arg.chars().all(plain) {
+        true => Cow::Borrowed(arg),
+        false => Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''"))),
+    }
+}
+
+/// Runs an adb command on one device and returns its stdout
+pub fn device_output<I, S>(runner: &dyn ToolRunner, serial: &str, args: I) -> crate::Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    runner.run(&device_command(serial, args))
+}
+
+/// Copies a file into a folder on the device, creating the folder first
+pub fn push(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    path: &Path,
+    device_dir: &str,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<()> {
+    device_output(runner, serial, ["shell", "mkdir", "-p", device_dir])
+        .with_context(|| format!("Failed to create {device_dir} on the device"))?;
+
+    // adb only shows push progress on a terminal, so only the size is reported
+    let size = std::fs::metadata(path).map(|m| m.len()).ok();
+    reporter.start(
+        Task::Push,
+        format!("Pushing {} to {device_dir}", path.display()),
+        size,
+    );
+    device_output(
+        runner,
+        serial,
+        [OsStr::new("push"), path.as_os_str(), OsStr::new(device_dir)],
+    )
+    .with_context(|| format!("Failed to copy {} to the device", path.display()))?;
+    if let Some(size) = size {
+        reporter.progress(Task::Push, size);
+    }
+    reporter.finish(Task::Push, format!("Pushed {}", path.display()));
+    Ok(())
+}
+
+/// Serials of the devices adb can talk to
+pub fn connected_devices(runner: &dyn ToolRunner) -> crate::Result<Vec<String>> {
+    let output = runner.run(&adb_command(["devices"]).timeout(QUERY_TIMEOUT))?;
+    Ok(output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (serial, state) = line.split_whitespace().collect_tuple()?;
+            (state == "device").then(|| serial.to_string())
+        })
+        .collect())
+}
+
+/// Serial of the device adb commands go to.
+/// ANDROID_SERIAL picks one like it does for adb itself
+pub fn device(runner: &dyn ToolRunner) -> crate::Result<String> {
+    let devices = connected_devices(runner)?;
+    let requested = std::env::var("ANDROID_SERIAL").ok();
+    pick_device(
+        &devices,
+        requested.as_deref().filter(|serial| !serial.is_empty()),
+    )
+}
+
+/// Picks the `requested` device, or the only connected one if none was requested.
+/// A requested device that is not connected is an error, never replaced by another device
+pub fn pick_device(devices: &[String], requested: Option<&str>) -> crate::Result<String> {
+    if let Some(serial) = requested {
+        return match devices.iter().any(|device| device == serial) {
+            true => Ok(serial.to_string()),
+            false => Err(Error::DeviceNotFound(serial.to_string())),
+        };
+    }
+    match devices {
+        [] => Err(Error::NoDevice),
+        [serial] => Ok(serial.clone()),
+        _ => Err(Error::MultipleDevices(devices.to_vec())),
+    }
+}
+
+/// Reads a system property from a device
+pub fn getprop(runner: &dyn ToolRunner, serial: &str, name: &str) -> crate::Result<String> {
+    let command = device_command(serial, ["shell", "getprop", name]).timeout(QUERY_TIMEOUT);
+    Ok(runner.run(&command)?.trim().to_string())
+}
+
+/// The ABIs a device can run and how it runs them
+#[derive(Debug, Clone)]
+pub struct DeviceAbis {
+    /// `ro.product.cpu.abilist`, in order of preference
+    pub abilist: Vec<String>,
+    /// The native bridge library translating foreign ABIs, e.g. `libndk_translation.so`
+    pub native_bridge: Option<String>,
+    /// `ro.dalvik.vm.native.bridge` as the device reports it, `0` or empty when disabled
+    pub native_bridge_property: String,
+}
+
+impl DeviceAbis {
+    /// Queries the ABIs of a device
+    pub fn query(runner: &dyn ToolRunner, serial: &str) -> crate::Result<Self> {
+        let abilist = getprop(runner, serial, "ro.product.cpu.abilist")?
+            .split(',')
+            .map(str::trim)
+            .filter(|abi| !abi.is_empty())
+            .map(str::to_string)
+            .collect();
+        let native_bridge_property = getprop(runner, serial, "ro.dalvik.vm.native.bridge")?;
+        // "0" or empty means native bridge is disabled
+        let native_bridge = Some(native_bridge_property.clone())
+            .filter(|bridge| !bridge.is_empty() && bridge != "0");
+
+        Ok(Self {
+            abilist,
+            native_bridge,
+            native_bridge_property,
+        })
+    }
+
+    /// The ABI family the device CPU runs natively
+    pub fn native_family(&self) -> Option<AbiFamily> {
+        self.abilist.first().map(|abi| AbiFamily::of(abi))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbiFamily {
+    Arm,
+    X86,
+    Other,
+}
+
+impl AbiFamily {
+    pub fn of(abi: &str) -> Self {
+        match abi {
+            "arm64-v8a" | "armeabi-v7a" | "armeabi" => AbiFamily::Arm,
+            "x86_64" | "x86" => AbiFamily::X86,
+            _ => AbiFamily::Other,
+        }
+    }
+}
+
+/// How a device would run the native libraries of an APK
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiCompatibility {
+    /// The APK has no native libraries
+    NoNativeLibs,
+    /// The device runs the ABI natively
+    Native { abi: String },
+    /// The device runs the ABI through native bridge translation
+    Translated { abi: String, bridge: String },
+    /// The device lists the ABI but native bridge is disabled, so the app will crash on load
+    BridgeDisabled { abi: String },
+    /// The device cannot run any ABI the APK ships
+    Incompatible { apk_abis: Vec<String> },
+}
+
+impl AbiCompatibility {
+    /// Picks the ABI the package manager would install, following the device's preference order
+    pub fn check(apk_abis: &[String], device: &DeviceAbis) -> Self {
+        if apk_abis.is_empty() {
+            return AbiCompatibility::NoNativeLibs;
+        }
+
+        let Some(abi) = device
+            .abilist
+            .iter()
+            .find(|abi| apk_abis.contains(abi))
+            .cloned()
+        else {
+            return AbiCompatibility::Incompatible {
+                apk_abis: apk_abis.to_vec(),
+            };
+        };
+
+        if device.native_family() == Some(AbiFamily::of(&abi)) {
+            return AbiCompatibility::Native { abi };
+        }
+        match &device.native_bridge {
+            Some(bridge) => AbiCompatibility::Translated {
+                abi,
+                bridge: bridge.clone(),
+            },
+            None => AbiCompatibility::BridgeDisabled { abi },
+        }
+    }
+
+    /// Whether installing is expected to give a working app
+    pub fn is_compatible(&self) -> bool {
+        !matches!(
+            self,
+            AbiCompatibility::BridgeDisabled { .. } | AbiCompatibility::Incompatible { .. }
+        )
+    }
+
+    /// A human readable explanation of the result
+    pub fn diagnosis(&self, device: &DeviceAbis) -> String {
+        let device_abis = device.abilist.join(", ");
+        match self {
+            AbiCompatibility::NoNativeLibs => "APK has no native libraries".to_string(),
+            AbiCompatibility::Native { abi } => format!("Device runs {abi} natively"),
+            AbiCompatibility::Translated { abi, bridge } => format!(
+                "Device runs {abi} through native bridge translation ({bridge}), expect lower performance"
+            ),
+            AbiCompatibility::BridgeDisabled { abi } => format!(
+                "Device lists {abi} but native bridge translation is disabled (ro.dalvik.vm.native.bridge={:?}), the app will crash when loading its native libraries. Use a system image with ARM translation enabled",
+                device.native_bridge_property
+            ),
+            AbiCompatibility::Incompatible { apk_abis } => {
+                let apk_abis = apk_abis.join(", ");
+                let hint = match apk_abis.contains("arm")
+                    && device.native_family() == Some(AbiFamily::X86)
+                {
+                    true => {
+                        "Quest APKs only ship ARM libraries. Use an x86_64 system image with ARM translation (Android 11+ google_apis images) or an arm64-v8a image on an ARM host"
+                    }
+                    false => "Use a system image matching one of the APK's ABIs",
+                };
+                format!(
+                    "APK ships native libraries for [{apk_abis}] but the device only supports [{device_abis}]. {hint}"
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! DLC and additional asset files downloaded next to an APK.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{apk::obb::device_obb_dir, error::Context};
+
+/// Name of the manifest listing the additional files of a version folder
+pub const ASSETS_FILE: &str = "quest_emu_assets.json";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AssetManifest {
+    /// Package the files belong to
+    pub package: String,
+    pub files: Vec<AssetFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetFile {
+    /// Path relative to the folder the manifest is in
+    pub path: PathBuf,
+    pub target: AssetTarget,
+}
+
+/// How an additional file gets onto the device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AssetTarget {
+    /// DLC shipped as its own APK, installed with `adb install`
+    Apk,
+    /// Pushed to a folder on the device, the OBB folder for store asset files
+    Push { device_dir: String },
+}
+
+impl AssetManifest {
+    /// Lists `files` inside `dir` as additional files of `package`
+    pub fn new(package: &str, dir: &Path, files: &[PathBuf]) -> crate::Result<Self> {
+        let files = files
+            .iter()
+            .map(|file| {
+                let path = file
+                    .strip_prefix(dir)
+                    .with_context(|| format!("{} is not inside {}", file.display(), dir.display()))?
+                    .to_path_buf();
+                let target = match path.extension().is_some_and(|e| e == "apk") {
+                    true => AssetTarget::Apk,
+                    false => AssetTarget::Push {
+                        device_dir: device_obb_dir(package),
+                    },
+                };
+                Ok(AssetFile { path, target })
+            })
+            .collect::<crate::Result<_>>()?;
+
+        Ok(Self {
+            package: package.to_string(),
+            files,
+        })
+    }
+
+    /// Loads the manifest of a folder, `None` if nothing extra was downloaded there
+    pub fn load(dir: &Path) -> crate::Result<Option<Self>> {
+        let path = dir.join(ASSETS_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, dir: &Path) -> crate::Result<()> {
+        let path = dir.join(ASSETS_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
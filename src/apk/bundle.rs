@@ -0,0 +1,126 @@
+//! Split APK sets and the bundle archives they are distributed in.
+
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    apk::{info::read_manifest_info, obb::is_package_obb},
+    error::{Context, bail},
+};
+
+/// Extensions of split APK bundle archives:
+/// `.apks` (bundletool, SAI), `.xapk` (APKPure) and `.apkm` (APKMirror)
+pub const BUNDLE_EXTENSIONS: [&str; 3] = ["apks", "xapk", "apkm"];
+
+/// A base APK with its split APKs and expansion files
+#[derive(Debug, Clone, Serialize)]
+pub struct ApkSet {
+    pub package: String,
+    pub base: PathBuf,
+    /// Split APKs of the base, e.g. `split_config.arm64_v8a.apk`
+    pub splits: Vec<PathBuf>,
+    pub obbs: Vec<PathBuf>,
+}
+
+impl ApkSet {
+    /// An APK without splits
+    pub fn single(package: String, base: PathBuf, obbs: Vec<PathBuf>) -> Self {
+        Self {
+            package,
+            base,
+            splits: Vec::new(),
+            obbs,
+        }
+    }
+
+    /// Finds the base APK, its splits and OBBs in a directory.
+    /// APKs of other packages than `package`, if given, and OBBs of other packages are ignored.
+    pub fn from_dir(dir: &Path, package: Option<&str>) -> crate::Result<Self> {
+        let mut bases = Vec::new();
+        let mut splits = Vec::new();
+        let mut obbs = Vec::new();
+
+        for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+            let path = entry.path();
+            // bundletool sets contain full APKs for pre-Lollipop devices, which are not splits
+            if !entry.file_type().is_file()
+                || path.components().any(|c| c.as_os_str() == "standalones")
+            {
+                continue;
+            }
+
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("obb") => obbs.push(path.to_path_buf()),
+                Some("apk") => {
+                    let manifest = read_manifest_info(path)?;
+                    if package.is_some_and(|p| p != manifest.package) {
+                        continue;
+                    }
+                    match manifest.split {
+                        Some(_) => splits.push((manifest.package, path.to_path_buf())),
+                        None => bases.push((manifest.package, path.to_path_buf())),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (package, base) = match bases.len() {
+            0 => bail!("No base APK found in {}", dir.display()),
+            1 => bases.remove(0),
+            _ => bail!(
+                "Found multiple base APKs in {}: {}",
+                dir.display(),
+                bases.iter().map(|(_, path)| path.display()).join(", ")
+            ),
+        };
+
+        let splits = splits
+            .into_iter()
+            .filter(|(split_package, _)| *split_package == package)
+            .map(|(_, path)| path)
+            .collect();
+
+        // Bundles may carry the OBBs of several apps
+        obbs.retain(|path| is_package_obb(path, &package));
+
+        Ok(Self {
+            package,
+            base,
+            splits,
+            obbs,
+        })
+    }
+
+    /// The base APK followed by its splits, the order `adb install-multiple` expects
+    pub fn apks(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.base).chain(&self.splits)
+    }
+}
+
+/// Whether a path is a split APK bundle archive
+pub fn is_bundle(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| BUNDLE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Unpacks a bundle archive into `dest` and finds the APK set inside
+pub fn extract_bundle(bundle: &Path, dest: &Path) -> crate::Result<ApkSet> {
+    let file = std::fs::File::open(bundle)
+        .with_context(|| format!("Failed to open {}", bundle.display()))?;
+    let mut archive = zip::ZipArchive::new(file).with_context(|| {
+        format!(
+            "Failed to read {} as zip file, encrypted .apkm bundles are not supported",
+            bundle.display()
+        )
+    })?;
+    archive
+        .extract(dest)
+        .with_context(|| format!("Failed to extract {}", bundle.display()))?;
+
+    ApkSet::from_dir(dest, None)
+}
@@ -0,0 +1,101 @@
+//! Tracks the files `apk download` created, so `--cleanup` never touches anything else.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{apk::patch_state::file_sha256, error::Context};
+
+/// Name of the manifest written next to the downloaded files
+pub const DOWNLOADS_FILE: &str = "quest_emu_downloads.json";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    pub files: Vec<DownloadedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadedFile {
+    /// Path relative to the folder the manifest is in
+    pub path: PathBuf,
+    /// Hex encoded SHA-256 when recorded, files that changed since are left alone
+    pub sha256: String,
+}
+
+/// What [`cleanup_downloads`] did with the tracked files
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CleanupReport {
+    pub removed: Vec<PathBuf>,
+    /// Files that changed since they were downloaded
+    pub kept: Vec<PathBuf>,
+}
+
+impl DownloadManifest {
+    /// Loads the manifest of a folder, empty if nothing was downloaded there
+    pub fn load(dir: &Path) -> crate::Result<Self> {
+        let path = dir.join(DOWNLOADS_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, dir: &Path) -> crate::Result<()> {
+        let path = dir.join(DOWNLOADS_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Records a file inside `dir`, updating its hash if it is already tracked
+    pub fn record(&mut self, dir: &Path, file: &Path) -> crate::Result<()> {
+        let relative = file
+            .strip_prefix(dir)
+            .with_context(|| format!("{} is not inside {}", file.display(), dir.display()))?
+            .to_path_buf();
+        let sha256 = file_sha256(file)?;
+
+        match self.files.iter_mut().find(|f| f.path == relative) {
+            Some(existing) => existing.sha256 = sha256,
+            None => self.files.push(DownloadedFile {
+                path: relative,
+                sha256,
+            }),
+        }
+        Ok(())
+    }
+}
+
+/// Removes the tracked files of `dir` and the manifest itself.
+/// Files modified since they were downloaded are kept.
+pub fn cleanup_downloads(dir: &Path) -> crate::Result<CleanupReport> {
+    let manifest = DownloadManifest::load(dir)?;
+    let mut report = CleanupReport::default();
+
+    for file in manifest.files {
+        let path = dir.join(&file.path);
+        if !path.is_file() {
+            continue;
+        }
+        if file_sha256(&path)? != file.sha256 {
+            report.kept.push(path);
+            continue;
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        report.removed.push(path);
+    }
+
+    let manifest_path = dir.join(DOWNLOADS_FILE);
+    if manifest_path.exists() {
+        std::fs::remove_file(&manifest_path)
+            .with_context(|| format!("Failed to remove {}", manifest_path.display()))?;
+    }
+
+    // The folder was created by the download, drop it if nothing else is left.
+    // remove_dir fails on non-empty folders, which is what we want.
+    let _ = std::fs::remove_dir(dir);
+
+    Ok(report)
+}
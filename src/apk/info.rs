@@ -0,0 +1,116 @@
+use std::{fs::File, io::Read, path::Path};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    apk::{
+        manifest::{MANIFEST_FILE, ManifestInfo, decode_manifest},
+        patch_state::{PATCH_STATE_FILE, PatchState},
+    },
+    error::Context,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NativeLib {
+    pub path: String,
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+/// Metadata about an APK file
+#[derive(Debug, Clone, Serialize)]
+pub struct ApkInfo {
+    #[serde(flatten)]
+    pub manifest: ManifestInfo,
+    /// ABIs with native libraries under `lib/`
+    pub abis: Vec<String>,
+    pub native_libs: Vec<NativeLib>,
+    pub file_size: u64,
+    /// What `apk patch` applied to this APK, if anything
+    pub patch_state: Option<PatchState>,
+}
+
+/// Opens an APK for reading with the zip crate
+pub fn open_apk(path: &Path) -> crate::Result<zip::ZipArchive<File>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    zip::ZipArchive::new(file).context("Failed to read APK as zip file")
+}
+
+/// Reads the raw binary manifest from an APK
+pub fn read_manifest_bytes(apk: &mut zip::ZipArchive<File>) -> crate::Result<Vec<u8>> {
+    let mut manifest_bytes = Vec::new();
+    apk.by_name(MANIFEST_FILE)
+        .context("APK has no AndroidManifest.xml")?
+        .read_to_end(&mut manifest_bytes)?;
+    Ok(manifest_bytes)
+}
+
+/// Reads and decodes the manifest of an APK
+pub fn read_manifest_info(path: &Path) -> crate::Result<ManifestInfo> {
+    let mut apk = open_apk(path)?;
+    let xml_str = decode_manifest(read_manifest_bytes(&mut apk)?, false)?;
+    ManifestInfo::parse(&xml_str).with_context(|| format!("Invalid manifest in {}", path.display()))
+}
+
+/// Reads the manifest and native library metadata of an APK
+pub fn read_apk_info(path: &Path) -> crate::Result<ApkInfo> {
+    let mut apk = open_apk(path)?;
+
+    let xml_str = decode_manifest(read_manifest_bytes(&mut apk)?, false)?;
+    let manifest = ManifestInfo::parse(&xml_str)?;
+
+    let patch_state = match apk.by_name(PATCH_STATE_FILE) {
+        Ok(mut file) => {
+            let mut json = Vec::new();
+            file.read_to_end(&mut json)?;
+            Some(PatchState::from_json(&json)?)
+        }
+        Err(zip::result::ZipError::FileNotFound) => None,
+        Err(e) => return Err(e).context("Failed to read patch state"),
+    };
+
+    let mut native_libs = Vec::new();
+    for i in 0..apk.len() {
+        let entry = apk.by_index_raw(i)?;
+        if entry.is_dir() || !entry.name().starts_with("lib/") {
+            continue;
+        }
+        native_libs.push(NativeLib {
+            path: entry.name().to_string(),
+            size: entry.size(),
+            compressed_size: entry.compressed_size(),
+        });
+    }
+
+    let abis = native_libs
+        .iter()
+        .filter_map(|lib| lib.path.split('/').nth(1))
+        .unique()
+        .map(str::to_string)
+        .collect();
+
+    Ok(ApkInfo {
+        manifest,
+        abis,
+        native_libs,
+        file_size: std::fs::metadata(path)?.len(),
+        patch_state,
+    })
+}
+
+/// Formats a byte count with binary units, e.g. "12.3 MiB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
@@ -0,0 +1,146 @@
+//! Installing APK sets with their OBBs and additional files on a device.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    adb::{AbiCompatibility, DeviceAbis, device, device_output, push},
+    apk::{
+        assets::{AssetManifest, AssetTarget},
+        bundle::ApkSet,
+        info::read_apk_info,
+        obb::{device_obb_dir, validate_obbs_for_apk},
+    },
+    error::{Context, Error},
+    progress::{ProgressReporter, Task},
+    runner::ToolRunner,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Install even if the device cannot run the APK's native libraries
+    pub skip_abi_check: bool,
+}
+
+/// What [`install`] did
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallReport {
+    /// Serial of the device the APK was installed on
+    pub serial: String,
+    pub package: String,
+    /// Installed APKs, the base first
+    pub apks: Vec<PathBuf>,
+    /// OBBs pushed to [`device_obb_dir`]
+    pub obbs: Vec<PathBuf>,
+    /// DLC and asset files from `apk download --dlc/--assets`
+    pub assets: Vec<PathBuf>,
+    /// Explanation of how the device runs the native libraries, `None` if not checked
+    pub abi_diagnosis: Option<String>,
+}
+
+/// Installs an APK set on the connected device and pushes its OBBs
+/// and the additional files listed next to the base APK
+pub fn install(
+    runner: &dyn ToolRunner,
+    apk_set: &ApkSet,
+    options: &InstallOptions,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<InstallReport> {
+    let serial = device(runner)?;
+    let abi_diagnosis = match options.skip_abi_check {
+        true => None,
+        false => Some(check_device_abis(runner, &serial, apk_set)?),
+    };
+
+    // Additional files from `apk download --dlc/--assets` are listed next to the APK
+    let asset_dir = apk_set.base.parent().unwrap_or(Path::new("."));
+    let assets = AssetManifest::load(asset_dir)?;
+    let asset_paths: Vec<_> = assets
+        .iter()
+        .flat_map(|manifest| &manifest.files)
+        .map(|file| asset_dir.join(&file.path))
+        .collect();
+    let obb_paths: Vec<_> = apk_set
+        .obbs
+        .iter()
+        .filter(|obb| !asset_paths.contains(obb))
+        .cloned()
+        .collect();
+
+    // Check the OBBs before installing so a mismatched download fails early
+    let obbs = validate_obbs_for_apk(&obb_paths, &apk_set.base)?;
+
+    let install_command = match apk_set.splits.is_empty() {
+        true => "install",
+        false => "install-multiple",
+    };
+    reporter.start(
+        Task::Install,
+        format!("Installing {}", apk_set.package),
+        None,
+    );
+    device_output(
+        runner,
+        &serial,
+        std::iter::once(Path::new(install_command)).chain(apk_set.apks().map(PathBuf::as_path)),
+    )
+    .context("Failed to install APK")?;
+    reporter.finish(Task::Install, format!("Installed {}", apk_set.package));
+
+    let obb_device_dir = device_obb_dir(&apk_set.package);
+    for obb in &obbs {
+        push(runner, &serial, &obb.path, &obb_device_dir, reporter)?;
+    }
+
+    for file in assets.iter().flat_map(|manifest| &manifest.files) {
+        let path = asset_dir.join(&file.path);
+        match &file.target {
+            AssetTarget::Apk => {
+                reporter.start(
+                    Task::Install,
+                    format!("Installing DLC {}", path.display()),
+                    None,
+                );
+                device_output(runner, &serial, [Path::new("install"), &path])
+                    .with_context(|| format!("Failed to install DLC {}", path.display()))?;
+                reporter.finish(Task::Install, format!("Installed DLC {}", path.display()));
+            }
+            AssetTarget::Push { device_dir } => push(runner, &serial, &path, device_dir, reporter)?,
+        }
+    }
+
+    Ok(InstallReport {
+        serial,
+        package: apk_set.package.clone(),
+        apks: apk_set.apks().cloned().collect(),
+        obbs: obbs.into_iter().map(|obb| obb.path).collect(),
+        assets: asset_paths,
+        abi_diagnosis,
+    })
+}
+
+/// Fails with a diagnosis if the connected device cannot run the APK's native libraries,
+/// otherwise returns the diagnosis
+pub fn check_device_abis(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    apk_set: &ApkSet,
+) -> crate::Result<String> {
+    // Split sets ship their native libraries in per-ABI config splits
+    let mut apk_abis = Vec::new();
+    for apk in apk_set.apks() {
+        apk_abis.extend(read_apk_info(apk)?.abis);
+    }
+    apk_abis.sort();
+    apk_abis.dedup();
+    let device = DeviceAbis::query(runner, serial)
+        .context("Failed to query device ABIs, is the emulator running?")?;
+
+    let compatibility = AbiCompatibility::check(&apk_abis, &device);
+    let diagnosis = compatibility.diagnosis(&device);
+    if !compatibility.is_compatible() {
+        return Err(Error::AbiIncompatible(diagnosis));
+    }
+    Ok(diagnosis)
+}
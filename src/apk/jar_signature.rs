@@ -0,0 +1,433 @@
+//! Verification of v1 (JAR) APK signatures.
+//!
+//! `META-INF/MANIFEST.MF` lists a digest of every entry, each `.SF` file lists digests
+//! of the manifest, and the PKCS#7 block next to the `.SF` file signs it.
+//! See <https://docs.oracle.com/en/java/javase/17/docs/specs/jar/jar.html#signed-jar-file>
+
+use std::{
+    collections::HashSet,
+    io::{Read, Seek},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use cms::{
+    cert::{CertificateChoices, x509::Certificate},
+    content_info::ContentInfo,
+    signed_data::{SignedData, SignerIdentifier, SignerInfo},
+};
+use der::{
+    Decode, Encode,
+    asn1::{ObjectIdentifier, OctetString},
+};
+use rsa::{Pkcs1v15Sign, RsaPublicKey, pkcs8::DecodePublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use zip::ZipArchive;
+
+use super::verify::{SignatureScheme, Signer};
+use crate::{
+    error::{Context, Error, bail},
+    keystore::fingerprint,
+};
+
+const MANIFEST_FILE: &str = "META-INF/MANIFEST.MF";
+
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.2");
+const ID_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const SHA1_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.5");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+
+/// Verifies the v1 signature of an APK and returns its signers.
+///
+/// `newer_schemes` are the v2/v3 signatures found in the APK, a `.SF` file that claims
+/// one of them is missing means the APK had it stripped to fall back to v1.
+pub(crate) fn verify_v1<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    newer_schemes: &[SignatureScheme],
+) -> crate::Result<Vec<Signer>> {
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+
+    let manifest = read_entry(archive, MANIFEST_FILE)?;
+    let sections = parse_sections(&manifest).context("Failed to parse MANIFEST.MF")?;
+    let (main_section, entry_sections) = sections.split_first().context("MANIFEST.MF is empty")?;
+
+    let mut signers = Vec::new();
+    for sf_name in names.iter().filter(|name| is_meta_inf_file(name, &[".SF"])) {
+        let base = &sf_name[..sf_name.len() - ".SF".len()];
+        let block_name = [".RSA", ".DSA", ".EC"]
+            .iter()
+            .map(|ext| format!("{base}{ext}"))
+            .find(|name| names.contains(name))
+            .with_context(|| format!("{sf_name} has no signature block"))?;
+
+        let sf = read_entry(archive, sf_name)?;
+        let signer = verify_signature_block(&read_entry(archive, &block_name)?, &sf)
+            .with_context(|| format!("Invalid signature block {block_name}"))?;
+        verify_signature_file(&sf, &manifest, main_section, entry_sections, newer_schemes)
+            .with_context(|| format!("{sf_name} does not match MANIFEST.MF"))?;
+        signers.push(signer);
+    }
+    if signers.is_empty() {
+        bail!("MANIFEST.MF is not signed, there is no .SF file");
+    }
+
+    // Every entry must be covered by the manifest, or it could be swapped freely
+    let mut listed = HashSet::new();
+    for section in entry_sections {
+        let name = section
+            .get("Name")
+            .context("MANIFEST.MF section has no name")?;
+        if !names.iter().any(|n| n == name) {
+            bail!("MANIFEST.MF lists {name}, which is not in the APK");
+        }
+        let content = read_entry(archive, name)?;
+        verify_digests(section, "-Digest", &content)
+            .with_context(|| format!("{name} was modified after signing"))?;
+        listed.insert(name);
+    }
+    if let Some(unlisted) = names
+        .iter()
+        .find(|name| !listed.contains(name.as_str()) && needs_digest(name))
+    {
+        bail!("{unlisted} is not listed in MANIFEST.MF, so it is not signed");
+    }
+
+    Ok(signers)
+}
+
+/// A manifest or `.SF` section, with the raw bytes its digest is computed over
+struct Section<'a> {
+    raw: &'a [u8],
+    attributes: Vec<(String, String)>,
+}
+
+impl Section<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Splits a manifest into its blank line separated sections, joining continuation lines
+fn parse_sections(data: &[u8]) -> crate::Result<Vec<Section<'_>>> {
+    let mut sections = Vec::new();
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i + 1);
+        let line = &data[pos..end];
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        pos = end;
+
+        if line.is_empty() {
+            // The separating blank line is part of the section's digest
+            if !attributes.is_empty() {
+                sections.push(Section {
+                    raw: &data[start..end],
+                    attributes: std::mem::take(&mut attributes),
+                });
+            }
+            start = end;
+            continue;
+        }
+
+        let line = std::str::from_utf8(line).context("Manifest is not valid UTF-8")?;
+        match line.strip_prefix(' ') {
+            Some(continued) => attributes
+                .last_mut()
+                .context("Manifest continuation line has no attribute")?
+                .1
+                .push_str(continued),
+            None => {
+                let (key, value) = line
+                    .split_once(": ")
+                    .with_context(|| format!("Invalid manifest line {line:?}"))?;
+                attributes.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+    if !attributes.is_empty() {
+        sections.push(Section {
+            raw: &data[start..],
+            attributes,
+        });
+    }
+    Ok(sections)
+}
+
+/// Checks the `.SF` file against the manifest: its digest of the whole manifest if that
+/// matches, otherwise its digests of the main attributes and of every manifest section
+fn verify_signature_file(
+    sf: &[u8],
+    manifest: &[u8],
+    main_section: &Section,
+    manifest_sections: &[Section],
+    newer_schemes: &[SignatureScheme],
+) -> crate::Result<()> {
+    let sections = parse_sections(sf)?;
+    let (main, sf_sections) = sections.split_first().context("The .SF file is empty")?;
+
+    if let Some(signed_with) = main.get("X-Android-APK-Signed") {
+        for id in signed_with.split(',').map(str::trim) {
+            let scheme = match id {
+                "2" => SignatureScheme::V2,
+                "3" => SignatureScheme::V3,
+                _ => continue,
+            };
+            if !newer_schemes.contains(&scheme) {
+                bail!("The APK was signed with {scheme} too, but that signature was stripped");
+            }
+        }
+    }
+
+    if verify_digests(main, "-Digest-Manifest", manifest).is_ok() {
+        return Ok(());
+    }
+    const MAIN_ATTRIBUTES: &str = "-Digest-Manifest-Main-Attributes";
+    if main
+        .attributes
+        .iter()
+        .any(|(key, _)| key.ends_with(MAIN_ATTRIBUTES))
+    {
+        verify_digests(main, MAIN_ATTRIBUTES, main_section.raw)
+            .context("Main attributes of MANIFEST.MF were modified")?;
+    }
+    for sf_section in sf_sections {
+        let name = sf_section.get("Name").context(".SF section has no name")?;
+        let section = manifest_sections
+            .iter()
+            .find(|s| s.get("Name") == Some(name))
+            .with_context(|| format!("{name} is not in MANIFEST.MF"))?;
+        verify_digests(sf_section, "-Digest", section.raw)
+            .with_context(|| format!("Digest of {name} does not match"))?;
+    }
+    // Every entry digest must be signed, not only the ones the .SF file lists
+    if manifest_sections.len() != sf_sections.len() {
+        bail!("MANIFEST.MF has sections that are not signed");
+    }
+    Ok(())
+}
+
+/// Checks every `<algorithm><suffix>` attribute of a section against `data`
+fn verify_digests(section: &Section, suffix: &str, data: &[u8]) -> crate::Result<()> {
+    let mut found = false;
+    for (key, value) in &section.attributes {
+        let Some(algorithm) = key
+            .strip_suffix(suffix)
+            .and_then(JarDigest::from_attribute_prefix)
+        else {
+            continue;
+        };
+        let expected = BASE64
+            .decode(value)
+            .with_context(|| format!("{key} is not valid base64"))?;
+        if algorithm.digest(data) != expected {
+            bail!("{key} does not match");
+        }
+        found = true;
+    }
+    if !found {
+        bail!("No supported {suffix} attribute, only SHA-1 and SHA-2 digests can be verified");
+    }
+    Ok(())
+}
+
+/// Verifies the PKCS#7 signature of a `.SF` file and returns the signer
+fn verify_signature_block(block: &[u8], sf: &[u8]) -> crate::Result<Signer> {
+    let content_info = ContentInfo::from_der(block).map_err(Error::external)?;
+    if content_info.content_type != ID_SIGNED_DATA {
+        bail!("Not a PKCS#7 SignedData block");
+    }
+    let signed_data: SignedData = content_info.content.decode_as().map_err(Error::external)?;
+    let signer_info = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .context("Signature block has no signers")?;
+
+    let digest = JarDigest::from_oid(signer_info.digest_alg.oid).with_context(|| {
+        format!(
+            "Unsupported digest algorithm {}",
+            signer_info.digest_alg.oid
+        )
+    })?;
+    if ![
+        RSA_ENCRYPTION,
+        SHA1_WITH_RSA,
+        SHA256_WITH_RSA,
+        SHA384_WITH_RSA,
+        SHA512_WITH_RSA,
+    ]
+    .contains(&signer_info.signature_algorithm.oid)
+    {
+        bail!(
+            "Unsupported signature algorithm {}, only RSA signatures can be verified",
+            signer_info.signature_algorithm.oid
+        );
+    }
+
+    // With signed attributes, the signature covers them and they hold the .SF digest
+    let signed = match &signer_info.signed_attrs {
+        Some(attributes) => {
+            let message_digest = attributes
+                .iter()
+                .find(|a| a.oid == ID_MESSAGE_DIGEST)
+                .and_then(|a| a.values.iter().next())
+                .context("Signed attributes have no message digest")?
+                .decode_as::<OctetString>()
+                .map_err(Error::external)?;
+            if message_digest.as_bytes() != digest.digest(sf) {
+                bail!("The .SF file was modified after signing");
+            }
+            attributes.to_der().map_err(Error::external)?
+        }
+        None => sf.to_vec(),
+    };
+
+    let cert = signer_certificate(&signed_data, signer_info)?;
+    let public_key_der = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(Error::external)?;
+    let public_key = RsaPublicKey::from_public_key_der(&public_key_der)
+        .context("Failed to parse signer public key, only RSA keys are supported")?;
+    public_key
+        .verify(
+            digest.pkcs1v15(),
+            &digest.digest(&signed),
+            signer_info.signature.as_bytes(),
+        )
+        .context("Signature does not match the .SF file")?;
+
+    Ok(Signer {
+        cert_sha256: fingerprint(&Sha256::digest(cert.to_der().map_err(Error::external)?)),
+        algorithm: digest.signature_name(),
+    })
+}
+
+fn signer_certificate<'a>(
+    signed_data: &'a SignedData,
+    signer_info: &SignerInfo,
+) -> crate::Result<&'a Certificate> {
+    let SignerIdentifier::IssuerAndSerialNumber(id) = &signer_info.sid else {
+        bail!("Signers identified by subject key are not supported");
+    };
+    signed_data
+        .certificates
+        .iter()
+        .flat_map(|certs| certs.0.iter())
+        .find_map(|cert| match cert {
+            CertificateChoices::Certificate(cert)
+                if cert.tbs_certificate.issuer == id.issuer
+                    && cert.tbs_certificate.serial_number == id.serial_number =>
+            {
+                Some(cert)
+            }
+            _ => None,
+        })
+        .context("Signature block does not contain the signer's certificate")
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> crate::Result<Vec<u8>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(Error::external)
+        .with_context(|| format!("Failed to find {name}"))?;
+    let mut data = Vec::new();
+    entry
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {name}"))?;
+    Ok(data)
+}
+
+/// A file directly in META-INF with one of the extensions
+fn is_meta_inf_file(name: &str, extensions: &[&str]) -> bool {
+    name.strip_prefix("META-INF/").is_some_and(|file_name| {
+        !file_name.contains('/') && extensions.iter().any(|ext| file_name.ends_with(ext))
+    })
+}
+
+/// Whether the manifest must have a digest of the entry, everything but
+/// directories and the signature files themselves
+fn needs_digest(name: &str) -> bool {
+    if name.ends_with('/') || name == MANIFEST_FILE {
+        return false;
+    }
+    let signature_file = is_meta_inf_file(name, &[".SF", ".RSA", ".DSA", ".EC"]);
+    !signature_file && !name.starts_with("META-INF/SIG-")
+}
+
+#[derive(Debug, Clone, Copy)]
+enum JarDigest {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl JarDigest {
+    /// From the start of a `SHA-256-Digest` style attribute name
+    fn from_attribute_prefix(prefix: &str) -> Option<Self> {
+        match prefix.to_ascii_uppercase().as_str() {
+            "SHA1" | "SHA-1" => Some(Self::Sha1),
+            "SHA-256" => Some(Self::Sha256),
+            "SHA-384" => Some(Self::Sha384),
+            "SHA-512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn from_oid(oid: ObjectIdentifier) -> Option<Self> {
+        [
+            (ID_SHA1, Self::Sha1),
+            (ID_SHA256, Self::Sha256),
+            (ID_SHA384, Self::Sha384),
+            (ID_SHA512, Self::Sha512),
+        ]
+        .into_iter()
+        .find_map(|(id, digest)| (id == oid).then_some(digest))
+    }
+
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => Sha1::digest(data).to_vec(),
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha384 => Sha384::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn pkcs1v15(self) -> Pkcs1v15Sign {
+        match self {
+            Self::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+            Self::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            Self::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            Self::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+        }
+    }
+
+    fn signature_name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "RSASSA-PKCS1-v1_5 with SHA-1",
+            Self::Sha256 => "RSASSA-PKCS1-v1_5 with SHA2-256",
+            Self::Sha384 => "RSASSA-PKCS1-v1_5 with SHA2-384",
+            Self::Sha512 => "RSASSA-PKCS1-v1_5 with SHA2-512",
+        }
+    }
+}
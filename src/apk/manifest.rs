@@ -0,0 +1,118 @@
+use std::io::Cursor;
+
+use mbf_axml::{AxmlReader, AxmlWriter, axml_to_xml, xml_to_axml};
+use serde::Serialize;
+use xml::reader::XmlEvent;
+
+use crate::error::{Context, Error};
+
+pub const MANIFEST_FILE: &str = "AndroidManifest.xml";
+
+const ANDROID_NAMESPACE: &str = "http://schemas.android.com/apk/res/android";
+
+/// Package the Quest runtime queries for, added by `apk patch`
+pub const HORIZON_PACKAGE: &str = "com.oculus.horizon";
+
+/// Decodes a binary AXML manifest to an XML string
+pub fn decode_manifest(manifest_bytes: Vec<u8>, indent: bool) -> crate::Result<String> {
+    let mut manifest_cursor = Cursor::new(manifest_bytes);
+    let mut axml_reader = AxmlReader::new(&mut manifest_cursor)
+        .map_err(Error::external)
+        .context("Failed to parse AndroidManifest.xml as AXML")?;
+    let mut xml_bytes = Vec::new();
+    {
+        let mut writer = xml::EmitterConfig::new()
+            .perform_indent(indent)
+            .create_writer(&mut xml_bytes);
+        axml_to_xml(&mut writer, &mut axml_reader).map_err(Error::external)?;
+    }
+    String::from_utf8(xml_bytes).context("Decoded manifest is not valid UTF-8")
+}
+
+/// Encodes an XML string back to binary AXML
+pub fn encode_manifest(xml_str: &str) -> crate::Result<Vec<u8>> {
+    let mut axml_bytes = Vec::new();
+    {
+        let mut axml_writer = AxmlWriter::new(&mut axml_bytes);
+        let mut xml_reader = xml::EventReader::from_str(xml_str);
+        xml_to_axml(&mut axml_writer, &mut xml_reader).map_err(Error::external)?;
+        axml_writer.finish().map_err(Error::external)?;
+    }
+    Ok(axml_bytes)
+}
+
+/// The interesting parts of a decoded AndroidManifest.xml
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ManifestInfo {
+    pub package: String,
+    /// Name of the split if this is a split APK
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split: Option<String>,
+    pub version_code: Option<u64>,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub target_sdk: Option<u32>,
+    pub permissions: Vec<String>,
+    /// Whether the manifest queries `com.oculus.horizon`, which `apk patch` adds
+    pub queries_horizon: bool,
+}
+
+impl ManifestInfo {
+    /// Reads the manifest info from a decoded XML manifest
+    pub fn parse(xml_str: &str) -> crate::Result<Self> {
+        let mut info = ManifestInfo::default();
+        let mut path: Vec<String> = Vec::new();
+
+        for event in xml::EventReader::from_str(xml_str) {
+            match event.context("Failed to parse decoded manifest")? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let attribute = |local_name: &str, android: bool| {
+                        attributes
+                            .iter()
+                            .find(|a| {
+                                a.name.local_name == local_name
+                                    && (!android
+                                        || a.name.namespace.as_deref() == Some(ANDROID_NAMESPACE)
+                                        || a.name.prefix.as_deref() == Some("android"))
+                            })
+                            .map(|a| a.value.clone())
+                    };
+
+                    match (path.last().map(String::as_str), name.local_name.as_str()) {
+                        (None, "manifest") => {
+                            info.package = attribute("package", false).unwrap_or_default();
+                            info.split = attribute("split", false);
+                            info.version_code =
+                                attribute("versionCode", true).and_then(|v| v.parse().ok());
+                            info.version_name = attribute("versionName", true);
+                        }
+                        (Some("manifest"), "uses-sdk") => {
+                            info.min_sdk =
+                                attribute("minSdkVersion", true).and_then(|v| v.parse().ok());
+                            info.target_sdk =
+                                attribute("targetSdkVersion", true).and_then(|v| v.parse().ok());
+                        }
+                        (Some("manifest"), "uses-permission") => {
+                            info.permissions.extend(attribute("name", true));
+                        }
+                        (Some("queries"), "package") => {
+                            info.queries_horizon |=
+                                attribute("name", true).as_deref() == Some(HORIZON_PACKAGE);
+                        }
+                        _ => {}
+                    }
+
+                    path.push(name.local_name);
+                }
+                XmlEvent::EndElement { .. } => {
+                    path.pop();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+}
@@ -0,0 +1,15 @@
+pub mod assets;
+pub mod bundle;
+pub mod downloads;
+pub mod info;
+pub mod install;
+mod jar_signature;
+pub mod manifest;
+pub mod obb;
+pub mod patch;
+pub mod patch_state;
+pub mod store;
+pub mod verify;
+
+pub use install::{InstallOptions, InstallReport, install};
+pub use patch::{PatchOptions, PatchReport, patch};
@@ -0,0 +1,121 @@
+//! OBB expansion files, named `<main|patch>.<versionCode>.<package>.obb`.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    apk::manifest::ManifestInfo,
+    error::{Context, bail},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObbKind {
+    Main,
+    Patch,
+}
+
+impl std::fmt::Display for ObbKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObbKind::Main => write!(f, "main"),
+            ObbKind::Patch => write!(f, "patch"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObbFile {
+    pub path: PathBuf,
+    pub kind: ObbKind,
+    /// Version code of the APK the OBB was published with
+    pub version_code: u64,
+    pub package: String,
+}
+
+impl ObbFile {
+    /// Parses the kind, version code and package from the OBB file name
+    pub fn parse(path: &Path) -> crate::Result<Self> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Invalid OBB path {}", path.display()))?;
+
+        let parse = || {
+            let rest = file_name.strip_suffix(".obb")?;
+            let (kind, rest) = rest.split_once('.')?;
+            let (version_code, package) = rest.split_once('.')?;
+            let kind = match kind {
+                "main" => ObbKind::Main,
+                "patch" => ObbKind::Patch,
+                _ => return None,
+            };
+            Some((kind, version_code.parse().ok()?, package))
+        };
+        let (kind, version_code, package) = parse().with_context(|| {
+            format!("OBB {file_name} is not named <main|patch>.<versionCode>.<package>.obb")
+        })?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            kind,
+            version_code,
+            package: package.to_string(),
+        })
+    }
+}
+
+/// Whether `path` is an OBB file named for the package, e.g. `main.1130.com.beatgames.beatsaber.obb`
+pub fn is_package_obb(path: &Path, package: &str) -> bool {
+    path.is_file() && ObbFile::parse(path).is_ok_and(|obb| obb.package == package)
+}
+
+/// Directory on the device the OBBs of a package are read from
+pub fn device_obb_dir(package: &str) -> String {
+    format!("/sdcard/Android/obb/{package}")
+}
+
+/// Checks that the OBBs belong to the APK and that there is at most one main and one patch OBB
+pub fn validate_obbs(obbs: &[PathBuf], manifest: &ManifestInfo) -> crate::Result<Vec<ObbFile>> {
+    let mut validated: Vec<ObbFile> = Vec::new();
+
+    for path in obbs {
+        let obb = ObbFile::parse(path)?;
+        if obb.package != manifest.package {
+            bail!(
+                "OBB {} belongs to {} but the APK is {}",
+                path.display(),
+                obb.package,
+                manifest.package
+            );
+        }
+
+        // An OBB can be reused by later APK versions, but not by earlier ones
+        if let Some(apk_version_code) = manifest.version_code
+            && obb.version_code > apk_version_code
+        {
+            bail!(
+                "OBB {} is for version code {} but the APK is version code {apk_version_code}",
+                path.display(),
+                obb.version_code
+            );
+        }
+
+        if let Some(existing) = validated.iter().find(|o| o.kind == obb.kind) {
+            bail!(
+                "Found multiple {} OBBs: {} and {}",
+                obb.kind,
+                existing.path.display(),
+                path.display()
+            );
+        }
+        validated.push(obb);
+    }
+
+    Ok(validated)
+}
+
+/// Reads the manifest the OBBs are validated against
+pub fn validate_obbs_for_apk(obbs: &[PathBuf], apk: &Path) -> crate::Result<Vec<ObbFile>> {
+    let manifest = crate::apk::info::read_manifest_info(apk)
+        .with_context(|| format!("Failed to read manifest of {}", apk.display()))?;
+    validate_obbs(obbs, &manifest)
+}
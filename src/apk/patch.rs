@@ -0,0 +1,245 @@
+//! Patching APKs to run in the emulator.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use mbf_zip::FileCompression;
+use serde::Serialize;
+
+use crate::{
+    apk::{
+        bundle::{ApkSet, extract_bundle, is_bundle},
+        info::read_manifest_info,
+        manifest::{MANIFEST_FILE, decode_manifest, encode_manifest},
+        patch_state::{HORIZON_QUERY_PATCH, PATCH_STATE_FILE, PatchState},
+        verify::verify_apk,
+    },
+    error::{Context, Error, bail},
+    keystore::SigningKey,
+    progress::{ProgressReporter, Task},
+};
+
+#[derive(Debug, Clone)]
+pub struct PatchOptions {
+    /// Key the patched APKs are signed with
+    pub signing_key: SigningKey,
+    /// Folder to unpack bundles into, defaults to the bundle path without its extension
+    pub output: Option<PathBuf>,
+}
+
+impl Default for PatchOptions {
+    /// Signs with the embedded debug key
+    fn default() -> Self {
+        Self {
+            signing_key: SigningKey::debug(),
+            output: None,
+        }
+    }
+}
+
+/// What [`patch`] did
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchReport {
+    pub apk_set: ApkSet,
+    /// Folder the bundle was unpacked to, if a bundle was patched
+    pub unpacked_to: Option<PathBuf>,
+    /// The base APK followed by its splits
+    pub apks: Vec<ApkPatchReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApkPatchReport {
+    pub path: PathBuf,
+    /// Patches applied in this run
+    pub applied: Vec<String>,
+    /// Whether the APK was written and re-signed.
+    /// `false` if it was already patched and signed with the same key
+    pub signed: bool,
+    /// SHA-256 fingerprint of the signing certificate
+    pub signer_sha256: String,
+}
+
+/// Patches an APK, a split APK folder or a bundle (.apks, .xapk, .apkm) in place.
+/// Bundles are unpacked to [`PatchOptions::output`] first.
+pub fn patch(
+    path: &Path,
+    options: &PatchOptions,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<PatchReport> {
+    let (apk_set, unpacked_to) = if is_bundle(path) {
+        let output = options
+            .output
+            .clone()
+            .unwrap_or_else(|| path.with_extension(""));
+        if output.exists() {
+            bail!("Output folder {} already exists", output.display());
+        }
+        reporter.start(Task::Extract, format!("Unpacking {}", path.display()), None);
+        let apk_set = extract_bundle(path, &output)?;
+        reporter.finish(Task::Extract, format!("Unpacked to {}", output.display()));
+        (apk_set, Some(output))
+    } else if path.is_dir() {
+        (ApkSet::from_dir(path, None)?, None)
+    } else {
+        let package = read_manifest_info(path)?.package;
+        (
+            ApkSet::single(package, path.to_path_buf(), Vec::new()),
+            None,
+        )
+    };
+
+    let apks = patch_set(&apk_set, &options.signing_key, reporter)?;
+    Ok(PatchReport {
+        apk_set,
+        unpacked_to,
+        apks,
+    })
+}
+
+/// Patches the base APK and re-signs its splits,
+/// since Android requires every APK in a set to be signed with the same certificate
+pub fn patch_set(
+    apk_set: &ApkSet,
+    signing_key: &SigningKey,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<Vec<ApkPatchReport>> {
+    reporter.start(
+        Task::Patch,
+        format!("Patching {}", apk_set.base.display()),
+        None,
+    );
+    let base = patch_apk(&apk_set.base, signing_key)?;
+    let name = base.path.display();
+    let outcome = match (base.signed, base.applied.is_empty()) {
+        (false, _) => format!("{name} is already patched and signed with this key"),
+        (true, true) => format!("Re-signed {name}"),
+        (true, false) => format!("Patched {name}: {}", base.applied.join(", ")),
+    };
+    reporter.finish(Task::Patch, outcome);
+
+    let mut reports = vec![base];
+    for split in &apk_set.splits {
+        reporter.start(Task::Patch, format!("Signing {}", split.display()), None);
+        let apk = open_zip(split)?;
+        sign_and_verify(apk, split, signing_key)?;
+        reporter.finish(Task::Patch, format!("Re-signed {}", split.display()));
+        reports.push(ApkPatchReport {
+            path: split.clone(),
+            applied: Vec::new(),
+            signed: true,
+            signer_sha256: signing_key.sha256_fingerprint(),
+        });
+    }
+    Ok(reports)
+}
+
+/// Applies the patches missing from the APK's patch state and signs it
+pub fn patch_apk(path: &Path, signing_key: &SigningKey) -> crate::Result<ApkPatchReport> {
+    let mut apk = open_zip(path)?;
+
+    let mut state = match apk.contains_file(PATCH_STATE_FILE) {
+        true => {
+            let json = apk.read_file(PATCH_STATE_FILE).map_err(Error::external)?;
+            PatchState::from_json(&json)?
+        }
+        false => PatchState::new(path)?,
+    };
+
+    let mut applied = Vec::new();
+    if !state.is_applied(HORIZON_QUERY_PATCH) {
+        let manifest_bytes = apk
+            .read_file(MANIFEST_FILE)
+            .map_err(Error::external)
+            .context("Failed to read AndroidManifest.xml from APK")?;
+        let axml_bytes = patch_manifest(manifest_bytes)?;
+        let mut axml_cursor = Cursor::new(axml_bytes);
+        apk.write_file(MANIFEST_FILE, &mut axml_cursor, FileCompression::Store)
+            .map_err(Error::external)
+            .context("Failed to write modified AndroidManifest.xml back to APK")?;
+        applied.push(HORIZON_QUERY_PATCH.to_string());
+    }
+
+    let signer_sha256 = signing_key.sha256_fingerprint();
+    if applied.is_empty() && state.signer_sha256() == Some(signer_sha256.as_str()) {
+        return Ok(ApkPatchReport {
+            path: path.to_path_buf(),
+            applied,
+            signed: false,
+            signer_sha256,
+        });
+    }
+
+    state.record(applied.clone(), signer_sha256.clone());
+    let mut state_cursor = Cursor::new(state.to_json()?);
+    apk.write_file(
+        PATCH_STATE_FILE,
+        &mut state_cursor,
+        FileCompression::Deflate,
+    )
+    .map_err(Error::external)
+    .context("Failed to write patch state to APK")?;
+
+    sign_and_verify(apk, path, signing_key)?;
+    Ok(ApkPatchReport {
+        path: path.to_path_buf(),
+        applied,
+        signed: true,
+        signer_sha256,
+    })
+}
+
+fn open_zip(path: &Path) -> crate::Result<mbf_zip::ZipFile<File>> {
+    let apk_file = OpenOptions::new()
+        .write(true)
+        .read(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    mbf_zip::ZipFile::open(apk_file)
+        .map_err(Error::external)
+        .context("Failed to read APK as zip file")
+}
+
+fn sign_and_verify(
+    mut apk: mbf_zip::ZipFile<File>,
+    path: &Path,
+    signing_key: &SigningKey,
+) -> crate::Result<()> {
+    let (cert, priv_key) = mbf_zip::signing::load_cert_and_priv_key(&signing_key.to_pem()?);
+    apk.save_and_sign_v2(&priv_key, &cert)
+        .map_err(Error::external)
+        .context("Failed to save modified APK")?;
+
+    // A bad signature only shows up later as an opaque `adb install` failure
+    let report = verify_apk(path).context("Failed to verify patched APK")?;
+    if !report.is_valid() {
+        return Err(Error::VerificationFailed {
+            path: path.to_path_buf(),
+            report: Box::new(report),
+        });
+    }
+    Ok(())
+}
+
+/// Adds a `<queries>` entry for `com.oculus.horizon` to a binary `AndroidManifest.xml`,
+/// so the app can see the Horizon service. Manifests that already query it are only
+/// re-encoded. Fails if the manifest cannot be decoded or has no `</manifest>` tag
+pub fn patch_manifest(manifest_bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+    let mut xml_str = decode_manifest(manifest_bytes, false)?;
+    let insert_str = r#"  <queries>    <package android:name="com.oculus.horizon"/>  </queries>"#;
+
+    // Check if the <queries> block with the package is already present
+    if !xml_str.contains(r#"<package android:name="com.oculus.horizon""#) {
+        match xml_str.rfind("</manifest>") {
+            Some(idx) => {
+                xml_str.insert_str(idx, insert_str);
+            }
+            None => {
+                bail!("No </manifest> tag found in manifest");
+            }
+        }
+    }
+    encode_manifest(&xml_str)
+}
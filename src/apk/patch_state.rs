@@ -0,0 +1,93 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Context;
+
+/// Path of the patch state inside patched APKs
+pub const PATCH_STATE_FILE: &str = "quest_emu/patch.json";
+
+/// Adds the `com.oculus.horizon` package query to the manifest
+pub const HORIZON_QUERY_PATCH: &str = "horizon_query";
+
+/// Record of what `apk patch` did to an APK, embedded in the APK itself
+/// so vanilla and patched files can be told apart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchState {
+    /// SHA-256 of the APK before it was first patched
+    pub original_sha256: String,
+    /// Every patch run, oldest first
+    pub history: Vec<PatchRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchRecord {
+    /// Version of quest_emu that ran the patch
+    pub tool_version: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Patches applied in this run, empty if the APK was only re-signed
+    pub patches: Vec<String>,
+    /// SHA-256 fingerprint of the certificate the APK was signed with
+    pub signer_sha256: String,
+}
+
+impl PatchState {
+    /// State for an APK that has not been patched yet
+    pub fn new(original: &Path) -> crate::Result<Self> {
+        Ok(Self {
+            original_sha256: file_sha256(original)?,
+            history: Vec::new(),
+        })
+    }
+
+    pub fn from_json(json: &[u8]) -> crate::Result<Self> {
+        serde_json::from_slice(json).context("Failed to parse patch state")
+    }
+
+    pub fn to_json(&self) -> crate::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Whether a patch was applied in any previous run
+    pub fn is_applied(&self, patch: &str) -> bool {
+        self.history
+            .iter()
+            .any(|record| record.patches.iter().any(|p| p == patch))
+    }
+
+    /// Fingerprint of the certificate used by the latest run
+    pub fn signer_sha256(&self) -> Option<&str> {
+        self.history
+            .last()
+            .map(|record| record.signer_sha256.as_str())
+    }
+
+    pub fn record(&mut self, patches: Vec<String>, signer_sha256: String) {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.history.push(PatchRecord {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp,
+            patches,
+            signer_sha256,
+        });
+    }
+}
+
+/// Hex encoded SHA-256 of a file
+pub fn file_sha256(path: &Path) -> crate::Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
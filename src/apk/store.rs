@@ -0,0 +1,175 @@
+//! Versions of an app published on the Oculus store.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use mbf_res_man::version_grabber::{AndroidBinary, SemiSemVer};
+use semver::{Version, VersionReq};
+use serde::Serialize;
+
+use crate::{date::civil_from_days, error::bail};
+
+/// A store version as shown by `apk versions`
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreVersion {
+    pub version: String,
+    /// Semver part of `version`, used for range matching
+    #[serde(skip)]
+    pub semver: Version,
+    pub version_code: Option<u32>,
+    /// Seconds since the Unix epoch
+    pub released: Option<u64>,
+    /// Size of the APK in bytes
+    pub size: Option<u64>,
+}
+
+impl StoreVersion {
+    /// Release date as `YYYY-MM-DD`
+    pub fn release_date(&self) -> Option<String> {
+        self.released.map(format_date)
+    }
+}
+
+/// Lists the versions returned by `get_live_versions`, newest first
+pub fn store_versions(versions: &HashMap<SemiSemVer, AndroidBinary>) -> Vec<StoreVersion> {
+    let mut sorted: Vec<_> = versions.iter().collect();
+    sorted.sort_by(|(a, a_binary), (b, b_binary)| {
+        b.semver
+            .cmp(&a.semver)
+            .then(b_binary.version_code.cmp(&a_binary.version_code))
+            .then(b.non_semver.cmp(&a.non_semver))
+    });
+
+    sorted
+        .into_iter()
+        .map(|(version, binary)| StoreVersion {
+            version: version.non_semver.clone(),
+            semver: version.semver.clone(),
+            version_code: binary.version_code,
+            released: binary.created_date,
+            size: binary.size,
+        })
+        .collect()
+}
+
+/// Which version `apk download` should fetch
+#[derive(Debug, Clone)]
+pub enum VersionQuery {
+    /// The newest version
+    Latest,
+    /// A version code, e.g. `1130`
+    Code(u32),
+    /// A version or semver range, e.g. `1.37.0`, `^1.37` or `>=1.35 <1.40`.
+    /// Bare versions only match themselves, `1.3` does not match `1.37.0`.
+    Req(String, VersionReq),
+    /// A store version that is not semver, e.g. `1.37.0_9064817954`
+    Exact(String),
+}
+
+impl VersionQuery {
+    pub fn parse(query: &str) -> Self {
+        let query = query.trim();
+        if query.eq_ignore_ascii_case("latest") {
+            return Self::Latest;
+        }
+        if let Ok(code) = query.parse() {
+            return Self::Code(code);
+        }
+
+        // Split on whitespace as well as commas, joining operators to their version
+        let mut comparators: Vec<String> = Vec::new();
+        let mut pending_operator = String::new();
+        for token in query.split([',', ' ']).filter(|t| !t.is_empty()) {
+            if token.chars().all(|c| "<>=~^".contains(c)) {
+                pending_operator.push_str(token);
+                continue;
+            }
+            let operator = std::mem::take(&mut pending_operator);
+            // semver treats a bare `1.3` as `^1.3`, which would match 1.37.0
+            let operator =
+                match operator.is_empty() && !token.starts_with(['<', '>', '=', '~', '^', '*']) {
+                    true => "=".to_string(),
+                    false => operator,
+                };
+            comparators.push(format!("{operator}{token}"));
+        }
+
+        match VersionReq::parse(&comparators.join(", ")) {
+            Ok(req) => Self::Req(query.to_string(), req),
+            Err(_) => Self::Exact(query.to_string()),
+        }
+    }
+
+    /// Picks the matching version from `versions`, which are sorted newest first.
+    /// An exact match on the store version string always wins.
+    pub fn resolve<'a>(&self, versions: &'a [StoreVersion]) -> crate::Result<&'a StoreVersion> {
+        let exact = |query: &str| versions.iter().find(|v| v.version == query);
+        let found = match self {
+            Self::Latest => versions.first(),
+            Self::Code(code) => exact(&code.to_string())
+                .or_else(|| versions.iter().find(|v| v.version_code == Some(*code))),
+            Self::Req(query, req) => {
+                exact(query).or_else(|| versions.iter().find(|v| req.matches(&v.semver)))
+            }
+            Self::Exact(query) => exact(query),
+        };
+        if let Some(found) = found {
+            return Ok(found);
+        }
+
+        if versions.is_empty() {
+            bail!("No versions are available");
+        }
+        bail!(
+            "No version matches {self}. Closest versions: {}",
+            self.closest(versions).iter().map(|v| &v.version).join(", ")
+        )
+    }
+
+    /// The versions nearest to what was asked for, to suggest when nothing matches
+    fn closest<'a>(&self, versions: &'a [StoreVersion]) -> Vec<&'a StoreVersion> {
+        const SUGGESTIONS: usize = 5;
+
+        let mut closest: Vec<_> = versions.iter().collect();
+        let target = match self {
+            Self::Latest => None,
+            Self::Code(code) => {
+                closest.sort_by_key(|v| v.version_code.map_or(u32::MAX, |c| c.abs_diff(*code)));
+                None
+            }
+            Self::Req(_, req) => req.comparators.first().cloned(),
+            // Compare by the leading version of e.g. `1.37.0_9064817954`
+            Self::Exact(query) => query
+                .split(|c: char| !c.is_ascii_digit() && c != '.')
+                .next()
+                .and_then(|prefix| semver::Comparator::parse(prefix.trim_end_matches('.')).ok()),
+        };
+        if let Some(target) = target {
+            closest.sort_by_key(|v| {
+                (
+                    v.semver.major.abs_diff(target.major),
+                    v.semver.minor.abs_diff(target.minor.unwrap_or(0)),
+                    v.semver.patch.abs_diff(target.patch.unwrap_or(0)),
+                )
+            });
+        }
+        closest.truncate(SUGGESTIONS);
+        closest
+    }
+}
+
+impl std::fmt::Display for VersionQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Latest => write!(f, "latest"),
+            Self::Code(code) => write!(f, "version code {code}"),
+            Self::Req(query, _) | Self::Exact(query) => write!(f, "{query}"),
+        }
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC `YYYY-MM-DD` date
+fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,649 @@
+//! Verification of APK signatures and zip alignment.
+//!
+//! Implements the parts of the APK Signature Scheme v2/v3 and of v1 (JAR) signing
+//! that `adb install` checks, so a broken signature shows up before attempting an install.
+//! See <https://source.android.com/docs/security/features/apksigning/v2>
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use rsa::{Pkcs1v15Sign, Pss, RsaPublicKey, pkcs8::DecodePublicKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256, Sha512};
+
+use super::jar_signature::verify_v1;
+use crate::{
+    error::{Context, Error, bail},
+    keystore::fingerprint,
+};
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const CD_ENTRY_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+
+const EOCD_MIN_SIZE: usize = 22;
+const CD_ENTRY_MIN_SIZE: usize = 46;
+const LOCAL_HEADER_SIZE: usize = 30;
+/// EOCD is followed by a comment of at most u16::MAX bytes
+const EOCD_SEARCH_SIZE: u64 = (EOCD_MIN_SIZE + u16::MAX as usize) as u64;
+
+const SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const V2_BLOCK_ID: u32 = 0x7109_871a;
+const V3_BLOCK_ID: u32 = 0xf053_68c0;
+
+/// Size of the chunks the content digest is computed over
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Stored entries must be 4 byte aligned so they can be mmapped
+const STORED_ALIGNMENT: u64 = 4;
+/// Stored native libraries must be page aligned to be loaded directly from the APK
+const NATIVE_LIB_ALIGNMENT: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureScheme {
+    V1,
+    V2,
+    V3,
+}
+
+impl std::fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureScheme::V1 => write!(f, "v1"),
+            SignatureScheme::V2 => write!(f, "v2"),
+            SignatureScheme::V3 => write!(f, "v3"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Signer {
+    /// SHA-256 fingerprint of the signer's certificate
+    pub cert_sha256: String,
+    /// Name of the signature algorithm that was verified
+    pub algorithm: &'static str,
+}
+
+/// Result of verifying a single signature scheme
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemeReport {
+    pub scheme: SignatureScheme,
+    /// The verified signers, or why verification failed
+    pub result: Result<Vec<Signer>, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MisalignedEntry {
+    pub name: String,
+    pub data_offset: u64,
+    pub required_alignment: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub schemes: Vec<SchemeReport>,
+    pub misaligned: Vec<MisalignedEntry>,
+}
+
+impl VerifyReport {
+    /// Whether the APK has a valid signature and correct alignment.
+    ///
+    /// Like Android 7.0+, a v1 signature only counts when there is no v2/v3 signature,
+    /// patching leaves the original JAR signature behind and it no longer matches.
+    pub fn is_valid(&self) -> bool {
+        let (v1, newer): (Vec<_>, Vec<_>) = self
+            .schemes
+            .iter()
+            .partition(|s| s.scheme == SignatureScheme::V1);
+        let checked = match newer.is_empty() {
+            true => v1,
+            false => newer,
+        };
+        !checked.is_empty()
+            && checked.iter().all(|s| s.result.is_ok())
+            && self.misaligned.is_empty()
+    }
+}
+
+/// The ID-value pairs of the APK Signing Block
+struct SigningBlock {
+    offset: u64,
+    pairs: Vec<(u32, Vec<u8>)>,
+}
+
+/// Offsets of the zip sections the APK signature covers
+struct ZipSections {
+    cd_offset: u64,
+    cd_size: u64,
+    eocd: Vec<u8>,
+}
+
+struct CentralDirectoryEntry {
+    name: String,
+    compression_method: u16,
+    local_header_offset: u64,
+}
+
+/// Verifies the signatures and alignment of an APK
+pub fn verify_apk(path: &Path) -> crate::Result<VerifyReport> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open APK {}", path.display()))?;
+
+    let sections = read_zip_sections(&mut file)?;
+    let entries = read_central_directory(&mut file, &sections)?;
+
+    let has_v1 = entries.iter().any(|e| is_jar_signature_file(&e.name));
+    let misaligned = find_misaligned_entries(&mut file, &entries)?;
+
+    let mut schemes = Vec::new();
+    if let Some(signing_block) = read_signing_block(&mut file, &sections)? {
+        for (id, scheme) in [
+            (V2_BLOCK_ID, SignatureScheme::V2),
+            (V3_BLOCK_ID, SignatureScheme::V3),
+        ] {
+            let Some((_, value)) = signing_block
+                .pairs
+                .iter()
+                .find(|(pair_id, _)| *pair_id == id)
+            else {
+                continue;
+            };
+            let result =
+                verify_scheme_block(&mut file, &sections, signing_block.offset, value, scheme)
+                    .map_err(|e| format!("{e:#}"));
+            schemes.push(SchemeReport { scheme, result });
+        }
+    }
+
+    if has_v1 {
+        let newer: Vec<_> = schemes.iter().map(|s| s.scheme).collect();
+        let result = zip::ZipArchive::new(&mut file)
+            .map_err(Error::external)
+            .and_then(|mut archive| verify_v1(&mut archive, &newer))
+            .map_err(|e| format!("{e:#}"));
+        schemes.insert(
+            0,
+            SchemeReport {
+                scheme: SignatureScheme::V1,
+                result,
+            },
+        );
+    }
+
+    Ok(VerifyReport {
+        schemes,
+        misaligned,
+    })
+}
+
+fn is_jar_signature_file(name: &str) -> bool {
+    let Some(file_name) = name.strip_prefix("META-INF/") else {
+        return false;
+    };
+    let upper = file_name.to_ascii_uppercase();
+    upper == "MANIFEST.MF"
+        || [".SF", ".RSA", ".DSA", ".EC"]
+            .iter()
+            .any(|ext| upper.ends_with(ext))
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> crate::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_zip_sections(file: &mut File) -> crate::Result<ZipSections> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let search_start = file_len.saturating_sub(EOCD_SEARCH_SIZE);
+    let tail = read_at(file, search_start, (file_len - search_start) as usize)?;
+    if tail.len() < EOCD_MIN_SIZE {
+        bail!("Not a zip file, it is too short for an end of central directory");
+    }
+
+    // Every candidate leaves room for a complete EOCD
+    let eocd_pos = (0..=tail.len() - EOCD_MIN_SIZE)
+        .rev()
+        .find(|&i| LittleEndian::read_u32(&tail[i..]) == EOCD_SIGNATURE)
+        .context("Not a zip file, end of central directory not found")?;
+    let eocd = tail[eocd_pos..].to_vec();
+
+    let cd_size = LittleEndian::read_u32(&eocd[12..]) as u64;
+    let cd_offset = LittleEndian::read_u32(&eocd[16..]) as u64;
+    if cd_offset == u32::MAX as u64 {
+        bail!("Zip64 APKs are not supported");
+    }
+    if cd_offset + cd_size > search_start + eocd_pos as u64 {
+        bail!("Central directory lies outside the file, the APK is truncated");
+    }
+
+    Ok(ZipSections {
+        cd_offset,
+        cd_size,
+        eocd,
+    })
+}
+
+fn read_central_directory(
+    file: &mut File,
+    sections: &ZipSections,
+) -> crate::Result<Vec<CentralDirectoryEntry>> {
+    let cd = read_at(file, sections.cd_offset, sections.cd_size as usize)
+        .context("Failed to read central directory")?;
+
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos + CD_ENTRY_MIN_SIZE <= cd.len() {
+        let header = &cd[pos..];
+        if LittleEndian::read_u32(header) != CD_ENTRY_SIGNATURE {
+            bail!("Invalid central directory entry at offset {pos}");
+        }
+        let compression_method = LittleEndian::read_u16(&header[10..]);
+        let name_len = LittleEndian::read_u16(&header[28..]) as usize;
+        let extra_len = LittleEndian::read_u16(&header[30..]) as usize;
+        let comment_len = LittleEndian::read_u16(&header[32..]) as usize;
+        let local_header_offset = LittleEndian::read_u32(&header[42..]) as u64;
+
+        let name = header
+            .get(CD_ENTRY_MIN_SIZE..CD_ENTRY_MIN_SIZE + name_len)
+            .context("Central directory entry name is truncated")?;
+        entries.push(CentralDirectoryEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            compression_method,
+            local_header_offset,
+        });
+
+        pos += CD_ENTRY_MIN_SIZE + name_len + extra_len + comment_len;
+    }
+
+    Ok(entries)
+}
+
+fn find_misaligned_entries(
+    file: &mut File,
+    entries: &[CentralDirectoryEntry],
+) -> crate::Result<Vec<MisalignedEntry>> {
+    let mut misaligned = Vec::new();
+    for entry in entries.iter().filter(|e| e.compression_method == 0) {
+        let header = read_at(file, entry.local_header_offset, LOCAL_HEADER_SIZE)?;
+        if LittleEndian::read_u32(&header) != LOCAL_HEADER_SIGNATURE {
+            bail!("Invalid local file header for {}", entry.name);
+        }
+        let name_len = LittleEndian::read_u16(&header[26..]) as u64;
+        let extra_len = LittleEndian::read_u16(&header[28..]) as u64;
+        let data_offset =
+            entry.local_header_offset + LOCAL_HEADER_SIZE as u64 + name_len + extra_len;
+
+        let required_alignment = match entry.name.ends_with(".so") {
+            true => NATIVE_LIB_ALIGNMENT,
+            false => STORED_ALIGNMENT,
+        };
+        if !data_offset.is_multiple_of(required_alignment) {
+            misaligned.push(MisalignedEntry {
+                name: entry.name.clone(),
+                data_offset,
+                required_alignment,
+            });
+        }
+    }
+    Ok(misaligned)
+}
+
+/// Reads the ID-value pairs of the APK Signing Block, which sits right before the central directory.
+/// Returns `None` if the APK has no signing block.
+fn read_signing_block(
+    file: &mut File,
+    sections: &ZipSections,
+) -> crate::Result<Option<SigningBlock>> {
+    // Footer: u64 size of block, 16 byte magic
+    let Some(footer_offset) = sections.cd_offset.checked_sub(24) else {
+        return Ok(None);
+    };
+    let footer = read_at(file, footer_offset, 24)?;
+    if &footer[8..] != SIGNING_BLOCK_MAGIC {
+        return Ok(None);
+    }
+
+    // The size covers everything but itself, so at least the footer
+    let block_size = LittleEndian::read_u64(&footer);
+    if block_size < 24 {
+        bail!("APK Signing Block size is invalid");
+    }
+    let block_offset = block_size
+        .checked_add(8)
+        .and_then(|total| sections.cd_offset.checked_sub(total))
+        .context("APK Signing Block size is invalid")?;
+    let block = read_at(file, block_offset, (block_size + 8) as usize)?;
+    if LittleEndian::read_u64(&block) != block_size {
+        bail!("APK Signing Block header and footer sizes do not match");
+    }
+
+    let mut pairs = Vec::new();
+    let mut remaining = &block[8..block.len() - 24];
+    while !remaining.is_empty() {
+        let len = take_u64(&mut remaining)? as usize;
+        if len < 4 || len > remaining.len() {
+            bail!("APK Signing Block entry is truncated");
+        }
+        let (pair, rest) = remaining.split_at(len);
+        pairs.push((LittleEndian::read_u32(pair), pair[4..].to_vec()));
+        remaining = rest;
+    }
+
+    Ok(Some(SigningBlock {
+        offset: block_offset,
+        pairs,
+    }))
+}
+
+fn verify_scheme_block(
+    file: &mut File,
+    sections: &ZipSections,
+    block_offset: u64,
+    block: &[u8],
+    scheme: SignatureScheme,
+) -> crate::Result<Vec<Signer>> {
+    let mut block = block;
+    let mut signers_data = take_prefixed(&mut block)?;
+
+    let mut signers = Vec::new();
+    while !signers_data.is_empty() {
+        let mut signer = take_prefixed(&mut signers_data)?;
+        signers.push(verify_signer(
+            file,
+            sections,
+            block_offset,
+            &mut signer,
+            scheme,
+        )?);
+    }
+
+    if signers.is_empty() {
+        bail!("No signers found");
+    }
+    Ok(signers)
+}
+
+fn verify_signer(
+    file: &mut File,
+    sections: &ZipSections,
+    block_offset: u64,
+    signer: &mut &[u8],
+    scheme: SignatureScheme,
+) -> crate::Result<Signer> {
+    let signed_data = take_prefixed(signer)?;
+    if scheme == SignatureScheme::V3 {
+        // minSdkVersion and maxSdkVersion
+        take_u32(signer)?;
+        take_u32(signer)?;
+    }
+    let mut signatures = take_prefixed(signer)?;
+    let public_key_der = take_prefixed(signer)?;
+
+    // Pick the strongest signature we know how to verify
+    let mut best: Option<(SignatureAlgorithm, &[u8])> = None;
+    while !signatures.is_empty() {
+        let mut signature = take_prefixed(&mut signatures)?;
+        let algorithm_id = take_u32(&mut signature)?;
+        let signature = take_prefixed(&mut signature)?;
+        if let Some(algorithm) = SignatureAlgorithm::from_id(algorithm_id)
+            && best.is_none_or(|(b, _)| algorithm.strength() > b.strength())
+        {
+            best = Some((algorithm, signature));
+        }
+    }
+    let (algorithm, signature) = best.context("No supported signature algorithm")?;
+    algorithm.verify(public_key_der, signed_data, signature)?;
+
+    // The signature is valid, so the signed data can be trusted from here on
+    let mut signed_data = signed_data;
+    let mut digests = take_prefixed(&mut signed_data)?;
+    let mut certificates = take_prefixed(&mut signed_data)?;
+
+    let mut expected_digest = None;
+    while !digests.is_empty() {
+        let mut digest = take_prefixed(&mut digests)?;
+        if take_u32(&mut digest)? == algorithm.id() {
+            expected_digest = Some(take_prefixed(&mut digest)?);
+        }
+    }
+    let expected_digest =
+        expected_digest.context("Signed data has no digest for the signature algorithm")?;
+
+    let actual_digest = content_digest(file, sections, block_offset, algorithm.uses_sha512())?;
+    if actual_digest != expected_digest {
+        bail!("APK contents do not match the signed digest, the APK was modified after signing");
+    }
+
+    let cert_der = take_prefixed(&mut certificates).context("Signer has no certificate")?;
+    // The SubjectPublicKeyInfo is embedded verbatim in the certificate
+    if !cert_der
+        .windows(public_key_der.len())
+        .any(|window| window == public_key_der)
+    {
+        bail!("Signer public key does not match its certificate");
+    }
+
+    Ok(Signer {
+        cert_sha256: fingerprint(&Sha256::digest(cert_der)),
+        algorithm: algorithm.name(),
+    })
+}
+
+/// Computes the chunked content digest over everything except the APK Signing Block
+fn content_digest(
+    file: &mut File,
+    sections: &ZipSections,
+    block_offset: u64,
+    sha512: bool,
+) -> crate::Result<Vec<u8>> {
+    // The EOCD is digested as if the central directory started where the signing block does
+    let mut eocd = sections.eocd.clone();
+    LittleEndian::write_u32(&mut eocd[16..], block_offset as u32);
+
+    let mut chunk_digests = Vec::new();
+    let mut chunk_count: u32 = 0;
+    let mut digest_chunk = |chunk: &[u8]| {
+        let mut prefix = [0xa5, 0, 0, 0, 0];
+        LittleEndian::write_u32(&mut prefix[1..], chunk.len() as u32);
+        let digest = match sha512 {
+            true => Sha512::new()
+                .chain_update(prefix)
+                .chain_update(chunk)
+                .finalize()
+                .to_vec(),
+            false => Sha256::new()
+                .chain_update(prefix)
+                .chain_update(chunk)
+                .finalize()
+                .to_vec(),
+        };
+        chunk_digests.extend(digest);
+        chunk_count += 1;
+    };
+
+    for (start, end) in [
+        (0, block_offset),
+        (sections.cd_offset, sections.cd_offset + sections.cd_size),
+    ] {
+        let mut offset = start;
+        while offset < end {
+            let len = CHUNK_SIZE.min(end - offset);
+            digest_chunk(&read_at(file, offset, len as usize)?);
+            offset += len;
+        }
+    }
+    for chunk in eocd.chunks(CHUNK_SIZE as usize) {
+        digest_chunk(chunk);
+    }
+
+    let mut prefix = [0x5a, 0, 0, 0, 0];
+    LittleEndian::write_u32(&mut prefix[1..], chunk_count);
+    Ok(match sha512 {
+        true => Sha512::new()
+            .chain_update(prefix)
+            .chain_update(&chunk_digests)
+            .finalize()
+            .to_vec(),
+        false => Sha256::new()
+            .chain_update(prefix)
+            .chain_update(&chunk_digests)
+            .finalize()
+            .to_vec(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignatureAlgorithm {
+    RsaPssSha256,
+    RsaPssSha512,
+    RsaPkcs1Sha256,
+    RsaPkcs1Sha512,
+    EcdsaSha256,
+    EcdsaSha512,
+    DsaSha256,
+}
+
+impl SignatureAlgorithm {
+    fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0x0101 => Some(Self::RsaPssSha256),
+            0x0102 => Some(Self::RsaPssSha512),
+            0x0103 => Some(Self::RsaPkcs1Sha256),
+            0x0104 => Some(Self::RsaPkcs1Sha512),
+            0x0201 => Some(Self::EcdsaSha256),
+            0x0202 => Some(Self::EcdsaSha512),
+            0x0301 => Some(Self::DsaSha256),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            Self::RsaPssSha256 => 0x0101,
+            Self::RsaPssSha512 => 0x0102,
+            Self::RsaPkcs1Sha256 => 0x0103,
+            Self::RsaPkcs1Sha512 => 0x0104,
+            Self::EcdsaSha256 => 0x0201,
+            Self::EcdsaSha512 => 0x0202,
+            Self::DsaSha256 => 0x0301,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::RsaPssSha256 => "RSASSA-PSS with SHA2-256",
+            Self::RsaPssSha512 => "RSASSA-PSS with SHA2-512",
+            Self::RsaPkcs1Sha256 => "RSASSA-PKCS1-v1_5 with SHA2-256",
+            Self::RsaPkcs1Sha512 => "RSASSA-PKCS1-v1_5 with SHA2-512",
+            Self::EcdsaSha256 => "ECDSA with SHA2-256",
+            Self::EcdsaSha512 => "ECDSA with SHA2-512",
+            Self::DsaSha256 => "DSA with SHA2-256",
+        }
+    }
+
+    fn uses_sha512(self) -> bool {
+        matches!(
+            self,
+            Self::RsaPssSha512 | Self::RsaPkcs1Sha512 | Self::EcdsaSha512
+        )
+    }
+
+    /// A signer signs with one key, so only algorithms of the same key type are compared
+    fn strength(self) -> u8 {
+        match self {
+            Self::RsaPkcs1Sha256 | Self::EcdsaSha256 | Self::DsaSha256 => 0,
+            Self::RsaPssSha256 => 1,
+            Self::RsaPkcs1Sha512 | Self::EcdsaSha512 => 2,
+            Self::RsaPssSha512 => 3,
+        }
+    }
+
+    /// Verifies `signature` over `data` with a DER encoded SubjectPublicKeyInfo
+    fn verify(self, public_key_der: &[u8], data: &[u8], signature: &[u8]) -> crate::Result<()> {
+        let digest = match self.uses_sha512() {
+            true => Sha512::digest(data).to_vec(),
+            false => Sha256::digest(data).to_vec(),
+        };
+        let verified = match self {
+            Self::RsaPssSha256 => rsa_key(public_key_der)?
+                .verify(Pss::new::<Sha256>(), &digest, signature)
+                .is_ok(),
+            Self::RsaPssSha512 => rsa_key(public_key_der)?
+                .verify(Pss::new::<Sha512>(), &digest, signature)
+                .is_ok(),
+            Self::RsaPkcs1Sha256 => rsa_key(public_key_der)?
+                .verify(Pkcs1v15Sign::new::<Sha256>(), &digest, signature)
+                .is_ok(),
+            Self::RsaPkcs1Sha512 => rsa_key(public_key_der)?
+                .verify(Pkcs1v15Sign::new::<Sha512>(), &digest, signature)
+                .is_ok(),
+            // Android signs with EC keys on any curve, apksigner creates P-256 and P-384 ones
+            Self::EcdsaSha256 | Self::EcdsaSha512 => {
+                use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+                if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(public_key_der) {
+                    p256::ecdsa::Signature::from_der(signature)
+                        .is_ok_and(|signature| key.verify_prehash(&digest, &signature).is_ok())
+                } else {
+                    let key = p384::ecdsa::VerifyingKey::from_public_key_der(public_key_der)
+                        .context("Failed to parse signer EC public key, only P-256 and P-384 keys are supported")?;
+                    p384::ecdsa::Signature::from_der(signature)
+                        .is_ok_and(|signature| key.verify_prehash(&digest, &signature).is_ok())
+                }
+            }
+            Self::DsaSha256 => {
+                use dsa::signature::hazmat::PrehashVerifier;
+
+                let key = dsa::VerifyingKey::from_public_key_der(public_key_der)
+                    .context("Failed to parse signer DSA public key")?;
+                dsa::Signature::try_from(signature)
+                    .is_ok_and(|signature| key.verify_prehash(&digest, &signature).is_ok())
+            }
+        };
+        if !verified {
+            bail!("Signature does not match signed data");
+        }
+        Ok(())
+    }
+}
+
+fn rsa_key(public_key_der: &[u8]) -> crate::Result<RsaPublicKey> {
+    RsaPublicKey::from_public_key_der(public_key_der)
+        .context("Failed to parse signer RSA public key")
+}
+
+fn take_u32(data: &mut &[u8]) -> crate::Result<u32> {
+    if data.len() < 4 {
+        bail!("Unexpected end of signature data");
+    }
+    let value = LittleEndian::read_u32(data);
+    *data = &data[4..];
+    Ok(value)
+}
+
+fn take_u64(data: &mut &[u8]) -> crate::Result<u64> {
+    if data.len() < 8 {
+        bail!("Unexpected end of signature data");
+    }
+    let value = LittleEndian::read_u64(data);
+    *data = &data[8..];
+    Ok(value)
+}
+
+/// Takes a u32 length-prefixed slice
+fn take_prefixed<'a>(data: &mut &'a [u8]) -> crate::Result<&'a [u8]> {
+    let len = take_u32(data)? as usize;
+    if data.len() < len {
+        bail!("Unexpected end of signature data");
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
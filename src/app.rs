@@ -0,0 +1,275 @@
+//! Driving installed apps with `am` and `pm` on the device.
+//!
+//! `am` and `pm` often exit successfully after a failure and only describe it in their
+//! output, so the output is parsed into typed errors.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+    adb::{device_command, shell_quote},
+    error::Error,
+    runner::{ToolOutput, ToolRunner},
+};
+
+/// Uninstalling or clearing the data of a large game can take a while
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Categories of the activity `launch` starts, in order. Quest apps only declare the VR one
+pub const LAUNCH_CATEGORIES: &[&str] = &[
+    "android.intent.category.LAUNCHER",
+    "com.oculus.intent.category.VR",
+];
+
+/// An installed package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub version_code: Option<u64>,
+    pub version_name: Option<String>,
+}
+
+/// Runs a command with `adb shell` on a device, failing only on errors of adb itself.
+/// The arguments are quoted, the device shell would expand e.g. the `$` of inner classes
+pub(crate) fn shell(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    args: &[&str],
+) -> crate::Result<ToolOutput> {
+    let command = device_command(serial, ["shell"])
+        .args(args.iter().map(|arg| shell_quote(arg).into_owned()))
+        .timeout(COMMAND_TIMEOUT);
+    check_adb(runner.output(&command)?)
+}
+
+/// Runs a script of several commands with `adb shell`, its parts must be quoted with
+/// [`shell_quote`]
+pub(crate) fn shell_script(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    script: &str,
+) -> crate::Result<ToolOutput> {
+    let command = device_command(serial, ["shell", script]).timeout(COMMAND_TIMEOUT);
+    check_adb(runner.output(&command)?)
+}
+
+/// Fails on errors of adb itself, e.g. an offline device, and leaves failures of the command
+/// on the device to the caller
+fn check_adb(output: ToolOutput) -> crate::Result<ToolOutput> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.starts_with("error:") || stderr.starts_with("adb:") {
+        return output.check("adb");
+    }
+    Ok(output)
+}
+
+/// stdout and stderr, since `am` and `pm` report errors on either
+fn output_text(output: &ToolOutput) -> String {
+    format!(
+        "{}{}",
+        output.stdout_lossy(),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// Fails on names that are not Java package names like `com.beatgames.beatsaber`,
+/// so a name cannot do anything else in a device shell or path
+pub fn validate_package(package: &str) -> crate::Result<()> {
+    let segment =
+        |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match package.contains('.') && package.split('.').all(segment) {
+        true => Ok(()),
+        false => Err(Error::InvalidPackageName(package.to_string())),
+    }
+}
+
+pub fn is_installed(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<bool> {
+    validate_package(package)?;
+    let output = shell(runner, serial, &["pm", "path", package])?;
+    Ok(output.status.success() && output.stdout_lossy().contains("package:"))
+}
+
+fn ensure_installed(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<()> {
+    match is_installed(runner, serial, package)? {
+        true => Ok(()),
+        false => Err(Error::PackageNotInstalled(package.to_string())),
+    }
+}
+
+/// The component of the activity that starts the app, e.g.
+/// `com.beatgames.beatsaber/com.unity3d.player.UnityPlayerActivity`, resolved by the
+/// package manager from the installed manifest
+pub fn launch_activity(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+) -> crate::Result<String> {
+    ensure_installed(runner, serial, package)?;
+    for category in LAUNCH_CATEGORIES {
+        let output = shell(
+            runner,
+            serial,
+            &[
+                "cmd",
+                "package",
+                "resolve-activity",
+                "--brief",
+                "-a",
+                "android.intent.action.MAIN",
+                "-c",
+                category,
+                package,
+            ],
+        )?;
+        // The component is on the last line, which says `No activity found` otherwise
+        let stdout = output.stdout_lossy();
+        let component = stdout
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .filter(|line| line.starts_with(&format!("{package}/")));
+        if let Some(component) = component {
+            return Ok(component.to_string());
+        }
+    }
+    Err(Error::NoLaunchActivity(package.to_string()))
+}
+
+/// Starts the app's launch activity and returns its component
+pub fn launch(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<String> {
+    let component = launch_activity(runner, serial, package)?;
+    let output = shell(runner, serial, &["am", "start", "-n", &component])?;
+    // `Error type 3` followed by `Error: Activity class {...} does not exist.`
+    let text = output_text(&output);
+    let errors: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("Error"))
+        .collect();
+    if let Some(reason) = errors
+        .iter()
+        .find_map(|line| line.strip_prefix("Error: "))
+        .or(errors.first().copied())
+    {
+        return Err(Error::AppCommandFailed {
+            command: "am start".to_string(),
+            reason: reason.to_string(),
+        });
+    }
+    output.check("am")?;
+    Ok(component)
+}
+
+/// Force-stops every process of the app
+pub fn stop(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<()> {
+    ensure_installed(runner, serial, package)?;
+    shell(runner, serial, &["am", "force-stop", package])?.check("am")?;
+    Ok(())
+}
+
+/// Deletes the app's data and cache, like Clear storage in the settings
+pub fn clear(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<()> {
+    ensure_installed(runner, serial, package)?;
+    let output = shell(runner, serial, &["pm", "clear", package])?;
+    pm_result(&output, "pm clear")
+}
+
+/// Uninstalls the app, keeping its data and cache with `keep_data`
+pub fn uninstall(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+    keep_data: bool,
+) -> crate::Result<()> {
+    ensure_installed(runner, serial, package)?;
+    let mut args = vec!["pm", "uninstall"];
+    if keep_data {
+        args.push("-k");
+    }
+    args.push(package);
+    let output = shell(runner, serial, &args)?;
+    pm_result(&output, "pm uninstall")
+}
+
+/// `Success`, or `Failure [DELETE_FAILED_INTERNAL_ERROR]`, or just `Failed` from `pm clear`
+fn pm_result(output: &ToolOutput, command: &str) -> crate::Result<()> {
+    let text = output_text(output);
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.clone().any(|line| line == "Success") {
+        return Ok(());
+    }
+    let reason = lines
+        .clone()
+        .find_map(|line| line.strip_prefix("Failure [")?.strip_suffix(']'))
+        .or(lines.next())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("exited with {}", output.status));
+    Err(Error::AppCommandFailed {
+        command: command.to_string(),
+        reason,
+    })
+}
+
+/// Third-party packages with their versions, sorted by name
+pub fn list(runner: &dyn ToolRunner, serial: &str) -> crate::Result<Vec<Package>> {
+    let output = shell(
+        runner,
+        serial,
+        &["pm", "list", "packages", "-3", "--show-versioncode"],
+    )?
+    .check("pm")?;
+
+    // package:com.beatgames.beatsaber versionCode:1130
+    let mut packages: Vec<Package> = output
+        .stdout_lossy()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.strip_prefix("package:")?;
+            let version_code = fields
+                .find_map(|field| field.strip_prefix("versionCode:"))
+                .and_then(|code| code.parse().ok());
+            Some(Package {
+                name: name.to_string(),
+                version_code,
+                version_name: None,
+            })
+        })
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for package in &mut packages {
+        package.version_name = dumpsys_versions(runner, serial, &package.name)?.1;
+    }
+    Ok(packages)
+}
+
+/// An installed package with its versions
+pub fn package(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<Package> {
+    ensure_installed(runner, serial, package)?;
+    let (version_code, version_name) = dumpsys_versions(runner, serial, package)?;
+    Ok(Package {
+        name: package.to_string(),
+        version_code,
+        version_name,
+    })
+}
+
+/// `versionCode=1130 minSdk=29 targetSdk=32` and `versionName=1.40.8_7379` from `dumpsys package`
+fn dumpsys_versions(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+) -> crate::Result<(Option<u64>, Option<String>)> {
+    let output = shell(runner, serial, &["dumpsys", "package", package])?.check("dumpsys")?;
+    let stdout = output.stdout_lossy();
+    let lines = || stdout.lines().map(str::trim);
+    let version_code = lines()
+        .find_map(|line| line.strip_prefix("versionCode="))
+        .and_then(|rest| rest.split_whitespace().next()?.parse().ok());
+    let version_name = lines()
+        .find_map(|line| line.strip_prefix("versionName="))
+        .map(str::to_string);
+    Ok((version_code, version_name))
+}
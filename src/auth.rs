@@ -0,0 +1,161 @@
+//! Storage and lookup of the Oculus auth token used to download APKs.
+
+use std::path::Path;
+
+use crate::{
+    constants::auth_token_path,
+    error::{Context, bail},
+};
+
+/// Environment variable checked before the stored token
+pub const TOKEN_ENV: &str = "OCULUS_TOKEN";
+
+/// Endpoint used to check a token, only returns the user ID
+const VALIDATE_URL: &str = "https://graph.oculus.com/me?fields=id";
+
+/// Where a token was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    Argument,
+    Env,
+    Stored,
+}
+
+impl std::fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenSource::Argument => write!(f, "--token"),
+            TokenSource::Env => write!(f, "{TOKEN_ENV} environment variable"),
+            TokenSource::Stored => write!(f, "{}", auth_token_path().display()),
+        }
+    }
+}
+
+/// Finds the token to use, preferring `--token`, then `OCULUS_TOKEN`, then the stored token
+pub fn find_token(argument: Option<String>) -> crate::Result<Option<(String, TokenSource)>> {
+    if let Some(token) = argument {
+        return Ok(Some((token, TokenSource::Argument)));
+    }
+    if let Ok(token) = std::env::var(TOKEN_ENV)
+        && !token.trim().is_empty()
+    {
+        return Ok(Some((token.trim().to_string(), TokenSource::Env)));
+    }
+    Ok(load_token()?.map(|token| (token, TokenSource::Stored)))
+}
+
+/// Like [`find_token`], but fails with instructions if there is no token
+pub fn require_token(argument: Option<String>) -> crate::Result<String> {
+    match find_token(argument)? {
+        Some((token, _)) => Ok(token),
+        None => bail!(
+            "No Oculus auth token found. Run `quest_emu auth login`, set {TOKEN_ENV} or pass --token"
+        ),
+    }
+}
+
+/// Reads the stored token, if any
+pub fn load_token() -> crate::Result<Option<String>> {
+    let path = auth_token_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let token = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let token = token.trim();
+    Ok((!token.is_empty()).then(|| token.to_string()))
+}
+
+/// Stores the token in a file only the current user can read.
+/// On Windows the file inherits the permissions of the user's config directory.
+pub fn save_token(token: &str) -> crate::Result<()> {
+    let path = auth_token_path();
+    let dir = path.parent().expect("token path has a parent");
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    restrict_permissions(dir, 0o700)?;
+    write_private_file(&path, token.as_bytes())
+}
+
+/// Writes a file only the current user can read, replacing it if it exists.
+/// On Windows the file inherits the permissions of its folder.
+pub(crate) fn write_private_file(path: &Path, data: &[u8]) -> crate::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    // The mode only applies to new files, an older file may be readable by others
+    restrict_permissions(path, 0o600)?;
+
+    std::io::Write::write_all(&mut file, data)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Removes the stored token, returns whether there was one
+pub fn delete_token() -> crate::Result<bool> {
+    let path = auth_token_path();
+    if !path.exists() {
+        return Ok(false);
+    }
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path, mode: u32) -> crate::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to restrict permissions of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path, _mode: u32) -> crate::Result<()> {
+    Ok(())
+}
+
+/// Shows the start and end of a token, enough to tell tokens apart
+pub fn mask_token(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    if chars.len() <= 12 {
+        return "*".repeat(chars.len());
+    }
+    let start: String = chars[..4].iter().collect();
+    let end: String = chars[chars.len() - 4..].iter().collect();
+    format!("{start}...{end}")
+}
+
+/// Checks the token with Oculus Graph.
+/// Returns `Ok(false)` if it was rejected, errors if Graph could not be reached.
+#[cfg(feature = "reqwest")]
+pub fn validate_token(token: &str) -> crate::Result<bool> {
+    let response = reqwest::blocking::Client::new()
+        .get(VALIDATE_URL)
+        .header("Authorization", format!("OAuth {token}"))
+        .send()
+        .context("Failed to reach Oculus Graph")?;
+
+    match response.status().as_u16() {
+        200..=299 => Ok(true),
+        400 | 401 | 403 => Ok(false),
+        status => bail!("Oculus Graph returned status {status}"),
+    }
+}
+
+/// Checks the token with Oculus Graph.
+/// Returns `Ok(false)` if it was rejected, errors if Graph could not be reached.
+#[cfg(feature = "ureq")]
+pub fn validate_token(token: &str) -> crate::Result<bool> {
+    let result = ureq::get(VALIDATE_URL)
+        .header("Authorization", &format!("OAuth {token}"))
+        .call();
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(ureq::Error::StatusCode(400 | 401 | 403)) => Ok(false),
+        Err(ureq::Error::StatusCode(status)) => bail!("Oculus Graph returned status {status}"),
+        Err(e) => Err(e).context("Failed to reach Oculus Graph"),
+    }
+}
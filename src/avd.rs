@@ -0,0 +1,90 @@
+//! Creating and deleting Android Virtual Devices.
+
+use std::path::PathBuf;
+
+use crate::{
+    constants::{self, avd_path},
+    error::{Context, Error},
+    runner::{ToolCommand, ToolRunner},
+    sdk,
+};
+
+/// Settings of a new AVD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvdSpec {
+    pub name: String,
+    /// System image package, e.g. `system-images;android-33;android-desktop;x86_64`
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    /// FPS limit of the display, 0 for unlimited
+    pub fps: u32,
+}
+
+impl Default for AvdSpec {
+    fn default() -> Self {
+        Self {
+            name: constants::DEFAULT_AVD_NAME.to_string(),
+            image: constants::DEFAULT_AVD_IMAGE.to_string(),
+            width: 1920,
+            height: 1080,
+            fps: 60,
+        }
+    }
+}
+
+/// Folder of an AVD, `{avd}/{name}.avd`
+pub fn avd_dir(name: &str) -> PathBuf {
+    avd_path().join(format!("{name}.avd"))
+}
+
+pub fn exists(name: &str) -> bool {
+    avd_dir(name).exists()
+}
+
+/// Creates an AVD and configures its display.
+/// Fails with [`Error::AvdExists`] if an AVD with the name exists,
+/// [`delete`] it first to replace it.
+/// Returns the folder of the AVD.
+pub fn create(runner: &dyn ToolRunner, spec: &AvdSpec) -> crate::Result<PathBuf> {
+    if !sdk::is_image_installed(&spec.image) {
+        return Err(Error::ImageNotInstalled(spec.image.clone()));
+    }
+    if exists(&spec.name) {
+        return Err(Error::AvdExists(spec.name.clone()));
+    }
+
+    run_avdmanager(
+        runner,
+        ["create", "avd", "-n", &spec.name, "-k", &spec.image],
+    )
+    .context("Failed to create AVD (Android Virtual Device)")?;
+
+    // Set the screen size in config.ini
+    let avd_dir = avd_dir(&spec.name);
+    let config_path = avd_dir.join("config.ini");
+    if config_path.exists() {
+        use std::io::Write;
+        let mut config = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&config_path)?;
+        writeln!(config, "hw.lcd.width={}", spec.width)?;
+        writeln!(config, "hw.lcd.height={}", spec.height)?;
+        writeln!(config, "hw.lcd.vsync={}", spec.fps)?;
+        writeln!(config, "hw.gpu.enabled=yes")?;
+        writeln!(config, "hw.gpu.mode=auto")?;
+    }
+    Ok(avd_dir)
+}
+
+pub fn delete(runner: &dyn ToolRunner, name: &str) -> crate::Result<()> {
+    run_avdmanager(runner, ["delete", "avd", "-n", name])
+        .context("Failed to delete AVD (Android Virtual Device)")
+}
+
+/// Runs avdmanager with its output captured, so it does not mix with the caller's output.
+/// stdin is closed, which answers avdmanager's custom hardware profile prompt with "no".
+fn run_avdmanager<const N: usize>(runner: &dyn ToolRunner, args: [&str; N]) -> crate::Result<()> {
+    runner.run(&ToolCommand::new("avdmanager", constants::avdmanager_path()).args(args))?;
+    Ok(())
+}
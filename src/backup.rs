@@ -0,0 +1,412 @@
+//! Backups of an app's files on the device, to reset its state while testing mods and saves.
+//!
+//! A backup folder holds [`MANIFEST_FILE`], the app's external data and OBB folders as
+//! pulled by adb, and an archive of `/data/data/<package>` when the device lets adb read it.
+//! Files restored to external storage are owned by the app through the storage daemon,
+//! the internal data is handed back to the app's user ID explicitly.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adb::{device_command, shell_quote},
+    apk::obb::device_obb_dir,
+    app,
+    error::{Context, bail},
+    progress::{ProgressReporter, Task},
+    runner::ToolRunner,
+};
+
+/// Describes a backup folder
+pub const MANIFEST_FILE: &str = "backup.json";
+
+/// Where the archive of the internal data is staged on the device before extracting it
+const DEVICE_STAGING_DIR: &str = "/data/local/tmp";
+
+/// Directory on the device with the external data of a package, e.g. save files of Unity games
+pub fn device_data_dir(package: &str) -> String {
+    format!("/sdcard/Android/data/{package}")
+}
+
+/// Directory with the internal data of a package, only readable by the app itself
+pub fn device_internal_dir(package: &str) -> String {
+    format!("/data/data/{package}")
+}
+
+/// A folder of the app on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupPart {
+    /// [`device_data_dir`]
+    Data,
+    /// [`device_obb_dir`]
+    Obb,
+    /// [`device_internal_dir`], only with [`InternalAccess`]
+    Internal,
+}
+
+impl BackupPart {
+    pub fn device_dir(self, package: &str) -> String {
+        match self {
+            BackupPart::Data => device_data_dir(package),
+            BackupPart::Obb => device_obb_dir(package),
+            BackupPart::Internal => device_internal_dir(package),
+        }
+    }
+
+    /// The part's folder, or archive for the internal data, inside the backup folder
+    pub fn backup_path(self) -> &'static str {
+        match self {
+            BackupPart::Data => "data",
+            BackupPart::Obb => "obb",
+            BackupPart::Internal => "internal.tar",
+        }
+    }
+}
+
+/// A part that is not in the backup and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedPart {
+    pub part: BackupPart,
+    pub reason: String,
+}
+
+/// Contents of [`MANIFEST_FILE`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub package: String,
+    pub version_code: Option<u64>,
+    pub version_name: Option<String>,
+    /// Serial of the device the backup was taken on
+    pub serial: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    pub parts: Vec<BackupPart>,
+    #[serde(default)]
+    pub skipped: Vec<SkippedPart>,
+}
+
+impl BackupManifest {
+    pub fn load(dir: &Path) -> crate::Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = std::fs::read(&path)
+            .with_context(|| format!("Failed to read {}, is it a backup?", path.display()))?;
+        let manifest: Self = serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        // The package name ends up in commands run as root
+        app::validate_package(&manifest.package)?;
+        Ok(manifest)
+    }
+
+    pub fn save(&self, dir: &Path) -> crate::Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// How adb can reach [`device_internal_dir`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalAccess {
+    /// adbd runs as root, after `adb root` on userdebug images
+    Root,
+    /// The image has `su`
+    Su,
+    /// The app is debuggable, so commands can run as the app with `run-as`
+    RunAs,
+}
+
+impl InternalAccess {
+    /// The best access the device allows, `None` on user builds with a release app
+    pub fn detect(
+        runner: &dyn ToolRunner,
+        serial: &str,
+        package: &str,
+    ) -> crate::Result<Option<Self>> {
+        for access in [
+            InternalAccess::Root,
+            InternalAccess::Su,
+            InternalAccess::RunAs,
+        ] {
+            let output = app::shell_script(runner, serial, &access.wrap(package, "id -u"))?;
+            let uid = output.stdout_lossy();
+            let works = match access {
+                InternalAccess::Root | InternalAccess::Su => uid.trim() == "0",
+                InternalAccess::RunAs => output.status.success() && !uid.trim().is_empty(),
+            };
+            if works {
+                return Ok(Some(access));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The script `command` run with this access, as the script for `adb shell`
+    fn wrap(self, package: &str, command: &str) -> String {
+        match self {
+            InternalAccess::Root => command.to_string(),
+            InternalAccess::Su => format!("su 0 sh -c {}", shell_quote(command)),
+            InternalAccess::RunAs => format!(
+                "run-as {} sh -c {}",
+                shell_quote(package),
+                shell_quote(command)
+            ),
+        }
+    }
+}
+
+/// Stops the app and copies its folders on the device into `dir`, which must be empty
+pub fn backup(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+    dir: &Path,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<BackupManifest> {
+    app::validate_package(package)?;
+    let info = app::package(runner, serial, package)?;
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        bail!("{} is not empty, back up to a new folder", dir.display());
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    // Files the app is writing would end up half written in the backup
+    app::stop(runner, serial, package)?;
+
+    let mut parts = Vec::new();
+    let mut skipped = Vec::new();
+    for part in [BackupPart::Data, BackupPart::Obb] {
+        let device_dir = part.device_dir(package);
+        if !device_dir_exists(runner, serial, &device_dir)? {
+            skipped.push(SkippedPart {
+                part,
+                reason: format!("{device_dir} does not exist"),
+            });
+            continue;
+        }
+        reporter.start(Task::Pull, format!("Pulling {device_dir}"), None);
+        // adb pull names the copy after the destination when it does not exist yet
+        let command =
+            device_command(serial, ["pull", &device_dir]).arg(dir.join(part.backup_path()));
+        runner
+            .run(&command)
+            .with_context(|| format!("Failed to pull {device_dir}"))?;
+        reporter.finish(Task::Pull, format!("Pulled {device_dir}"));
+        parts.push(part);
+    }
+
+    match InternalAccess::detect(runner, serial, package)? {
+        Some(access) => {
+            pull_internal(runner, serial, package, access, dir, reporter)?;
+            parts.push(BackupPart::Internal);
+        }
+        None => skipped.push(SkippedPart {
+            part: BackupPart::Internal,
+            reason: format!(
+                "{} is only readable on rooted images, run `adb root` on userdebug images, or for debuggable apps",
+                device_internal_dir(package)
+            ),
+        }),
+    }
+
+    let manifest = BackupManifest {
+        package: package.to_string(),
+        version_code: info.version_code,
+        version_name: info.version_name,
+        serial: serial.to_string(),
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        parts,
+        skipped,
+    };
+    manifest.save(dir)?;
+    Ok(manifest)
+}
+
+fn device_dir_exists(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    device_dir: &str,
+) -> crate::Result<bool> {
+    Ok(app::shell(runner, serial, &["test", "-d", device_dir])?
+        .status
+        .success())
+}
+
+/// Streams a tar archive of the internal data into the backup
+fn pull_internal(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+    access: InternalAccess,
+    dir: &Path,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<()> {
+    let device_dir = device_internal_dir(package);
+    let path = dir.join(BackupPart::Internal.backup_path());
+    reporter.start(Task::Pull, format!("Archiving {device_dir}"), None);
+
+    let mut file =
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut write_error = None;
+    // Without a pty the archive stays intact, unlike exec-out this keeps the exit status
+    let archive = access.wrap(
+        package,
+        &format!("tar -cf - -C {} .", shell_quote(&device_dir)),
+    );
+    let command = device_command(serial, ["shell", "-T", &archive]);
+    let output = runner.stream(&command, &mut |bytes| {
+        if write_error.is_none() {
+            write_error = file.write_all(bytes).err();
+        }
+    })?;
+    output
+        .check("adb")
+        .with_context(|| format!("Failed to archive {device_dir}"))?;
+    if let Some(e) = write_error {
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+
+    reporter.finish(Task::Pull, format!("Archived {device_dir}"));
+    Ok(())
+}
+
+/// Stops the app and replaces its folders on the device with the ones in the backup.
+/// The app must be installed, files it created since the backup are deleted
+pub fn restore(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    dir: &Path,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<BackupManifest> {
+    let manifest = BackupManifest::load(dir)?;
+    let package = manifest.package.as_str();
+    app::stop(runner, serial, package)?;
+
+    // Check before changing anything, so a restore does not stop half way
+    let access = match manifest.parts.contains(&BackupPart::Internal) {
+        true => Some(InternalAccess::detect(runner, serial, package)?.with_context(|| {
+            format!(
+                "The backup has {}, which is only writable on rooted images or for debuggable apps",
+                device_internal_dir(package)
+            )
+        })?),
+        false => None,
+    };
+
+    for &part in &manifest.parts {
+        match (part, access) {
+            (BackupPart::Internal, Some(access)) => {
+                push_internal(runner, serial, package, access, dir, reporter)?
+            }
+            (BackupPart::Internal, None) => {}
+            (part, _) => push_dir(runner, serial, package, part, dir, reporter)?,
+        }
+    }
+    Ok(manifest)
+}
+
+/// Empties the part's folder on the device and pushes the backed up files into it
+fn push_dir(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+    part: BackupPart,
+    dir: &Path,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<()> {
+    let device_dir = part.device_dir(package);
+    let local_dir = dir.join(part.backup_path());
+    let mut entries = std::fs::read_dir(&local_dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| Ok(entry?.path()))
+                .collect::<std::io::Result<Vec<PathBuf>>>()
+        })
+        .with_context(|| format!("Failed to read {}", local_dir.display()))?;
+    entries.sort();
+
+    // The folder itself stays, the storage daemon made it the app's
+    let quoted = shell_quote(&device_dir);
+    let empty = format!("mkdir -p {quoted} && find {quoted} -mindepth 1 -delete");
+    app::shell_script(runner, serial, &empty)?
+        .check("adb")
+        .with_context(|| format!("Failed to empty {device_dir}"))?;
+
+    reporter.start(Task::Push, format!("Pushing {}", local_dir.display()), None);
+    for entry in entries {
+        let command = device_command(serial, ["push"])
+            .arg(&entry)
+            .arg(&device_dir);
+        runner
+            .run(&command)
+            .with_context(|| format!("Failed to copy {} to the device", entry.display()))?;
+    }
+    reporter.finish(Task::Push, format!("Restored {device_dir}"));
+    Ok(())
+}
+
+/// Replaces the internal data with the archive in the backup, owned by the app's user
+fn push_internal(
+    runner: &dyn ToolRunner,
+    serial: &str,
+    package: &str,
+    access: InternalAccess,
+    dir: &Path,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<()> {
+    let device_dir = device_internal_dir(package);
+    let archive = dir.join(BackupPart::Internal.backup_path());
+    let staged = format!("{DEVICE_STAGING_DIR}/{package}.backup.tar");
+    reporter.start(Task::Push, format!("Pushing {}", archive.display()), None);
+    runner
+        .run(&device_command(serial, ["push"]).arg(&archive).arg(&staged))
+        .with_context(|| format!("Failed to copy {} to the device", archive.display()))?;
+
+    // run-as extracts as the app's user, root has to hand the files back to it
+    let (quoted_dir, quoted_staged) = (shell_quote(&device_dir), shell_quote(&staged));
+    let mut extract =
+        format!("find {quoted_dir} -mindepth 1 -delete; tar -xf {quoted_staged} -C {quoted_dir}");
+    if access != InternalAccess::RunAs {
+        let uid = app_uid(runner, serial, package)?;
+        extract.push_str(&format!(
+            " && chown -R {uid}:{uid} {quoted_dir} && restorecon -R {quoted_dir}"
+        ));
+    }
+    let result = app::shell(runner, serial, &["chmod", "644", &staged])?
+        .check("adb")
+        .and_then(|_| {
+            app::shell_script(runner, serial, &access.wrap(package, &extract))?.check("adb")
+        });
+    // The staged archive is removed even if extracting failed
+    app::shell(runner, serial, &["rm", "-f", &staged])?;
+    result.with_context(|| format!("Failed to restore {device_dir}"))?;
+
+    reporter.finish(Task::Push, format!("Restored {device_dir}"));
+    Ok(())
+}
+
+/// `package:com.beatgames.beatsaber uid:10123` from `pm list packages -U`
+fn app_uid(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<u32> {
+    let output =
+        app::shell(runner, serial, &["pm", "list", "packages", "-U", package])?.check("pm")?;
+    output
+        .stdout_lossy()
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next()? == format!("package:{package}"))
+                .then(|| fields.find_map(|field| field.strip_prefix("uid:")?.parse().ok()))?
+        })
+        .with_context(|| format!("Failed to find the user ID of {package}"))
+}
@@ -1,21 +1,32 @@
 use std::{
-    fs::OpenOptions,
-    io::Cursor,
+    collections::HashMap,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 // use bytes::{BufMut, Bytes, BytesMut};
 use color_eyre::eyre::{Context, ContextCompat, bail, eyre};
-use itertools::Itertools;
 use mbf_res_man::version_grabber;
-use mbf_zip::FileCompression;
+use owo_colors::OwoColorize;
 use semver::Version;
 
 use crate::{
-    commands::Command,
-    constants::{self, adb_path},
+    apk::{
+        self, InstallOptions, InstallReport, PatchOptions, PatchReport,
+        assets::{ASSETS_FILE, AssetManifest},
+        bundle::{ApkSet, is_bundle},
+        downloads::{CleanupReport, DOWNLOADS_FILE, DownloadManifest, cleanup_downloads},
+        info::{ApkInfo, format_size, open_apk, read_apk_info, read_manifest_bytes},
+        manifest::decode_manifest,
+        obb::is_package_obb,
+        store::{StoreVersion, VersionQuery, store_versions},
+        verify::{SignatureScheme, VerifyReport, verify_apk},
+    },
+    auth::require_token,
+    commands::{Command, GlobalContext},
+    error::Error,
+    keystore::{KeystoreFormat, SigningKey},
 };
-use mbf_axml::{AxmlReader, AxmlWriter, axml_to_xml, xml_to_axml};
 
 #[derive(clap::Parser, Debug)]
 pub struct ApkArgs {
@@ -27,14 +38,23 @@ pub struct ApkArgs {
 pub enum ApkAction {
     /// Download an APK from Oculus Graph and optionally patch and install it
     Download {
-        /// Oculus auth token, can be found in the browser devtools when logged in to oculus.com
+        /// Oculus auth token, can be found in the browser devtools when logged in to oculus.com.
+        /// Defaults to OCULUS_TOKEN or the token stored by `auth login`
+        #[arg(long)]
+        token: Option<String>,
+        /// The ID of the APK to download, e.g. "com.beatgames.beatsaber" is 2448060205267927.
+        /// Defaults to the graph_app_id setting
         #[arg(long)]
-        token: String,
-        /// The ID of the APK to download, e.g. "com.beatgames.beatsaber" is 2448060205267927
-        #[arg(long, default_value = "2448060205267927")]
-        graph_app_id: String,
-        /// The version of the APK to download, e.g. "1.0.0"
-        fuzzy_version: String,
+        graph_app_id: Option<String>,
+        /// The version of the APK to download: "latest", a version such as "1.37.0",
+        /// a semver range such as "^1.37" or ">=1.35 <1.40", or a version code.
+        /// With --interactive, only versions containing this are offered
+        #[arg(required_unless_present = "interactive")]
+        fuzzy_version: Option<String>,
+
+        /// Pick the version to download from a list of the available versions
+        #[arg(long, short, default_value_t = false)]
+        interactive: bool,
 
         /// Output path, defaults to current directory
         output: Option<PathBuf>,
@@ -46,12 +66,85 @@ pub enum ApkAction {
         /// Installs APK and obb after download
         #[arg(long, default_value_t = false)]
         install: bool,
+
+        /// Remove the downloaded files after installing
+        #[arg(long, default_value_t = false, requires = "install")]
+        cleanup: bool,
+
+        /// Also download the DLC the store lists for the version
+        #[arg(long, default_value_t = false)]
+        dlc: bool,
+
+        /// Also download the additional asset files the store lists for the version.
+        /// Some apps do not work without them
+        #[arg(long, default_value_t = false)]
+        assets: bool,
+
+        /// Install even if the device cannot run the APK's native libraries
+        #[arg(long, default_value_t = false)]
+        skip_abi_check: bool,
+
+        #[command(flatten)]
+        signing: SigningArgs,
+    },
+    /// List the versions of an app available on the Oculus store
+    Versions {
+        /// Oculus auth token, can be found in the browser devtools when logged in to oculus.com.
+        /// Defaults to OCULUS_TOKEN or the token stored by `auth login`
+        #[arg(long)]
+        token: Option<String>,
+        /// The ID of the app, e.g. "com.beatgames.beatsaber" is 2448060205267927.
+        /// Defaults to the graph_app_id setting
+        #[arg(long)]
+        graph_app_id: Option<String>,
     },
     /// Patch an APK to work in the emulator.
     Patch {
-        /// Path to the APK to patch
+        /// Path to the APK, split APK folder or bundle (.apks, .xapk, .apkm) to patch
+        path: PathBuf,
+
+        /// Folder to unpack bundles into, defaults to the bundle path without its extension
+        #[arg(long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        signing: SigningArgs,
+    },
+    /// Show the package, versions, SDK levels, ABIs and permissions of an APK
+    Info {
+        /// Path to the APK to inspect
+        path: PathBuf,
+    },
+    /// Print the decoded AndroidManifest.xml of an APK
+    Manifest {
+        /// Path to the APK to inspect
+        path: PathBuf,
+    },
+    /// Verify the signature and zip alignment of an APK
+    Verify {
+        /// Path to the APK to verify
         path: PathBuf,
     },
+    /// Generate a new keystore for signing patched APKs
+    Keygen {
+        /// Path of the keystore to create, e.g. "release.p12"
+        output: PathBuf,
+        /// Format of the keystore, guessed from the file extension if omitted
+        #[arg(long, value_enum)]
+        format: Option<KeystoreFormat>,
+        /// Alias of the generated key entry
+        #[arg(long, default_value = "quest_emu")]
+        alias: String,
+        /// Keystore password. Defaults to QUEST_EMU_KEYSTORE_PASS, prompted for if neither is set
+        #[arg(long)]
+        password: Option<String>,
+        /// Common name (CN) of the generated certificate
+        #[arg(long, default_value = "Quest Emu")]
+        common_name: String,
+        /// Overwrite the keystore if it already exists
+        #[arg(long, default_value_t = false)]
+        overwrite: bool,
+    },
     /// Install an APK and its OBB file to the emulator
     Install {
         /// The ID of the APK to install. E.g. "com.beatgames.beatsaber"
@@ -59,56 +152,284 @@ pub enum ApkAction {
         /// The folder where the APK and OBB files are located. Directory looks like:
         /// <output_folder>/
         ///     <apk_id>.apk
+        ///     split_config.<name>.apk (optional)
         ///     main.<version_code>.<apk_id>.obb (optional)
+        ///     patch.<version_code>.<apk_id>.obb (optional)
+        ///     quest_emu_assets.json (optional, DLC and asset files from `apk download`)
+        /// A split APK bundle (.apks, .xapk, .apkm) is unpacked, patched and re-signed before installing
         folder_path: PathBuf,
+
+        /// Install even if the device cannot run the APK's native libraries
+        #[arg(long, default_value_t = false)]
+        skip_abi_check: bool,
+
+        /// Remove the files `apk download` put in the folder after installing.
+        /// Files the tool did not download are never removed, bundles are not supported
+        #[arg(long, default_value_t = false)]
+        cleanup: bool,
+
+        #[command(flatten)]
+        signing: SigningArgs,
     },
 }
-const MANIFEST_FILE: &str = "AndroidManifest.xml";
-const CERT_PEM: &[u8] = include_bytes!("../debug_cert.pem");
+
+/// Options for the key used to sign patched APKs.
+/// Devices refuse to update an app signed with a different key,
+/// so use the same keystore for every build installed on a device.
+#[derive(clap::Args, Debug)]
+pub struct SigningArgs {
+    /// Keystore to sign the APK with. Defaults to the embedded debug key
+    #[arg(long)]
+    keystore: Option<PathBuf>,
+    /// Format of the keystore, guessed from the file extension if omitted
+    #[arg(long, value_enum, requires = "keystore")]
+    keystore_format: Option<KeystoreFormat>,
+    /// Alias of the key in the keystore. Defaults to the first private key entry
+    #[arg(long, requires = "keystore")]
+    key_alias: Option<String>,
+    /// Keystore password. Defaults to QUEST_EMU_KEYSTORE_PASS, prompted for if neither is set
+    #[arg(long, requires = "keystore")]
+    keystore_pass: Option<String>,
+    /// Key password for JKS keystores. Defaults to the keystore password
+    #[arg(long, requires = "keystore")]
+    key_pass: Option<String>,
+}
+
+impl SigningArgs {
+    /// Loads the configured signing key, falling back to the debug key
+    pub fn load(&self, ctx: &GlobalContext) -> color_eyre::Result<SigningKey> {
+        let Some(keystore) = &self.keystore else {
+            return Ok(SigningKey::debug());
+        };
+
+        let format = self
+            .keystore_format
+            .or_else(|| KeystoreFormat::from_path(keystore))
+            .with_context(|| {
+                format!(
+                    "Unable to detect the format of {}, pass --keystore-format",
+                    keystore.display()
+                )
+            })?;
+
+        let store_password = match (&self.keystore_pass, format) {
+            (Some(password), _) => password.clone(),
+            (None, KeystoreFormat::Pem) => String::new(),
+            (None, _) => prompt_password(ctx, "Keystore password", false)?,
+        };
+
+        Ok(SigningKey::load(
+            keystore,
+            format,
+            self.key_alias.as_deref(),
+            &store_password,
+            self.key_pass.as_deref(),
+        )?)
+    }
+}
+
+/// Environment variable with the keystore password, for scripts that cannot pass it as an argument
+const KEYSTORE_PASSWORD_ENV: &str = "QUEST_EMU_KEYSTORE_PASS";
+
+/// Reads the password from [`KEYSTORE_PASSWORD_ENV`] or prompts for it.
+/// When prompts are skipped, an existing keystore is opened with an empty password,
+/// while a `new` keystore is refused rather than left unprotected
+fn prompt_password(ctx: &GlobalContext, prompt: &str, new: bool) -> color_eyre::Result<String> {
+    if let Ok(password) = std::env::var(KEYSTORE_PASSWORD_ENV) {
+        return Ok(password);
+    }
+    if ctx.yes {
+        if new {
+            bail!(
+                "A password is required for the keystore, pass --password or set {KEYSTORE_PASSWORD_ENV}"
+            );
+        }
+        return Ok(String::new());
+    }
+
+    let mut password = dialoguer::Password::new().with_prompt(prompt);
+    if new {
+        password = password.with_confirmation("Confirm password", "Passwords do not match");
+    }
+    Ok(password.allow_empty_password(true).interact()?)
+}
 
 impl Command for ApkArgs {
-    fn execute(self, _ctx: &crate::commands::GlobalContext) -> color_eyre::Result<()> {
-        match self.action {
-            ApkAction::Patch { path } => {
-                do_patch(&path)?;
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let result = match self.action {
+            ApkAction::Patch {
+                path,
+                output,
+                signing,
+            } => {
+                let options = PatchOptions {
+                    signing_key: signing.load(ctx)?,
+                    output,
+                };
+                let report = run_patch(ctx, &path, &options)?;
+                if let Some(unpacked_to) = &report.unpacked_to {
+                    ctx.info(format_args!(
+                        "Patched APKs written to {}",
+                        unpacked_to.display()
+                    ));
+                }
+                serde_json::to_value(report)?
             }
-            ApkAction::Install {
-                apk_id,
-                folder_path,
+            ApkAction::Versions {
+                token,
+                graph_app_id,
             } => {
-                let apk_path = folder_path.join(format!("{}.apk", &apk_id));
-                if !apk_path.exists() {
-                    bail!("APK file not found at path: {:?}", apk_path);
+                let graph_app_id = graph_app_id.unwrap_or_else(|| ctx.config.graph_app_id.clone());
+                let token = require_token(token)?;
+                let versions = version_grabber::get_live_versions(
+                    &token,
+                    Version::new(0, 0, 0),
+                    &graph_app_id,
+                )
+                .map_err(|e| eyre!(e))?;
+                let store_versions = store_versions(&versions);
+                if !ctx.json {
+                    print_store_versions(&store_versions);
+                }
+                serde_json::to_value(store_versions)?
+            }
+            ApkAction::Info { path } => {
+                let info = read_apk_info(&path)?;
+                if !ctx.json {
+                    print_apk_info(&info);
+                }
+                serde_json::to_value(info)?
+            }
+            ApkAction::Manifest { path } => {
+                let mut apk = open_apk(&path)?;
+                let xml_str = decode_manifest(read_manifest_bytes(&mut apk)?, true)?;
+                if !ctx.json {
+                    println!("{xml_str}");
+                }
+                serde_json::json!({ "manifest": xml_str })
+            }
+            ApkAction::Verify { path } => {
+                let report = verify_apk(&path)?;
+                if !report.is_valid() {
+                    return Err(verification_failed(ctx, path, report));
+                }
+                if !ctx.json {
+                    print_verify_report(&report);
+                }
+                serde_json::to_value(report)?
+            }
+            ApkAction::Keygen {
+                output,
+                format,
+                alias,
+                password,
+                common_name,
+                overwrite,
+            } => {
+                if output.exists() && !overwrite {
+                    bail!(
+                        "Keystore {} already exists, pass --overwrite to replace it",
+                        output.display()
+                    );
                 }
 
-                // Find the obb file if it exists
-                let obb_files: Vec<_> = std::fs::read_dir(&folder_path)?
-                    .filter_map(|entry| {
-                        let entry = entry.ok()?;
-                        let file_name = entry.file_name().to_string_lossy().to_string();
-                        if file_name.ends_with(".obb")
-                        {
-                            Some(entry.path())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
+                let format = format
+                    .or_else(|| KeystoreFormat::from_path(&output))
+                    .unwrap_or(KeystoreFormat::Pkcs12);
+                let password = match (password, format) {
+                    (Some(password), _) => password,
+                    (None, KeystoreFormat::Pem) => String::new(),
+                    (None, _) => prompt_password(ctx, "Keystore password", true)?,
+                };
 
-                let obb_path = obb_files
-                    .first()
-                    .cloned();
+                ctx.info("Generating signing key, this may take a moment");
+                let signing_key = SigningKey::generate(&common_name)?;
+                signing_key.save(&output, format, &alias, &password)?;
 
-                do_install(&folder_path, &apk_path, obb_path.as_deref())?;
+                ctx.info(format_args!("Created keystore at {}", output.display()));
+                ctx.info(format_args!(
+                    "SHA-256 fingerprint: {}",
+                    signing_key.sha256_fingerprint()
+                ));
+                if format == KeystoreFormat::Pem {
+                    ctx.info("The PEM private key is not encrypted, keep this file private");
+                }
+                serde_json::json!({
+                    "path": output,
+                    "format": format,
+                    "alias": alias,
+                    "sha256": signing_key.sha256_fingerprint(),
+                })
+            }
+            ApkAction::Install {
+                apk_id,
+                folder_path,
+                skip_abi_check,
+                cleanup,
+                signing,
+            } => {
+                if is_bundle(&folder_path) {
+                    if cleanup {
+                        bail!(
+                            "--cleanup removes the files of `apk download` from a folder, it does not apply to bundles"
+                        );
+                    }
+                    // The unpacked copy is ours and removed when dropped, the bundle itself is left alone
+                    let unpack_dir = tempfile::Builder::new()
+                        .prefix("quest_emu_bundle_")
+                        .tempdir()
+                        .context("Failed to create a folder to unpack the bundle to")?;
+                    let options = PatchOptions {
+                        signing_key: signing.load(ctx)?,
+                        output: Some(unpack_dir.path().join("unpacked")),
+                    };
+                    let patch = run_patch(ctx, &folder_path, &options)?;
+                    if patch.apk_set.package != apk_id {
+                        bail!(
+                            "Bundle contains {} but {} was requested",
+                            patch.apk_set.package,
+                            apk_id
+                        );
+                    }
+                    let install = run_install(ctx, &patch.apk_set, skip_abi_check)?;
+                    let mut patch = serde_json::to_value(patch)?;
+                    // Removed along with unpack_dir before the command returns
+                    if let Some(patch) = patch.as_object_mut() {
+                        patch.remove("unpacked_to");
+                    }
+                    serde_json::json!({ "patch": patch, "install": install })
+                } else {
+                    let apk_set = ApkSet::from_dir(&folder_path, Some(&apk_id))?;
+                    let install = run_install(ctx, &apk_set, skip_abi_check)?;
+                    let cleanup = match cleanup {
+                        true => do_cleanup(ctx, &folder_path)?,
+                        false => CleanupReport::default(),
+                    };
+                    serde_json::json!({
+                        "install": install,
+                        "removed": cleanup.removed,
+                        "kept": cleanup.kept,
+                    })
+                }
             }
             ApkAction::Download {
                 token,
                 graph_app_id,
                 fuzzy_version,
+                interactive,
                 output,
                 patch,
                 install,
+                cleanup,
+                dlc,
+                assets,
+                skip_abi_check,
+                signing,
             } => {
+                let signing_key = patch.then(|| signing.load(ctx)).transpose()?;
+
+                let graph_app_id = graph_app_id.unwrap_or_else(|| ctx.config.graph_app_id.clone());
+                let token = require_token(token)?;
                 let versions = version_grabber::get_live_versions(
                     &token,
                     Version::new(0, 0, 0),
@@ -116,35 +437,30 @@ impl Command for ApkArgs {
                 )
                 .map_err(|e| eyre!(e))?;
 
-                let matching_version: &str = versions
-                    // 1) Try exact match
-                    .iter()
-                    .find_map(|(v, _)| {
-                        let exact = v.non_semver == fuzzy_version;
-                        exact.then_some(&v.non_semver)
-                    })
-                    // 2) If no exact match, try a fuzzy match (contains / contained-by)
-                    .or_else(|| {
-                        versions.iter().find_map(|(v, _)| {
-                            let ns = &v.non_semver;
-                            let matches = ns.contains(&fuzzy_version) || fuzzy_version.contains(ns);
-                            matches.then_some(ns)
-                        })
-                    })
-                    // convert Option<&String> -> Option<&str> and fallback to the original input
-                    .map(|s| s.as_str())
-                    .unwrap_or(&fuzzy_version);
+                let store_versions = store_versions(&versions);
+                let matching_version = match (interactive, &fuzzy_version) {
+                    (true, filter) => select_version(ctx, &store_versions, filter.as_deref())?,
+                    (false, Some(fuzzy_version)) => VersionQuery::parse(fuzzy_version)
+                        .resolve(&store_versions)?
+                        .version
+                        .clone(),
+                    (false, None) => bail!("No version given, pass a version or --interactive"),
+                };
 
-                println!("Downloading {} version {}", graph_app_id, matching_version);
+                ctx.info(format_args!(
+                    "Downloading {} version {}",
+                    graph_app_id, matching_version
+                ));
 
                 let output = output.unwrap_or("./apk".into());
+                let before = file_times(&output);
                 let downloaded = version_grabber::download_version(
                     &token,
                     &versions,
-                    matching_version,
-                    false,
+                    &matching_version,
+                    assets,
                     &output,
-                    false,
+                    dlc,
                 )
                 .map_err(|e| eyre!(e))?
                 .context("Version not found")?;
@@ -152,153 +468,399 @@ impl Command for ApkArgs {
                 let version_folder = output.join(&downloaded.main.version);
                 let apk_path = version_folder.join(format!("{}.apk", &downloaded.main.id));
 
-                let obb_files: Vec<_> = std::fs::read_dir(&version_folder)?
-                    .filter_map(|entry| {
-                        let entry = entry.ok()?;
-                        let file_name = entry.file_name().to_string_lossy().to_string();
-                        if file_name.ends_with(".obb")
-                        {
-                            Some(entry.path())
-                        } else {
-                            None
-                        }
+                // Only files this download wrote are the store's, the folder may hold
+                // files of earlier downloads or the user's own
+                let written: Vec<PathBuf> = file_times(&output)
+                    .into_iter()
+                    .filter(|(path, modified)| {
+                        path.parent() == Some(version_folder.as_path())
+                            && before.get(path) != Some(modified)
                     })
+                    .map(|(path, _)| path)
                     .collect();
+                // Everything else the store provided is an additional file,
+                // OBBs that do not follow the main/patch naming included
+                let mut additional_files: Vec<PathBuf> = match dlc || assets {
+                    true => written
+                        .iter()
+                        .filter(|path| {
+                            let file_name = path
+                                .file_name()
+                                .and_then(|n| n.to_str())
+                                .unwrap_or_default();
+                            **path != apk_path
+                                && ![DOWNLOADS_FILE, ASSETS_FILE].contains(&file_name)
+                                && !is_package_obb(path, &downloaded.main.id)
+                        })
+                        .cloned()
+                        .collect(),
+                    false => Vec::new(),
+                };
+                additional_files.sort();
+                // OBBs of an earlier download are installed too, but only recorded once
+                let mut obb_files: Vec<PathBuf> = std::fs::read_dir(&version_folder)?
+                    .map(|entry| Ok(entry?.path()))
+                    .collect::<std::io::Result<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|path| is_package_obb(path, &downloaded.main.id))
+                    .collect();
+                obb_files.sort();
 
-                let obb_path = obb_files
-                    .first()
-                    .cloned();
+                if dlc || assets {
+                    AssetManifest::new(&downloaded.main.id, &version_folder, &additional_files)?
+                        .save(&version_folder)?;
+                    ctx.info(format_args!(
+                        "Downloaded {} additional files",
+                        additional_files.len()
+                    ));
+                }
 
-                println!(
+                let apk_set =
+                    ApkSet::single(downloaded.main.id.clone(), apk_path.clone(), obb_files);
+
+                ctx.info(format_args!(
                     "Downloaded {} version {}",
                     downloaded.main.id, matching_version
-                );
-                match patch {
-                    true => {
-                        println!("Patching APK");
-                        do_patch(&apk_path)?
+                ));
+                let patch = match signing_key {
+                    Some(signing_key) => {
+                        let options = PatchOptions {
+                            signing_key,
+                            output: None,
+                        };
+                        Some(run_patch(ctx, &apk_path, &options)?)
                     }
-                    false => {
-                        println!(
+                    None => {
+                        ctx.info(
                             "You may need to patch the APK to work in the emulator using `apk patch`",
                         );
+                        None
                     }
+                };
+
+                // Recorded after patching so the patched APK is what cleanup expects
+                let mut downloads = DownloadManifest::load(&version_folder)?;
+                let downloaded_obbs = apk_set.obbs.iter().filter(|obb| written.contains(obb));
+                for file in apk_set
+                    .apks()
+                    .chain(downloaded_obbs)
+                    .chain(&additional_files)
+                {
+                    downloads.record(&version_folder, file)?;
                 }
+                downloads.save(&version_folder)?;
 
+                let mut result = serde_json::json!({
+                    "package": downloaded.main.id,
+                    "version": matching_version,
+                    "folder": version_folder,
+                    "apk": apk_path,
+                    "obbs": apk_set.obbs,
+                    "additional_files": additional_files,
+                    "patch": patch,
+                    "install": null,
+                    "removed": [],
+                    "kept": [],
+                });
                 if install {
-                    do_install(&output, &apk_path, obb_path.as_deref())?;
+                    result["install"] =
+                        serde_json::to_value(run_install(ctx, &apk_set, skip_abi_check)?)?;
+                    if cleanup {
+                        let cleanup = do_cleanup(ctx, &version_folder)?;
+                        result["removed"] = serde_json::to_value(cleanup.removed)?;
+                        result["kept"] = serde_json::to_value(cleanup.kept)?;
+                    }
                 }
+                result
             }
+        };
+        Ok(result)
+    }
+}
+
+fn run_install(
+    ctx: &GlobalContext,
+    apk_set: &ApkSet,
+    skip_abi_check: bool,
+) -> color_eyre::Result<InstallReport> {
+    let options = InstallOptions { skip_abi_check };
+    let report = match apk::install(&*ctx.runner, apk_set, &options, &*ctx.reporter) {
+        Err(e @ Error::AbiIncompatible(_)) => {
+            return Err(color_eyre::Report::new(e)
+                .wrap_err("Device cannot run the APK, pass --skip-abi-check to install anyway"));
         }
-        Ok(())
+        result => result?,
+    };
+
+    if let Some(diagnosis) = &report.abi_diagnosis {
+        ctx.info(diagnosis);
     }
+    ctx.info("Successfully installed APK");
+    Ok(report)
 }
 
-fn do_install(
-    output: &Path,
-    apk_path: &Path,
-    obb_binary: Option<&Path>,
-) -> Result<(), color_eyre::eyre::Error> {
-    println!("Installing APK");
-    let adb_path = adb_path();
-
-    std::process::Command::new(&adb_path)
-        .arg("install")
-        .arg(apk_path)
-        .status()
-        .context("Failed to install APK")?;
-    if obb_binary.is_some_and(|o| o.exists()) {
-        let file_name = obb_binary.as_deref().unwrap().file_name().unwrap().to_string_lossy().to_string();
-        // Extract package name from filename main.{id}.{package_name}.obb
-        let package_name: String = file_name
-            .split('.')
-            .skip(2)
-            .take_while(|e| e != &"obb")
-            .join(".");
-
-        let obb_device_path = format!("/sdcard/Android/obb/{}", &package_name);
-        std::process::Command::new(&adb_path)
-            .arg("shell")
-            .arg("mkdir")
-            .arg("-p")
-            .arg(&obb_device_path)
-            .status()
-            .context("Failed to create obb directory")?;
-
-        std::process::Command::new(&adb_path)
-            .arg("push")
-            .arg(&obb_binary.unwrap())
-            .arg(obb_device_path)
-            .status()
-            .context("Failed to copy obb")?;
+/// Patches and prints what was done, with the verification report if signing went wrong
+fn run_patch(
+    ctx: &GlobalContext,
+    path: &Path,
+    options: &PatchOptions,
+) -> color_eyre::Result<PatchReport> {
+    let report = match apk::patch(path, options, &*ctx.reporter) {
+        Err(Error::VerificationFailed { path, report }) => {
+            return Err(verification_failed(ctx, path, *report));
+        }
+        result => result?,
+    };
+
+    if report.apks.iter().any(|apk| apk.signed) {
+        ctx.info(format_args!(
+            "Signed with certificate SHA-256 {}",
+            options.signing_key.sha256_fingerprint()
+        ));
     }
-    std::fs::remove_dir_all(output).context("Failed to remove apk directory")?;
-    println!("Successfully installed APK");
-    Ok(())
+    Ok(report)
 }
 
-fn do_patch(path: &Path) -> Result<(), color_eyre::eyre::Error> {
-    println!("Patching APK from path: {path:?}");
-    let apk_file = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .open(path)
-        .context("")?;
-    let mut apk = mbf_zip::ZipFile::open(apk_file)
-        .map_err(|a| color_eyre::eyre::eyre!(a))
-        .context("Failed to read APK as zip file")?;
-    let manifest_bytes = apk
-        .read_file(MANIFEST_FILE)
-        .map_err(|a| color_eyre::eyre::eyre!(a))
-        .context("Failed to read AndroidManifest.xml from APK")?;
-    let axml_bytes = patch_manifest(manifest_bytes)?;
-    let mut axml_cursor = Cursor::new(axml_bytes);
-    apk.write_file(MANIFEST_FILE, &mut axml_cursor, FileCompression::Store)
-        .map_err(|a| color_eyre::eyre::eyre!(a))
-        .context("Failed to write modified AndroidManifest.xml back to APK")?;
-    let (cert, priv_key) = mbf_zip::signing::load_cert_and_priv_key(CERT_PEM);
-    apk.save_and_sign_v2(&priv_key, &cert)
-        .map_err(|a| color_eyre::eyre::eyre!(a))
-        .context("Failed to save modified APK")?;
-    println!("Successfully patched AndroidManifest.xml");
-    Ok(())
+/// Prints the report of an invalid APK, with `--json` it is part of the error instead
+fn verification_failed(
+    ctx: &GlobalContext,
+    path: PathBuf,
+    report: VerifyReport,
+) -> color_eyre::Report {
+    if !ctx.json {
+        print_verify_report(&report);
+    }
+    Error::VerificationFailed {
+        path,
+        report: Box::new(report),
+    }
+    .into()
 }
 
-/// AI generated code to patch the manifest
-/// I'm too lazy to do it myself
-/// since it's XML :(
-fn patch_manifest(manifest_bytes: Vec<u8>) -> Result<Vec<u8>, color_eyre::eyre::Error> {
-    let mut manifest_cursor = Cursor::new(manifest_bytes);
-    let mut axml_reader = AxmlReader::new(&mut manifest_cursor)
-        .map_err(|a| color_eyre::eyre::eyre!(a))
-        .context("Failed to parse AndroidManifest.xml as AXML")?;
-    let mut xml_bytes = Vec::new();
-    {
-        let mut writer = xml::EventWriter::new(&mut xml_bytes);
-        axml_to_xml(&mut writer, &mut axml_reader).map_err(|a| color_eyre::eyre::eyre!(a))?;
+/// Lets the user pick a store version, optionally narrowed down by `filter`
+fn select_version(
+    ctx: &GlobalContext,
+    versions: &[StoreVersion],
+    filter: Option<&str>,
+) -> color_eyre::Result<String> {
+    if ctx.yes {
+        bail!("--interactive cannot be combined with --yes, pass a version instead");
     }
-    let mut xml_str = String::from_utf8(xml_bytes)?;
-    let insert_str = r#"  <queries>    <package android:name="com.oculus.horizon"/>  </queries>"#;
-
-    // Check if the <queries> block with the package is already present
-    if !xml_str.contains(r#"<package android:name="com.oculus.horizon""#) {
-        match xml_str.rfind("</manifest>") {
-            Some(idx) => {
-                xml_str.insert_str(idx, insert_str);
-            }
-            None => {
-                bail!("No </manifest> tag found in manifest");
+
+    let candidates: Vec<_> = versions
+        .iter()
+        .filter(|v| filter.is_none_or(|filter| v.version.contains(filter)))
+        .collect();
+    if candidates.is_empty() {
+        bail!("No versions match {}", filter.unwrap_or_default());
+    }
+
+    let items: Vec<_> = candidates
+        .iter()
+        .map(|v| {
+            format!(
+                "{} ({}, released {}, {})",
+                v.version,
+                v.version_code
+                    .map_or("unknown code".to_string(), |c| format!("code {c}")),
+                v.release_date().as_deref().unwrap_or("unknown"),
+                v.size.map_or("unknown size".to_string(), format_size)
+            )
+        })
+        .collect();
+    let selection = dialoguer::Select::new()
+        .with_prompt("Version to download")
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    Ok(candidates[selection].version.clone())
+}
+
+fn print_store_versions(versions: &[StoreVersion]) {
+    let rows: Vec<_> = versions
+        .iter()
+        .map(|v| {
+            [
+                v.version.clone(),
+                v.version_code.map(|c| c.to_string()).unwrap_or_default(),
+                v.release_date().unwrap_or_default(),
+                v.size.map(format_size).unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let headers = ["VERSION", "CODE", "RELEASED", "SIZE"];
+    let widths: Vec<_> = (0..headers.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain([headers[i].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let line = |row: [&str; 4]| {
+        format!(
+            "{:<w0$}  {:>w1$}  {:<w2$}  {:>w3$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        )
+    };
+    println!("{}", line(headers).bold());
+    for row in &rows {
+        println!("{}", line(row.each_ref().map(String::as_str)));
+    }
+}
+
+/// Modification times and sizes of the files in `dir` and its subfolders, to tell which files
+/// a download wrote. Missing folders have no files
+fn file_times(dir: &Path) -> HashMap<PathBuf, Option<(SystemTime, u64)>> {
+    walkdir::WalkDir::new(dir)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let modified = entry
+                .metadata()
+                .ok()
+                .and_then(|m| Some((m.modified().ok()?, m.len())));
+            (entry.into_path(), modified)
+        })
+        .collect()
+}
+
+/// Removes the files `apk download` created in a folder, leaving everything else
+fn do_cleanup(ctx: &GlobalContext, dir: &Path) -> color_eyre::Result<CleanupReport> {
+    if !dir.join(DOWNLOADS_FILE).exists() {
+        ctx.info(format_args!(
+            "Nothing to clean up, {} has no files downloaded by quest_emu",
+            dir.display()
+        ));
+        return Ok(CleanupReport::default());
+    }
+
+    let report = cleanup_downloads(dir)?;
+    for kept in &report.kept {
+        ctx.info(format_args!(
+            "Keeping {}, it changed since it was downloaded",
+            kept.display()
+        ));
+    }
+    ctx.info(format_args!(
+        "Removed {} downloaded files from {}",
+        report.removed.len(),
+        dir.display()
+    ));
+    Ok(report)
+}
+
+fn print_apk_info(info: &ApkInfo) {
+    let manifest = &info.manifest;
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+
+    println!("Package: {}", manifest.package);
+    println!(
+        "Version: {} ({})",
+        or_unknown(manifest.version_name.clone()),
+        or_unknown(manifest.version_code.map(|v| v.to_string()))
+    );
+    println!(
+        "Min SDK: {}",
+        or_unknown(manifest.min_sdk.map(|v| v.to_string()))
+    );
+    println!(
+        "Target SDK: {}",
+        or_unknown(manifest.target_sdk.map(|v| v.to_string()))
+    );
+    println!("Size: {}", format_size(info.file_size));
+    println!(
+        "Patched: {}",
+        match manifest.queries_horizon || info.patch_state.is_some() {
+            true => "yes".green().to_string(),
+            false => "no".yellow().to_string(),
+        }
+    );
+    if let Some(state) = &info.patch_state {
+        println!("Original SHA-256: {}", state.original_sha256);
+        println!("Patch history:");
+        for record in &state.history {
+            let patches = match record.patches.is_empty() {
+                true => "re-signed".to_string(),
+                false => record.patches.join(", "),
+            };
+            println!(
+                "    {} quest_emu {}: {patches} (signer {})",
+                record.timestamp, record.tool_version, record.signer_sha256
+            );
+        }
+    }
+
+    match info.abis.is_empty() {
+        true => println!("ABIs: none"),
+        false => println!("ABIs: {}", info.abis.join(", ")),
+    }
+
+    if !manifest.permissions.is_empty() {
+        println!("Permissions:");
+        for permission in &manifest.permissions {
+            println!("    {permission}");
+        }
+    }
+
+    if !info.native_libs.is_empty() {
+        println!("Native libraries:");
+        for lib in &info.native_libs {
+            println!("    {} ({})", lib.path, format_size(lib.size));
+        }
+    }
+}
+
+fn print_verify_report(report: &VerifyReport) {
+    if report.schemes.is_empty() {
+        println!("{} No v1, v2 or v3 signature found", "FAIL".red());
+    }
+    let has_newer = report
+        .schemes
+        .iter()
+        .any(|s| s.scheme != SignatureScheme::V1);
+    for scheme in &report.schemes {
+        match &scheme.result {
+            Ok(signers) => {
+                println!("{} {} signature", "OK".green(), scheme.scheme);
+                for signer in signers {
+                    println!("    Signer SHA-256: {}", signer.cert_sha256);
+                    println!("    Algorithm: {}", signer.algorithm);
+                }
             }
+            // Android ignores the v1 signature when there is a newer one
+            Err(e) if scheme.scheme == SignatureScheme::V1 && has_newer => println!(
+                "{} {} signature, ignored since there is a newer one: {e}",
+                "NOTE".yellow(),
+                scheme.scheme
+            ),
+            Err(e) => println!("{} {} signature: {e}", "FAIL".red(), scheme.scheme),
         }
     }
-    let mut axml_bytes = Vec::new();
-    {
-        let mut axml_writer = AxmlWriter::new(&mut axml_bytes);
-        let mut xml_reader = xml::EventReader::from_str(&xml_str);
-        xml_to_axml(&mut axml_writer, &mut xml_reader).map_err(|a| color_eyre::eyre::eyre!(a))?;
-        axml_writer
-            .finish()
-            .map_err(|a| color_eyre::eyre::eyre!(a))?;
+
+    match report.misaligned.is_empty() {
+        true => println!("{} zip alignment", "OK".green()),
+        false => {
+            println!("{} zip alignment", "FAIL".red());
+            for entry in &report.misaligned {
+                println!(
+                    "    {} at offset {} is not {} byte aligned",
+                    entry.name, entry.data_offset, entry.required_alignment
+                );
+            }
+        }
     }
-    Ok(axml_bytes)
 }
@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use owo_colors::OwoColorize;
+
+use crate::{
+    adb, app,
+    backup::{self, BackupManifest},
+    commands::{Command, GlobalContext},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct AppArgs {
+    #[command(subcommand)]
+    action: AppAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AppAction {
+    /// Start an installed app with its launcher or VR activity
+    Launch {
+        /// Package name, e.g. com.beatgames.beatsaber
+        package: String,
+    },
+    /// Force-stop every process of an app
+    Stop { package: String },
+    /// Delete the data and cache of an app
+    Clear { package: String },
+    /// Uninstall an app
+    Uninstall {
+        package: String,
+
+        /// Keep the app's data and cache, so reinstalling it keeps its state
+        #[arg(long, default_value_t = false)]
+        keep_data: bool,
+    },
+    /// List the installed third-party apps with their versions
+    List,
+    /// Stop an app and copy its data, OBB and, on rooted images or for debuggable apps,
+    /// internal data folders into a new folder
+    Backup {
+        package: String,
+        /// Folder for the backup, must be empty or not exist yet
+        dir: PathBuf,
+    },
+    /// Stop an app and replace its folders on the device with the ones of a backup
+    Restore {
+        /// Folder created by `app backup`
+        dir: PathBuf,
+    },
+}
+
+impl Command for AppArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let runner = &*ctx.runner;
+        let serial = adb::device(runner)?;
+
+        let result = match self.action {
+            AppAction::Launch { package } => {
+                let component = app::launch(runner, &serial, &package)?;
+                ctx.info(format_args!("Launched {}", component.green()));
+                serde_json::json!({
+                    "serial": serial,
+                    "package": package,
+                    "activity": component,
+                })
+            }
+            AppAction::Stop { package } => {
+                app::stop(runner, &serial, &package)?;
+                ctx.info(format_args!("Stopped {package}"));
+                serde_json::json!({ "serial": serial, "package": package })
+            }
+            AppAction::Clear { package } => {
+                app::clear(runner, &serial, &package)?;
+                ctx.info(format_args!("Cleared the data of {package}"));
+                serde_json::json!({ "serial": serial, "package": package })
+            }
+            AppAction::Uninstall { package, keep_data } => {
+                app::uninstall(runner, &serial, &package, keep_data)?;
+                match keep_data {
+                    true => ctx.info(format_args!("Uninstalled {package}, keeping its data")),
+                    false => ctx.info(format_args!("Uninstalled {package}")),
+                }
+                serde_json::json!({
+                    "serial": serial,
+                    "package": package,
+                    "kept_data": keep_data,
+                })
+            }
+            AppAction::List => {
+                let packages = app::list(runner, &serial)?;
+                if packages.is_empty() {
+                    ctx.info("No third-party apps installed");
+                }
+                for package in &packages {
+                    let version =
+                        version_text(package.version_name.as_deref(), package.version_code);
+                    ctx.info(format_args!("{} {}", package.name, version.dimmed()));
+                }
+                serde_json::json!({ "serial": serial, "packages": packages })
+            }
+            AppAction::Backup { package, dir } => {
+                let manifest = backup::backup(runner, &serial, &package, &dir, &*ctx.reporter)?;
+                for skipped in &manifest.skipped {
+                    ctx.info(format_args!("Not backed up: {}", skipped.reason).yellow());
+                }
+                ctx.info(format_args!("Backed up {package} to {}", dir.display()));
+                backup_json(&serial, &dir, &manifest)
+            }
+            AppAction::Restore { dir } => {
+                let manifest = backup::BackupManifest::load(&dir)?;
+                let installed = app::package(runner, &serial, &manifest.package)?;
+                if installed.version_code != manifest.version_code {
+                    ctx.info(
+                        format_args!(
+                            "The backup is of version {}, {} is installed",
+                            version_text(manifest.version_name.as_deref(), manifest.version_code),
+                            version_text(installed.version_name.as_deref(), installed.version_code),
+                        )
+                        .yellow(),
+                    );
+                }
+                let manifest = backup::restore(runner, &serial, &dir, &*ctx.reporter)?;
+                ctx.info(format_args!(
+                    "Restored {} from {}",
+                    manifest.package,
+                    dir.display()
+                ));
+                backup_json(&serial, &dir, &manifest)
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// `1.40.8_7379 (1130)`, or whichever of the two is known
+fn version_text(name: Option<&str>, code: Option<u64>) -> String {
+    match (name, code) {
+        (Some(name), Some(code)) => format!("{name} ({code})"),
+        (Some(name), None) => name.to_string(),
+        (None, Some(code)) => code.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+fn backup_json(serial: &str, dir: &Path, manifest: &BackupManifest) -> serde_json::Value {
+    serde_json::json!({
+        "serial": serial,
+        "dir": dir,
+        "backup": manifest,
+    })
+}
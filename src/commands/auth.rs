@@ -0,0 +1,126 @@
+use std::io::{IsTerminal, Read};
+
+use color_eyre::eyre::{Context, bail};
+use owo_colors::OwoColorize;
+
+use crate::{
+    auth::{
+        TOKEN_ENV, TokenSource, delete_token, find_token, mask_token, save_token, validate_token,
+    },
+    commands::{Command, GlobalContext},
+    constants::auth_token_path,
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct AuthArgs {
+    #[command(subcommand)]
+    action: AuthAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AuthAction {
+    /// Store the Oculus auth token used by `apk download`.
+    /// The token is read from stdin if it is piped in, otherwise it is prompted for
+    Login {
+        /// Store the token without checking it with Oculus Graph
+        #[arg(long, default_value_t = false)]
+        no_validate: bool,
+    },
+    /// Show which token is used and whether Oculus accepts it
+    Status,
+    /// Remove the stored token
+    Logout,
+}
+
+impl Command for AuthArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let result = match self.action {
+            AuthAction::Login { no_validate } => {
+                let token = read_token(ctx)?;
+                if token.is_empty() {
+                    bail!("No token given");
+                }
+
+                if !no_validate {
+                    let valid = validate_token(&token).context(
+                        "Failed to validate token, pass --no-validate to store it anyway",
+                    )?;
+                    if !valid {
+                        bail!("Oculus rejected the token, make sure it is copied completely");
+                    }
+                }
+
+                save_token(&token)?;
+                ctx.info(format_args!(
+                    "Stored token in {}",
+                    auth_token_path().display()
+                ));
+                serde_json::json!({
+                    "path": auth_token_path(),
+                    "validated": !no_validate,
+                })
+            }
+            AuthAction::Status => {
+                let Some((token, source)) = find_token(None)? else {
+                    ctx.info("Not logged in".yellow());
+                    ctx.info(format_args!(
+                        "Run `quest_emu auth login` or set {TOKEN_ENV}"
+                    ));
+                    return Ok(serde_json::json!({ "logged_in": false }));
+                };
+
+                ctx.info(format_args!("Token: {}", mask_token(&token)));
+                ctx.info(format_args!("Source: {source}"));
+                let validation = validate_token(&token);
+                match &validation {
+                    Ok(true) => ctx.info("Valid".green()),
+                    Ok(false) => {
+                        ctx.info("Rejected by Oculus, run `quest_emu auth login` again".red())
+                    }
+                    Err(e) => ctx.info(format_args!("{} {e}", "Unable to validate:".yellow())),
+                }
+                serde_json::json!({
+                    "logged_in": true,
+                    "token": mask_token(&token),
+                    "source": source.to_string(),
+                    "valid": validation.as_ref().ok(),
+                    "validation_error": validation.as_ref().err().map(ToString::to_string),
+                })
+            }
+            AuthAction::Logout => {
+                let removed = delete_token()?;
+                match removed {
+                    true => ctx.info(format_args!("Removed {}", auth_token_path().display())),
+                    false => ctx.info("No stored token"),
+                }
+                let env_token = matches!(find_token(None)?, Some((_, TokenSource::Env)));
+                if env_token {
+                    ctx.info(format_args!("{TOKEN_ENV} is still set and will be used"));
+                }
+                serde_json::json!({ "removed": removed, "env_token": env_token })
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// Reads the token from stdin when piped, so it stays out of shell history and process listings
+fn read_token(ctx: &GlobalContext) -> color_eyre::Result<String> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut token = String::new();
+        stdin
+            .lock()
+            .read_to_string(&mut token)
+            .context("Failed to read token from stdin")?;
+        return Ok(token.trim().to_string());
+    }
+
+    if ctx.yes {
+        bail!("Cannot prompt for the token with --yes, pipe it in instead");
+    }
+    let token = dialoguer::Password::new()
+        .with_prompt("Oculus auth token (from the oculus.com cookies in your browser devtools)")
+        .interact()?;
+    Ok(token.trim().to_string())
+}
@@ -0,0 +1,138 @@
+use owo_colors::OwoColorize;
+
+use crate::{
+    commands::{Command, GlobalContext},
+    config::{self, CONFIG_FILE, KEYS},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the value of a setting
+    Get {
+        /// Name of the setting, e.g. avd_name or gpu. `config list` shows all of them
+        key: String,
+    },
+    /// Store a setting in the user config file, or in the profile given with --profile
+    Set {
+        /// Name of the setting, e.g. avd_name or gpu. `config list` shows all of them
+        key: String,
+        /// The value, emulator_args are separated by spaces
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+        /// Store the setting in the project's quest_emu.toml instead,
+        /// created in the current directory if there is none
+        #[arg(long, default_value_t = false)]
+        project: bool,
+    },
+    /// Show all settings and where their values come from
+    List,
+}
+
+impl Command for ConfigArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let config = &ctx.config;
+        let result = match self.action {
+            ConfigAction::Get { key } => match config.get(&key)? {
+                Some((value, source)) => {
+                    if !ctx.json {
+                        println!("{}", format_value(value));
+                    }
+                    serde_json::json!({ "key": key, "value": value, "source": source })
+                }
+                // Unset settings print nothing, so scripts can test for an empty value
+                None => serde_json::json!({ "key": key, "value": null, "source": null }),
+            },
+            ConfigAction::Set {
+                key,
+                value,
+                project,
+            } => {
+                let path = match project {
+                    true => match &config.project_file {
+                        Some(path) => path.clone(),
+                        None => std::env::current_dir()?.join(CONFIG_FILE),
+                    },
+                    false => config.user_file.clone(),
+                };
+                config::set(&path, config.profile.as_deref(), &key, &value)?;
+                match &config.profile {
+                    Some(profile) => ctx.info(format_args!(
+                        "Set {key} in [profiles.{profile}] of {}",
+                        path.display()
+                    )),
+                    None => ctx.info(format_args!("Set {key} in {}", path.display())),
+                }
+                serde_json::json!({ "key": key, "path": path, "profile": config.profile })
+            }
+            ConfigAction::List => {
+                let mut settings = serde_json::Map::new();
+                for key in KEYS {
+                    let setting = match config.get(key)? {
+                        Some((value, source)) => {
+                            ctx.info(format_args!(
+                                "{key} = {} {}",
+                                format_value(value),
+                                format!("({source})").dimmed()
+                            ));
+                            serde_json::json!({ "value": value, "source": source })
+                        }
+                        None => {
+                            ctx.info(format_args!("{key} {}", "(unset)".dimmed()));
+                            serde_json::json!({ "value": null, "source": null })
+                        }
+                    };
+                    settings.insert(key.to_string(), setting);
+                }
+
+                ctx.info("");
+                ctx.info(format_args!("User file: {}", config.user_file.display()));
+                match &config.project_file {
+                    Some(path) => ctx.info(format_args!("Project file: {}", path.display())),
+                    None => ctx.info(format_args!(
+                        "No {CONFIG_FILE} in this directory or its parents"
+                    )),
+                }
+                if !config.profiles.is_empty() {
+                    ctx.info(format_args!("Profiles: {}", config.profiles.join(", ")));
+                }
+                match &config.profile {
+                    Some(profile) if config.profile_found => {
+                        ctx.info(format_args!("Using profile {profile}"))
+                    }
+                    Some(profile) => ctx.info(format_args!(
+                        "Profile {profile} is not defined, create it with `config set --profile {profile}`"
+                    )),
+                    None => {}
+                }
+
+                serde_json::json!({
+                    "settings": settings,
+                    "profile": config.profile,
+                    "profiles": config.profiles,
+                    "user_file": config.user_file,
+                    "project_file": config.project_file,
+                })
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// Strings without quotes and arrays separated by spaces, like they are passed to `config set`
+fn format_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(format_value)
+            .collect::<Vec<_>>()
+            .join(" "),
+        value => value.to_string(),
+    }
+}
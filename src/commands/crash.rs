@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use owo_colors::OwoColorize;
+
+use crate::{
+    adb,
+    commands::{Command, GlobalContext},
+    tombstone::{self, SymbolStatus, SymbolicatedFrame, Symbols, Tombstone},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct CrashArgs {
+    /// Unstripped libraries, or folders with them, e.g. the build output of a mod.
+    /// Frames are matched by library file name and build ID. Can be given several times
+    #[arg(long, short)]
+    symbols: Vec<PathBuf>,
+
+    /// Number of tombstones to show, newest first
+    #[arg(long, short = 'n', default_value_t = 1)]
+    count: usize,
+
+    /// Read tombstones from these files instead of the device
+    #[arg(long, conflicts_with_all = ["count", "save"])]
+    file: Vec<PathBuf>,
+
+    /// Save the tombstones pulled from the device to this folder
+    #[arg(long)]
+    save: Option<PathBuf>,
+}
+
+impl Command for CrashArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let symbols = Symbols::load(&self.symbols)?;
+        if symbols.is_empty() {
+            ctx.info(
+                "Pass --symbols with unstripped libraries to see function names and source lines",
+            );
+        }
+
+        let mut tombstones = Vec::new();
+        if self.file.is_empty() {
+            let serial = adb::device(&*ctx.runner)?;
+            let names = tombstone::list(&*ctx.runner, &serial)?;
+            if names.is_empty() {
+                ctx.info("No tombstones on the device");
+            }
+            for name in names.into_iter().take(self.count) {
+                let text = tombstone::pull(&*ctx.runner, &serial, &name)?;
+                if let Some(dir) = &self.save {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {}", dir.display()))?;
+                    std::fs::write(dir.join(&name), &text)
+                        .with_context(|| format!("Failed to save {name}"))?;
+                }
+                tombstones.push((name, text));
+            }
+        } else {
+            for path in &self.file {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                tombstones.push((path.display().to_string(), text));
+            }
+        }
+
+        let mut results = Vec::new();
+        for (name, text) in tombstones {
+            let mut tombstone =
+                Tombstone::parse(&text).with_context(|| format!("Failed to parse {name}"))?;
+            tombstone.name = Some(name);
+            let frames = tombstone
+                .frames
+                .iter()
+                .map(|frame| symbols.symbolicate(frame))
+                .collect::<Result<Vec<_>, _>>()?;
+            print_tombstone(ctx, &tombstone, &frames);
+
+            let mut result = serde_json::to_value(&tombstone)?;
+            result["frames"] = serde_json::to_value(&frames)?;
+            results.push(result);
+        }
+        Ok(serde_json::json!({ "tombstones": results }))
+    }
+}
+
+fn print_tombstone(ctx: &GlobalContext, tombstone: &Tombstone, frames: &[SymbolicatedFrame]) {
+    let unknown = || "unknown".to_string();
+    ctx.info(
+        format!(
+            "{}: {} (pid {}, thread {})",
+            tombstone.name.clone().unwrap_or_else(unknown),
+            tombstone.process.clone().unwrap_or_else(unknown),
+            tombstone.pid.map_or_else(unknown, |pid| pid.to_string()),
+            tombstone.thread.clone().unwrap_or_else(unknown),
+        )
+        .bold(),
+    );
+    if let Some(signal) = &tombstone.signal {
+        ctx.info(signal.red());
+    }
+    if let Some(message) = &tombstone.abort_message {
+        ctx.info(format_args!("Abort message: {}", message.red()));
+    }
+
+    for frame in frames {
+        let mut line = format!(
+            "  #{:02} pc {:016x}  {}",
+            frame.frame.index,
+            frame.frame.pc,
+            frame.frame.library()
+        );
+        match frame.status {
+            SymbolStatus::Symbolicated => {}
+            SymbolStatus::MissingLibrary | SymbolStatus::NoDebugInfo => {
+                if let Some(symbol) = &frame.frame.symbol {
+                    line.push_str(&format!(" ({symbol})"));
+                }
+            }
+            SymbolStatus::BuildIdMismatch => {
+                line.push_str(&" (symbols are from a different build)".yellow().to_string());
+            }
+        }
+        ctx.info(line);
+
+        for (index, location) in frame.locations.iter().enumerate() {
+            let function = location.function.as_deref().unwrap_or("??");
+            let source = match (&location.file, location.line) {
+                (Some(file), Some(line)) => format!(" at {file}:{line}"),
+                (Some(file), None) => format!(" at {file}"),
+                _ => String::new(),
+            };
+            let inlined = if index + 1 < frame.locations.len() {
+                "inlined "
+            } else {
+                ""
+            };
+            ctx.info(format_args!(
+                "        {inlined}{}{}",
+                function.green(),
+                source.dimmed()
+            ));
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use color_eyre::eyre::{Context, bail};
+use color_eyre::eyre::{ContextCompat, bail};
 
 use crate::{
+    avd::{self, AvdSpec},
     commands::{Command, GlobalContext},
-    constants::{self, adb_path, avd_path},
+    sdk,
 };
 
 #[derive(clap::Parser)]
@@ -15,49 +16,53 @@ pub struct CreateArgs {
     #[arg(long = "overwrite", default_value_t = false)]
     overwrite_avd: bool,
 
-    /// Name of AVD (Android Virtual Device)
-    #[arg(long, default_value_t = constants::DEFAULT_AVD_NAME.to_string())]
-    name: String,
-
-    /// Screen size for the AVD (Android Virtual Device), e.g. "1920x1080"
-    #[arg(long = "screen-size", default_value = "1920x1080")]
-    screen_size: String,
-
-    /// Limit the emulator FPS to save CPU/GPU resources (0 = unlimited)
-    #[arg(long = "fps", default_value_t = 60)]
-    fps_limit: u32,
-
-    /// System image of AVD (Android Virtual Device)
-    #[arg(long = "image", default_value_t = constants::DEFAULT_AVD_IMAGE.to_string())]
-    system_image: String,
+    /// Name of AVD (Android Virtual Device).
+    /// Defaults to the avd_name setting, "android13desktop" if unset
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Screen size for the AVD (Android Virtual Device), e.g. "1920x1080".
+    /// Defaults to the screen_size setting, "1920x1080" if unset
+    #[arg(long = "screen-size")]
+    screen_size: Option<String>,
+
+    /// Limit the emulator FPS to save CPU/GPU resources (0 = unlimited).
+    /// Defaults to the fps setting, 60 if unset
+    #[arg(long = "fps")]
+    fps_limit: Option<u32>,
+
+    /// System image of AVD (Android Virtual Device).
+    /// Defaults to the image setting
+    #[arg(long = "image")]
+    system_image: Option<String>,
 }
 
 impl Command for CreateArgs {
-    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<()> {
-        let android_emu_image_installed = constants::emulator_path().exists()
-            && adb_path()
-                .exists()
-            && constants::android_sdk_path()
-                .join(self.system_image.replace(";", "/"))
-                .exists();
-
-        if !android_emu_image_installed {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let name = self.name.unwrap_or_else(|| ctx.config.avd_name.clone());
+        let system_image = self
+            .system_image
+            .unwrap_or_else(|| ctx.config.image.clone());
+        let screen_size = self
+            .screen_size
+            .unwrap_or_else(|| ctx.config.screen_size.clone());
+        let fps_limit = self.fps_limit.unwrap_or(ctx.config.fps);
+
+        if !sdk::is_image_installed(&system_image) {
             bail!(
                 "The specified system image '{}' is not installed. Please run the 'setup' command to install the Android Emulator and system image.",
-                self.system_image
+                system_image
             );
         }
 
-        let mut avd_folder_name = self.name.clone();
-        avd_folder_name.push_str(".avd");
-        if avd_path().join(&avd_folder_name).exists() {
+        if avd::exists(&name) {
             let overwrite_avd = (ctx.yes || self.overwrite_avd)
                 || dialoguer::Confirm::new()
                     .with_prompt("An existing AVD (Android Virtual Device) was found, do you want to delete this?")
                     .interact()?;
 
             match overwrite_avd {
-                true => delete_emulator(&self.name)?,
+                true => avd::delete(&*ctx.runner, &name)?,
                 false => bail!("An existing AVD (Android Virtual Device) was found!"),
             }
         }
@@ -67,84 +72,42 @@ impl Command for CreateArgs {
             || dialoguer::Confirm::new()
                 .with_prompt("Do you want to create an AVD (Android Virtual Device)?")
                 .interact()?;
-        if create_avd {
-            create_emulator_with_screen_size(
-                &self.name,
-                &self.system_image,
-                &self.screen_size,
-                self.fps_limit,
-            )?;
-            println!(
-                "Run the emulator using the '{} start --name {}'",
-                std::env::var("CARGO_BIN_NAME").unwrap_or_else(|_| "quest_emu".to_string()),
-                self.name
-            );
+        if !create_avd {
+            return Ok(serde_json::json!({ "avd": name, "created": false }));
         }
-
-        Ok(())
-    }
-}
-
-pub fn create_emulator(name: &str, image: &str) -> Result<(), color_eyre::eyre::Error> {
-    let status = std::process::Command::new(constants::avdmanager_path())
-        .arg("create")
-        .arg("avd")
-        .arg("-n")
-        .arg(name)
-        .arg("-k")
-        .arg(image)
-        .status()
-        .context("Failed to create AVD (Android Virtual Device)")?;
-    if !status.success() {
-        bail!("avdmanager exited with status: {}", status);
-    };
-    Ok(())
-}
-
-/// Create an emulator with a specific screen size (e.g. "1080x1920")
-pub fn create_emulator_with_screen_size(
-    name: &str,
-    image: &str,
-    screen_size: &str,
-    fps: u32,
-) -> Result<(), color_eyre::eyre::Error> {
-    // Create the AVD first
-    create_emulator(name, image)?;
-    // Set the screen size in config.ini
-    let avd_dir = avd_path().join(format!("{}.avd", name));
-    let config_path = avd_dir.join("config.ini");
-    if config_path.exists() {
-        use std::fs;
-        use std::io::Write;
-        let mut config = fs::OpenOptions::new().append(true).open(&config_path)?;
-        writeln!(
-            config,
-            "hw.lcd.width={}",
-            screen_size.split('x').next().unwrap_or("1080")
-        )?;
-        writeln!(
-            config,
-            "hw.lcd.height={}",
-            screen_size.split('x').nth(1).unwrap_or("1920")
+        let (width, height) = screen_size
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .with_context(|| {
+                format!(
+                    "Invalid screen size '{}', expected e.g. \"1920x1080\"",
+                    screen_size
+                )
+            })?;
+        let avd_dir = avd::create(
+            &*ctx.runner,
+            &AvdSpec {
+                name: name.clone(),
+                image: system_image.clone(),
+                width,
+                height,
+                fps: fps_limit,
+            },
         )?;
-        writeln!(config, "hw.lcd.vsync={fps}")?;
-        writeln!(config, "hw.gpu.enabled=yes")?;
-        writeln!(config, "hw.gpu.mode=auto")?;
-    }
-    Ok(())
-}
-
-pub fn delete_emulator(name: &str) -> Result<(), color_eyre::eyre::Error> {
-    let status = std::process::Command::new(constants::avdmanager_path())
-        .arg("delete")
-        .arg("avd")
-        .arg("-n")
-        .arg(name)
-        .status()
-        .context("Failed to delete AVD (Android Virtual Device)")?;
-
-    if !status.success() {
-        bail!("avdmanager exited with status: {}", status);
+        ctx.info(format_args!(
+            "Run the emulator using the '{} start --name {}'",
+            std::env::var("CARGO_BIN_NAME").unwrap_or_else(|_| "quest_emu".to_string()),
+            name
+        ));
+
+        Ok(serde_json::json!({
+            "avd": name,
+            "created": true,
+            "path": avd_dir,
+            "image": system_image,
+            "width": width,
+            "height": height,
+            "fps": fps_limit,
+        }))
     }
-    Ok(())
 }
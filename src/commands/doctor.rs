@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Context;
+use owo_colors::OwoColorize;
+
+use crate::{
+    adb::{self, AbiCompatibility, DeviceAbis},
+    apk::info::read_apk_info,
+    commands::{Command, GlobalContext},
+    constants::{adb_path, android_sdk_path, avdmanager_path, emulator_path, sdkmanager_path},
+    error::Error,
+};
+
+/// The ABI Quest APKs ship, checked when no APK is given
+const QUEST_ABI: &str = "arm64-v8a";
+
+#[derive(clap::Parser, Debug)]
+pub struct DoctorArgs {
+    /// APK to check against the ABIs of the connected device.
+    /// Defaults to checking whether the device can run Quest (arm64-v8a) apps
+    #[arg(long)]
+    apk: Option<PathBuf>,
+}
+
+impl Command for DoctorArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        ctx.info(format_args!(
+            "Android SDK path: {}",
+            android_sdk_path().display()
+        ));
+        let mut tools = serde_json::Map::new();
+        for (name, path) in [
+            ("sdkmanager", sdkmanager_path()),
+            ("avdmanager", avdmanager_path()),
+            ("emulator", emulator_path()),
+            ("adb", adb_path()),
+        ] {
+            match path.exists() {
+                true => ctx.info(format_args!("{} {name}: {}", "OK".green(), path.display())),
+                false => ctx.info(format_args!(
+                    "{} {name} not found, run `setup`",
+                    "MISSING".red()
+                )),
+            }
+            tools.insert(
+                name.to_string(),
+                serde_json::json!({ "path": path, "found": path.exists() }),
+            );
+        }
+        let mut result = serde_json::json!({
+            "sdk_path": android_sdk_path(),
+            "tools": tools,
+            "device": null,
+        });
+
+        if !adb_path().exists() {
+            return Ok(result);
+        }
+
+        let serial = match adb::device(&*ctx.runner) {
+            Ok(serial) => serial,
+            Err(Error::NoDevice) => {
+                ctx.info("No device connected, start the emulator with `start`");
+                return Ok(result);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        ctx.info(format_args!("Device: {serial}"));
+
+        let device =
+            DeviceAbis::query(&*ctx.runner, &serial).context("Failed to query device ABIs")?;
+        ctx.info(format_args!("Device ABIs: {}", device.abilist.join(", ")));
+        ctx.info(format_args!(
+            "Native bridge: {}",
+            device.native_bridge.as_deref().unwrap_or("disabled")
+        ));
+
+        let apk_abis = match &self.apk {
+            Some(apk) => read_apk_info(apk)?.abis,
+            None => vec![QUEST_ABI.to_string()],
+        };
+        let compatibility = AbiCompatibility::check(&apk_abis, &device);
+        let diagnosis = compatibility.diagnosis(&device);
+        if !compatibility.is_compatible() {
+            ctx.info(format_args!("{} {diagnosis}", "FAIL".red()));
+            return Err(color_eyre::Report::new(Error::AbiIncompatible(diagnosis))
+                .wrap_err("Device cannot run the APK's native libraries"));
+        }
+        ctx.info(format_args!("{} {diagnosis}", "OK".green()));
+
+        result["device"] = serde_json::json!({
+            "serial": serial,
+            "abis": device.abilist,
+            "native_bridge": device.native_bridge,
+            "apk_abis": apk_abis,
+            "diagnosis": diagnosis,
+        });
+        Ok(result)
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::PathBuf,
+};
+
+use color_eyre::eyre::Context;
+use owo_colors::OwoColorize;
+
+use crate::{
+    adb,
+    commands::{Command, GlobalContext},
+    logcat::{self, Highlight, LogEntry, LogFilter, Priority},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct LogcatArgs {
+    /// Only show the log of this package's processes, e.g. com.beatgames.beatsaber.
+    /// Its native crashes are shown whatever their tag and level
+    package: Option<String>,
+
+    /// Only show entries with this tag, can be given several times
+    #[arg(long, short)]
+    tag: Vec<String>,
+
+    /// Only show entries of at least this level
+    #[arg(long, short, value_enum)]
+    level: Option<Priority>,
+
+    /// Also write the shown entries to this file, without colors
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+
+    /// Print the current log and exit instead of following it
+    #[arg(long, short, default_value_t = false)]
+    dump: bool,
+}
+
+impl Command for LogcatArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let runner = &*ctx.runner;
+        let serial = adb::device(runner)?;
+
+        let pids = match &self.package {
+            Some(package) => logcat::pids(runner, &serial, package)?,
+            None => Vec::new(),
+        };
+        match &self.package {
+            Some(package) if pids.is_empty() => ctx.info(format_args!(
+                "{package} is not running, its log is shown once it starts"
+            )),
+            Some(package) => ctx.info(format_args!(
+                "Showing the log of {package} (PID {})",
+                pids.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            None => {}
+        }
+        let mut filter = LogFilter::new(self.package.clone(), pids.clone());
+        filter.tags = self.tag;
+        filter.min_priority = self.level;
+
+        let mut file = match &self.output {
+            Some(path) => {
+                Some(LineWriter::new(File::create(path).with_context(|| {
+                    format!("Failed to create {}", path.display())
+                })?))
+            }
+            None => None,
+        };
+        let mut write_error = None;
+        let mut shown = 0;
+        let mut crashes = Vec::new();
+        logcat::stream(
+            runner,
+            &logcat::logcat_command(&serial, self.dump),
+            &mut |entry| {
+                for entry in filter.filter(entry) {
+                    shown += 1;
+                    crashes.extend(crash_json(&entry));
+                    let text = entry.format();
+                    if let Some(file) = &mut file
+                        && write_error.is_none()
+                    {
+                        write_error = writeln!(file, "{text}").err();
+                    }
+                    ctx.info(colorize(&entry, &text));
+                }
+            },
+        )?;
+        if let (Some(e), Some(path)) = (write_error, &self.output) {
+            return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+        }
+
+        Ok(serde_json::json!({
+            "serial": serial,
+            "package": self.package,
+            "pids": pids,
+            "entries": shown,
+            "crashes": crashes,
+            "output": self.output,
+        }))
+    }
+}
+
+/// A crash for the result, from the entry naming the crashed process
+fn crash_json(entry: &LogEntry) -> Option<serde_json::Value> {
+    let names_process = match entry.highlight()? {
+        // pid: 4321, tid: 4390, name: UnityMain  >>> com.beatgames.beatsaber <<<
+        Highlight::NativeCrash => entry.message.starts_with("pid: "),
+        // FATAL EXCEPTION: main
+        Highlight::JavaCrash => entry.message.starts_with("FATAL EXCEPTION"),
+        Highlight::Exception => false,
+    };
+    names_process.then(|| {
+        serde_json::json!({
+            "kind": entry.highlight(),
+            "pid": entry.pid,
+            "message": entry.message,
+        })
+    })
+}
+
+fn colorize(entry: &LogEntry, text: &str) -> String {
+    match (entry.highlight(), entry.priority) {
+        (Some(Highlight::NativeCrash | Highlight::JavaCrash), _) => text.red().bold().to_string(),
+        (Some(Highlight::Exception), _) => text.magenta().to_string(),
+        (None, Priority::Fatal | Priority::Error) => text.red().to_string(),
+        (None, Priority::Warn) => text.yellow().to_string(),
+        (None, Priority::Info) => text.to_string(),
+        (None, Priority::Debug | Priority::Verbose) => text.dimmed().to_string(),
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::bail;
+
+use crate::{
+    commands::{Command, GlobalContext},
+    instances::InstanceState,
+    logs::{self, KEPT_LOGS, MAX_LOG_SIZE, RotatingWriter},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct LogsArgs {
+    /// Serial (e.g. emulator-5556) or AVD name of an emulator started with `start --detach`.
+    /// An AVD name shows its running instance, or its latest log if none is running
+    target: String,
+
+    /// Keep printing new output while the emulator is running
+    #[arg(long, short, default_value_t = false)]
+    follow: bool,
+
+    /// Number of lines to show from the end of the log. Shows all by default
+    #[arg(long, short = 'n')]
+    lines: Option<usize>,
+}
+
+impl Command for LogsArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let state = InstanceState::load()?;
+        let matching: Vec<_> = state
+            .instances
+            .iter()
+            .filter(|instance| instance.matches(&self.target) && instance.log.is_some())
+            .collect();
+        let log = match matching[..] {
+            [instance] => instance
+                .log
+                .clone()
+                .expect("only instances with a log match"),
+            [] => match logs::latest_log(&self.target)? {
+                Some(log) => log,
+                None => bail!(
+                    "No log for {}, logs are only written with `start --detach`",
+                    self.target
+                ),
+            },
+            _ => bail!(
+                "{} emulators of {} are running, pass one of their serials: {}",
+                matching.len(),
+                self.target,
+                matching
+                    .iter()
+                    .map(|instance| instance.serial())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let (avd, serial) = logs::parse_log_name(&log).unwrap_or((&self.target, ""));
+        let is_running = |state: &InstanceState| {
+            state
+                .instances
+                .iter()
+                .any(|i| i.avd == avd && i.serial() == serial)
+        };
+        let running = is_running(&state);
+
+        // With --json the lines are part of the result
+        let mut lines = Vec::new();
+        let mut print = |line: &str| match ctx.json {
+            true => lines.push(line.to_string()),
+            false => println!("{line}"),
+        };
+        for line in logs::tail(&log, self.lines.unwrap_or(usize::MAX))? {
+            print(&line);
+        }
+        if self.follow && running {
+            logs::follow(
+                &log,
+                || InstanceState::load().is_ok_and(|state| is_running(&state)),
+                &mut print,
+            )?;
+        }
+        if self.follow || !running {
+            ctx.info(format_args!("No emulator of {avd} is running"));
+        }
+
+        Ok(serde_json::json!({
+            "avd": avd,
+            "serial": serial,
+            "log": log,
+            "running": running && !self.follow,
+            "lines": lines,
+        }))
+    }
+}
+
+/// Appends stdin to a log, rotating it by size. Started by `start --detach` to receive
+/// the output of the emulator, which would otherwise grow its log without limit
+#[derive(clap::Parser, Debug)]
+pub struct LogWriterArgs {
+    log: PathBuf,
+}
+
+impl Command for LogWriterArgs {
+    fn execute(self, _ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let mut writer = RotatingWriter::new(&self.log, MAX_LOG_SIZE, KEPT_LOGS);
+        std::io::copy(&mut std::io::stdin().lock(), &mut writer)?;
+        Ok(serde_json::json!({ "log": self.log }))
+    }
+}
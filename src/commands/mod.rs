@@ -1,14 +1,74 @@
 pub mod apk;
+pub mod app;
+pub mod auth;
+pub mod config;
+pub mod crash;
 pub mod create;
+pub mod doctor;
+pub mod logcat;
+pub mod logs;
 pub mod setup;
 pub mod start;
+pub mod stop;
+
+use std::fmt::Display;
+
+use crate::{
+    config::Config,
+    error::Error,
+    progress::{IndicatifReporter, JsonReporter, ProgressReporter, SilentReporter},
+    runner::ToolRunner,
+};
 
 pub struct GlobalContext {
     pub yes: bool,
+    /// Print the result of the command as JSON on stdout and everything else on stderr
+    pub json: bool,
+    /// Receives the progress of downloads, installs and patching
+    pub reporter: Box<dyn ProgressReporter>,
+    /// Defaults from the config files and environment, used for arguments that are not given
+    pub config: Config,
+    /// Runs adb, avdmanager, sdkmanager and the emulator
+    pub runner: Box<dyn ToolRunner>,
+}
+
+impl GlobalContext {
+    /// Prints a message for humans, on stderr with `--json` so stdout only has the result
+    pub fn info(&self, message: impl Display) {
+        match self.json {
+            true => eprintln!("{message}"),
+            false => println!("{message}"),
+        }
+    }
+}
+
+/// How progress of long running operations is shown
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// Progress bars and messages
+    #[default]
+    Bars,
+    /// No progress output
+    None,
+    /// One JSON event per line on stderr, for frontends wrapping the CLI
+    Json,
+}
+
+impl ProgressFormat {
+    /// The reporter for this format, keeping stdout free for the result with `--json`
+    pub fn reporter(self, json: bool) -> Box<dyn ProgressReporter> {
+        match self {
+            ProgressFormat::Bars if json => Box::new(IndicatifReporter::stderr()),
+            ProgressFormat::Bars => Box::new(IndicatifReporter::new()),
+            ProgressFormat::None => Box::new(SilentReporter),
+            ProgressFormat::Json => Box::new(JsonReporter::stderr()),
+        }
+    }
 }
 
 pub trait Command {
-    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<()>;
+    /// Runs the command and returns its result, printed with `--json`
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value>;
 }
 
 #[derive(clap::Parser)]
@@ -17,21 +77,93 @@ pub enum MainCommand {
     Create(create::CreateArgs),
     /// Start the Android Emulator with a specified AVD
     Start(start::StartArgs),
+    /// Stop emulators launched with `start`
+    Stop(stop::StopArgs),
+    /// Show the output of emulators started with `start --detach`
+    Logs(logs::LogsArgs),
+    /// Writes the output of a detached emulator to its log, started by `start --detach`
+    #[command(hide = true)]
+    LogWriter(logs::LogWriterArgs),
+    /// Show the device log, optionally only of one package, with crashes highlighted
+    Logcat(logcat::LogcatArgs),
+    /// Show native crashes from the device's tombstones, symbolicated with local libraries
+    Crash(crash::CrashArgs),
     /// Commands for patching APKs
     Apk(apk::ApkArgs),
+    /// Launch, stop, clear, uninstall and list apps on the device
+    App(app::AppArgs),
+    /// Manage the Oculus auth token used to download APKs
+    Auth(auth::AuthArgs),
     /// Setup the Android SDK, Emulator, and AVD
     Setup(setup::SetupArgs),
+    /// Show and change the defaults stored in quest_emu.toml
+    Config(config::ConfigArgs),
+    /// Check the SDK installation and whether the connected device can run Quest APKs
+    Doctor(doctor::DoctorArgs),
 }
 
 impl Command for MainCommand {
-    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<()> {
-        match self {
-            MainCommand::Create(args) => args.execute(ctx)?,
-            MainCommand::Apk(args) => args.execute(ctx)?,
-            MainCommand::Start(args) => args.execute(ctx)?,
-            MainCommand::Setup(setup_args) => setup_args.execute(ctx)?,
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        // `config set --profile` creates the profile
+        if !matches!(self, MainCommand::Config(_)) {
+            ctx.config.check_profile()?;
         }
 
-        Ok(())
+        match self {
+            MainCommand::Create(args) => args.execute(ctx),
+            MainCommand::Apk(args) => args.execute(ctx),
+            MainCommand::App(args) => args.execute(ctx),
+            MainCommand::Auth(args) => args.execute(ctx),
+            MainCommand::Start(args) => args.execute(ctx),
+            MainCommand::Stop(args) => args.execute(ctx),
+            MainCommand::Logs(args) => args.execute(ctx),
+            MainCommand::LogWriter(args) => args.execute(ctx),
+            MainCommand::Logcat(args) => args.execute(ctx),
+            MainCommand::Crash(args) => args.execute(ctx),
+            MainCommand::Setup(setup_args) => setup_args.execute(ctx),
+            MainCommand::Config(args) => args.execute(ctx),
+            MainCommand::Doctor(args) => args.execute(ctx),
+        }
     }
 }
+
+/// The JSON document printed for a failed command with `--json`.
+/// `code` is the [`Error::code`] of the first library error in the chain,
+/// "io" for other I/O errors and "other" for everything else
+pub fn error_json(report: &color_eyre::Report) -> serde_json::Value {
+    let error = report.chain().find_map(|e| e.downcast_ref::<Error>());
+    let code = match error {
+        Some(error) => error.code(),
+        None if report.chain().any(|e| e.is::<std::io::Error>()) => "io",
+        None => "other",
+    };
+    let details = match error {
+        Some(Error::VerificationFailed { report, .. }) => serde_json::to_value(report).ok(),
+        Some(Error::MultipleDevices(devices)) => Some(serde_json::json!({ "devices": devices })),
+        Some(Error::DeviceNotFound(serial)) => Some(serde_json::json!({ "serial": serial })),
+        Some(Error::EmulatorExited {
+            serial,
+            status,
+            log,
+            tail,
+        }) => Some(serde_json::json!({
+            "serial": serial,
+            "exit_code": status.code(),
+            "log": log,
+            "tail": tail,
+        })),
+        Some(Error::AppCommandFailed { command, reason }) => Some(serde_json::json!({
+            "command": command,
+            "reason": reason,
+        })),
+        _ => None,
+    };
+    serde_json::json!({
+        "error": {
+            "code": code,
+            "message": report.to_string(),
+            "causes": report.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+            "details": details,
+        }
+    })
+}
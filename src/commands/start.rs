@@ -1,59 +1,381 @@
-use std::process;
+use std::{
+    io::Write,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use color_eyre::eyre::{Context, bail};
-use itertools::Itertools;
 
-use crate::{commands::Command, constants::{self, emulator_path}};
+use crate::{
+    commands::{Command, GlobalContext},
+    emulator::{self, CameraMode, GpuMode, LaunchOptions},
+    error::Error,
+    instances::{Instance, InstanceState},
+    logs,
+    runner::{OutputTarget, ToolCommand, ToolProcess},
+};
+
+/// How long detached emulators are watched for crashes before `start` returns
+const STARTUP_CHECK: Duration = Duration::from_secs(3);
+
+/// Lines of the log shown when a detached emulator exits while starting
+const CRASH_LOG_LINES: usize = 20;
+
+/// `--port`, a console port or `auto`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortArg {
+    Auto,
+    Port(u16),
+}
+
+impl FromStr for PortArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(PortArg::Auto),
+            port => port
+                .parse()
+                .map(PortArg::Port)
+                .map_err(|_| format!("expected a port number or auto, got {port}")),
+        }
+    }
+}
 
 #[derive(clap::Parser, Debug)]
 pub struct StartArgs {
-    /// Name of the AVD to start
-    #[arg(long, default_value_t = constants::DEFAULT_AVD_NAME.to_string())]
-    pub name: String,
+    /// Name of the AVD to start. Defaults to the avd_name setting
+    #[arg(long)]
+    pub name: Option<String>,
 
     /// Start the emulator without loading a snapshot
     #[arg(long, default_value_t = false)]
     pub fresh: bool,
 
-    /// Additional arguments to pass to the emulator
+    /// Run without a window, e.g. on CI. Defaults to the headless setting
+    #[arg(long, default_value_t = false, overrides_with = "window")]
+    pub headless: bool,
+
+    /// Show a window even if the headless setting is on
+    #[arg(long, default_value_t = false, overrides_with = "headless")]
+    pub window: bool,
+
+    /// How to render graphics. Defaults to the gpu setting, chosen by the emulator if unset
+    #[arg(long, value_enum)]
+    pub gpu: Option<GpuMode>,
+
+    /// RAM of the device in MB. Defaults to the memory setting, the AVD's if unset
+    #[arg(long)]
+    pub memory: Option<u32>,
+
+    /// Number of CPU cores. Defaults to the cores setting, the AVD's if unset
+    #[arg(long)]
+    pub cores: Option<u32>,
+
+    /// Console port, an even number from 5554 to 5682, or auto for the first free one.
+    /// adb connects to the next port. Defaults to auto
+    #[arg(long)]
+    pub port: Option<PortArg>,
+
+    /// Number of emulators to start. They share the AVD, so all of them are read-only
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub instances: u16,
+
+    /// Disable audio. Defaults to the no_audio setting
+    #[arg(long, default_value_t = false, overrides_with = "audio")]
+    pub no_audio: bool,
+
+    /// Enable audio even if the no_audio setting is on
+    #[arg(long, default_value_t = false, overrides_with = "no_audio")]
+    pub audio: bool,
+
+    /// Reset the device to its initial state
+    #[arg(long, default_value_t = false)]
+    pub wipe_data: bool,
+
+    /// Leave the AVD unchanged, so several emulators can run it at the same time.
+    /// Implied when the AVD is already running or with --instances
+    #[arg(long, default_value_t = false)]
+    pub read_only: bool,
+
+    /// What the back camera shows. Defaults to the camera setting
+    #[arg(long, value_enum)]
+    pub camera: Option<CameraMode>,
+
+    /// Do not save a snapshot when the emulator exits
+    #[arg(long, default_value_t = false)]
+    pub no_snapshot_save: bool,
+
+    /// Do not pass `-selinux permissive -feature QtRawKeyboardInput`,
+    /// which the desktop image needs. Defaults to the default_flags setting
+    #[arg(long, default_value_t = false, overrides_with = "default_flags")]
+    pub no_default_flags: bool,
+
+    /// Pass the default flags even if the default_flags setting is off
+    #[arg(long, default_value_t = false, overrides_with = "no_default_flags")]
+    pub default_flags: bool,
+
+    /// Return once the emulator started, keeping it running in the background.
+    /// Its output goes to a log file, see `logs`
+    #[arg(long, default_value_t = false)]
+    pub detach: bool,
+
+    /// Additional arguments to pass to the emulator, after the emulator_args setting
     #[arg(last = true)]
     pub args: Vec<String>,
 }
 
 impl Command for StartArgs {
-    fn execute(self, _ctx: &crate::commands::GlobalContext) -> color_eyre::Result<()> {
-        // use emulator @android13desktop -selinux permissive -feature QtRawKeyboardInput
+    fn execute(
+        self,
+        ctx: &crate::commands::GlobalContext,
+    ) -> color_eyre::Result<serde_json::Value> {
+        let config = &ctx.config;
+        let name = self.name.unwrap_or_else(|| config.avd_name.clone());
+        ctx.info(format_args!("Starting emulator with AVD name: {}", name));
 
-        println!("Starting emulator with AVD name: {}", self.name);
+        // The emulator refuses to run an AVD twice unless the other instances are read-only
+        let state = InstanceState::load()?;
+        if let Some(writable) = state
+            .instances
+            .iter()
+            .find(|i| i.avd == name && !i.read_only)
+        {
+            bail!(
+                "{name} is already running writable as {}, stop it or start both with --read-only",
+                writable.serial()
+            );
+        }
+        let shared = self.instances > 1 || state.instances.iter().any(|i| i.avd == name);
+        let ports = match self.port {
+            Some(PortArg::Port(port)) if self.instances > 1 => {
+                bail!("--port {port} starts a single emulator, use --port auto with --instances")
+            }
+            Some(PortArg::Port(port)) => vec![port],
+            Some(PortArg::Auto) | None => {
+                emulator::free_ports(self.instances.into(), &state.ports())?
+            }
+        };
+
+        let options = LaunchOptions {
+            default_flags: flag(self.default_flags, self.no_default_flags)
+                .unwrap_or(config.default_flags),
+            headless: flag(self.headless, self.window).unwrap_or(config.headless),
+            gpu: self.gpu.or(config.gpu),
+            memory: self.memory.or(config.memory),
+            cores: self.cores.or(config.cores),
+            port: None,
+            no_audio: flag(self.no_audio, self.audio).unwrap_or(config.no_audio),
+            wipe_data: self.wipe_data,
+            read_only: self.read_only || shared,
+            camera: self.camera.or(config.camera),
+            no_snapshot_load: self.fresh,
+            no_snapshot_save: self.no_snapshot_save,
+            extra_args: config
+                .emulator_args
+                .iter()
+                .cloned()
+                .chain(self.args)
+                .collect(),
+        };
+
+        let mut started: Vec<(Instance, Box<dyn ToolProcess>)> = Vec::new();
+        for port in ports {
+            let mut command = LaunchOptions {
+                port: Some(port),
+                ..options.clone()
+            }
+            .command(&name)?;
+            ctx.info(command.command_line());
+
+            let log = self
+                .detach
+                .then(|| logs::log_file(&name, &format!("emulator-{port}")));
+            command = match &log {
+                Some(log) => {
+                    logs::rotate(log, logs::MAX_LOG_SIZE, logs::KEPT_LOGS)?;
+                    append_line(
+                        log,
+                        &format!("--- Starting emulator-{port}: {}", command.command_line()),
+                    )?;
+                    command
+                        .stdout(OutputTarget::Pipe(Box::new(log_writer(log)?)))
+                        .detach()
+                }
+                // The emulator logs to stdout, which is reserved for the result with --json
+                None if ctx.json => command.stdout(OutputTarget::Stderr),
+                None => command,
+            };
 
-        let mut command = process::Command::new(emulator_path());
-        command
-            .arg(format!("@{}", self.name))
-            .arg("-selinux")
-            .arg("permissive")
-            .arg("-feature")
-            .arg("QtRawKeyboardInput");
+            let process = match ctx.runner.spawn(&command) {
+                Ok(process) => process,
+                Err(e) => {
+                    for (_, mut process) in started {
+                        let _ = process.kill();
+                    }
+                    return Err(e).context("Failed to start emulator");
+                }
+            };
+            let instance = Instance {
+                avd: name.clone(),
+                port,
+                pid: process.id(),
+                read_only: options.read_only,
+                log,
+            };
+            started.push((instance, process));
+        }
+
+        let instances: Vec<_> = started
+            .iter()
+            .map(|(instance, _)| instance.clone())
+            .collect();
+        InstanceState::add(&instances)?;
+        for instance in &instances {
+            ctx.info(format_args!("Started {}", instance.serial()));
+        }
+        if self.detach {
+            return watch_startup(ctx, &name, started);
+        }
 
-        if self.fresh {
-            command.arg("-no-snapshot-load");
+        let mut results = Vec::new();
+        let mut failures = Vec::new();
+        for (instance, mut process) in started {
+            let status = process.wait().context("Failed to wait for the emulator")?;
+            InstanceState::remove(instance.port)?;
+            if !status.success() {
+                failures.push(format!(
+                    "Emulator {} exited with status: {status}",
+                    instance.serial()
+                ));
+            }
+            let mut result = instance_json(&instance);
+            result["exit_code"] = status.code().into();
+            results.push(result);
+        }
+        if !failures.is_empty() {
+            bail!("{}", failures.join("\n"));
         }
 
-        if !self.args.is_empty() {
-            command.args(self.args);
+        let mut result = serde_json::json!({ "avd": name, "instances": results });
+        // A single instance keeps the shape from before `--instances` existed
+        if let [instance] = &results[..] {
+            result["serial"] = instance["serial"].clone();
+            result["exit_code"] = instance["exit_code"].clone();
         }
+        Ok(result)
+    }
+}
+
+/// A switch given on the command line as a `--x`/`--no-x` pair, `None` if neither was passed.
+/// clap keeps only the last of the pair, so the CLI can turn a setting off again
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
 
-        println!(
-            "{} {}",
-            emulator_path().to_string_lossy(),
-            command.get_args().map(|s| s.display()).join(" ")
-        );
+/// Writes PID files for detached emulators and fails if one of them exits
+/// within [`STARTUP_CHECK`], e.g. because the AVD is broken or the host lacks virtualization
+fn watch_startup(
+    ctx: &GlobalContext,
+    name: &str,
+    started: Vec<(Instance, Box<dyn ToolProcess>)>,
+) -> color_eyre::Result<serde_json::Value> {
+    for (instance, _) in &started {
+        logs::write_pid_file(&instance.serial(), instance.pid)?;
+    }
+    let instances: Vec<_> = started
+        .iter()
+        .map(|(instance, _)| instance_json(instance))
+        .collect();
 
-        let status = command.status().context("Failed to start emulator")?;
+    let deadline = Instant::now() + STARTUP_CHECK;
+    let mut running = started;
+    let mut crashes = Vec::new();
+    while !running.is_empty() && Instant::now() < deadline {
+        let mut still_running = Vec::new();
+        for (instance, mut process) in running {
+            match process.try_wait().context("Failed to check the emulator")? {
+                Some(status) => crashes.push(crashed(&instance, status)?),
+                None => still_running.push((instance, process)),
+            }
+        }
+        running = still_running;
+        if !running.is_empty() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
 
-        if !status.success() {
-            bail!("Emulator exited with status: {}", status);
+    let mut crashes = crashes.into_iter();
+    if let Some(first) = crashes.next() {
+        for other in crashes {
+            ctx.info(other);
         }
+        return Err(first.into());
+    }
 
-        Ok(())
+    for (instance, _) in &running {
+        if let Some(log) = &instance.log {
+            ctx.info(format_args!(
+                "Logging {} to {}, see `quest_emu logs {}`",
+                instance.serial(),
+                log.display(),
+                instance.serial()
+            ));
+        }
     }
+    Ok(serde_json::json!({ "avd": name, "detached": true, "instances": instances }))
+}
+
+/// Forgets an emulator that exited while starting and describes its crash
+fn crashed(instance: &Instance, status: std::process::ExitStatus) -> color_eyre::Result<Error> {
+    let serial = instance.serial();
+    InstanceState::remove(instance.port)?;
+    logs::remove_pid_file(&serial)?;
+    let log = instance
+        .log
+        .clone()
+        .unwrap_or_else(|| logs::log_file(&instance.avd, &serial));
+    Ok(Error::EmulatorExited {
+        tail: logs::tail(&log, CRASH_LOG_LINES)?,
+        serial,
+        status,
+        log,
+    })
+}
+
+/// `quest_emu log-writer`, which appends the output of a detached emulator to its log
+/// and rotates it, for as long as the emulator runs
+fn log_writer(log: &Path) -> color_eyre::Result<ToolCommand> {
+    let exe = std::env::current_exe().context("Failed to find the quest_emu executable")?;
+    Ok(ToolCommand::new("quest_emu", exe)
+        .arg("log-writer")
+        .arg(log)
+        .detach())
+}
+
+fn append_line(path: &Path, line: &str) -> color_eyre::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+/// An instance with its serial, as printed with `--json`
+pub fn instance_json(instance: &Instance) -> serde_json::Value {
+    serde_json::json!({
+        "serial": instance.serial(),
+        "avd": instance.avd,
+        "port": instance.port,
+        "pid": instance.pid,
+        "read_only": instance.read_only,
+        "log": instance.log,
+    })
 }
@@ -0,0 +1,40 @@
+use crate::{
+    commands::{Command, GlobalContext, start::instance_json},
+    instances::{self, InstanceState},
+};
+
+#[derive(clap::Parser, Debug)]
+pub struct StopArgs {
+    /// Serials (e.g. emulator-5556) or AVD names of the emulators to stop
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    targets: Vec<String>,
+
+    /// Stop every emulator started by `start`
+    #[arg(long, default_value_t = false)]
+    all: bool,
+}
+
+impl Command for StopArgs {
+    fn execute(self, ctx: &GlobalContext) -> color_eyre::Result<serde_json::Value> {
+        let state = InstanceState::load()?;
+        let stopping = match self.all {
+            true => state.instances,
+            false => instances::find(&state, &self.targets)?,
+        };
+        if stopping.is_empty() {
+            ctx.info("No emulators started by quest_emu are running");
+        }
+
+        let mut stopped = Vec::new();
+        for instance in &stopping {
+            instances::stop(&*ctx.runner, instance)?;
+            ctx.info(format_args!(
+                "Stopped {} ({})",
+                instance.serial(),
+                instance.avd
+            ));
+            stopped.push(instance_json(instance));
+        }
+        Ok(serde_json::json!({ "stopped": stopped }))
+    }
+}
@@ -0,0 +1,457 @@
+//! Persistent defaults from `quest_emu.toml` files.
+//!
+//! Settings are read from the user configuration directory and from the closest
+//! `quest_emu.toml` in the current directory or its parents. Each file can define named
+//! profiles in `[profiles.<name>]` tables that override the file's top-level settings.
+//!
+//! From highest to lowest precedence: command line arguments (applied by the commands),
+//! `QUEST_EMU_*` environment variables, the project file, the user file, built-in defaults.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use toml_edit::DocumentMut;
+
+use crate::{
+    constants::{self, user_config_file},
+    emulator::{CameraMode, GpuMode},
+    error::{Context, Error, bail},
+};
+
+/// Name of configuration files
+pub const CONFIG_FILE: &str = "quest_emu.toml";
+
+/// Prefix of the environment variables overriding settings, e.g. `QUEST_EMU_FPS`
+pub const ENV_PREFIX: &str = "QUEST_EMU_";
+
+/// Environment variable selecting a profile when `--profile` is not given
+pub const PROFILE_ENV: &str = "QUEST_EMU_PROFILE";
+
+/// Names of all settings, in the order they are listed
+pub const KEYS: &[&str] = &[
+    "avd_name",
+    "image",
+    "screen_size",
+    "fps",
+    "graph_app_id",
+    "emulator_args",
+    "default_flags",
+    "headless",
+    "gpu",
+    "memory",
+    "cores",
+    "no_audio",
+    "camera",
+];
+
+/// Settings holding a number
+const NUMBER_KEYS: &[&str] = &["fps", "memory", "cores"];
+
+/// Settings holding true or false
+const BOOL_KEYS: &[&str] = &["default_flags", "headless", "no_audio"];
+
+/// Settings of a single file, profile or environment, unset values fall through
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Name of the AVD to create and start
+    pub avd_name: Option<String>,
+    /// System image of new AVDs
+    pub image: Option<String>,
+    /// Screen size of new AVDs, e.g. "1920x1080"
+    pub screen_size: Option<String>,
+    /// FPS limit of new AVDs, 0 for unlimited
+    pub fps: Option<u32>,
+    /// Oculus Graph ID of the app to download
+    pub graph_app_id: Option<String>,
+    /// Arguments passed to the emulator on every start
+    pub emulator_args: Option<Vec<String>>,
+    /// Whether to pass the flags the desktop image needs, see [`crate::emulator::DEFAULT_FLAGS`]
+    pub default_flags: Option<bool>,
+    /// Start the emulator without a window
+    pub headless: Option<bool>,
+    pub gpu: Option<GpuMode>,
+    /// RAM of the emulator in MB
+    pub memory: Option<u32>,
+    /// CPU cores of the emulator
+    pub cores: Option<u32>,
+    pub no_audio: Option<bool>,
+    pub camera: Option<CameraMode>,
+}
+
+impl Settings {
+    pub fn built_in() -> Self {
+        Self {
+            avd_name: Some(constants::DEFAULT_AVD_NAME.to_string()),
+            image: Some(constants::DEFAULT_AVD_IMAGE.to_string()),
+            screen_size: Some("1920x1080".to_string()),
+            fps: Some(60),
+            graph_app_id: Some(constants::DEFAULT_GRAPH_APP_ID.to_string()),
+            emulator_args: Some(Vec::new()),
+            default_flags: Some(true),
+            headless: Some(false),
+            gpu: None,
+            memory: None,
+            cores: None,
+            no_audio: Some(false),
+            camera: None,
+        }
+    }
+
+    /// Reads the settings from `QUEST_EMU_*` environment variables
+    pub fn from_env() -> crate::Result<Self> {
+        let mut document = DocumentMut::new();
+        for key in KEYS {
+            let variable = env_variable(key);
+            if let Ok(value) = std::env::var(&variable) {
+                let value =
+                    parse_value(key, &value).with_context(|| format!("Invalid {variable}"))?;
+                document[key] = toml_edit::Item::Value(value);
+            }
+        }
+        toml_edit::de::from_document(document).context("Invalid environment variables")
+    }
+
+    /// The settings as a JSON object, unset settings are null
+    fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => unreachable!("settings serialize to an object"),
+        }
+    }
+}
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    BuiltIn,
+    File {
+        path: PathBuf,
+        /// Profile the value is set in, `None` for the top-level settings
+        profile: Option<String>,
+    },
+    Env {
+        variable: String,
+    },
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::BuiltIn => write!(f, "built-in"),
+            Source::File {
+                path,
+                profile: None,
+            } => write!(f, "{}", path.display()),
+            Source::File {
+                path,
+                profile: Some(profile),
+            } => write!(f, "{} [profiles.{profile}]", path.display()),
+            Source::Env { variable } => write!(f, "{variable}"),
+        }
+    }
+}
+
+/// A parsed `quest_emu.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigFile {
+    pub settings: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    /// Reads a config file, a missing file has no settings
+    pub fn load(path: &Path) -> crate::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let document = read_document(path)?;
+        Self::from_document(document).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    fn from_document(mut document: DocumentMut) -> crate::Result<Self> {
+        #[derive(Deserialize)]
+        struct Profiles {
+            profiles: BTreeMap<String, Settings>,
+        }
+
+        // Parsed separately since unknown keys cannot be rejected in flattened structs
+        let profiles = match document.remove("profiles") {
+            Some(item) => {
+                let mut profiles = DocumentMut::new();
+                profiles["profiles"] = item;
+                toml_edit::de::from_document::<Profiles>(profiles)
+                    .map_err(Error::external)?
+                    .profiles
+            }
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            settings: toml_edit::de::from_document(document).map_err(Error::external)?,
+            profiles,
+        })
+    }
+}
+
+/// The settings in effect, merged from all sources
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub avd_name: String,
+    pub image: String,
+    pub screen_size: String,
+    pub fps: u32,
+    pub graph_app_id: String,
+    pub emulator_args: Vec<String>,
+    pub default_flags: bool,
+    pub headless: bool,
+    pub gpu: Option<GpuMode>,
+    pub memory: Option<u32>,
+    pub cores: Option<u32>,
+    pub no_audio: bool,
+    pub camera: Option<CameraMode>,
+    /// The selected profile
+    pub profile: Option<String>,
+    pub user_file: PathBuf,
+    /// The closest `quest_emu.toml` in the current directory or its parents
+    pub project_file: Option<PathBuf>,
+    /// Names of the profiles defined in either file
+    pub profiles: Vec<String>,
+    /// Whether the selected profile is defined in either file
+    pub profile_found: bool,
+    sources: BTreeMap<String, (serde_json::Value, Source)>,
+}
+
+impl Config {
+    /// Loads the user and project files and the environment.
+    /// `profile` defaults to `QUEST_EMU_PROFILE`.
+    pub fn load(profile: Option<&str>) -> crate::Result<Self> {
+        let profile = match profile {
+            Some(profile) => Some(profile.to_string()),
+            None => std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()),
+        };
+        let user_file = user_config_file();
+        let project_file = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| find_project_file(&cwd))
+            .filter(|path| *path != user_file);
+        Self::load_from(user_file, project_file, profile, Settings::from_env()?)
+    }
+
+    /// Merges the given files and environment settings over the built-in defaults
+    pub fn load_from(
+        user_file: PathBuf,
+        project_file: Option<PathBuf>,
+        profile: Option<String>,
+        env: Settings,
+    ) -> crate::Result<Self> {
+        let mut layers = vec![(Source::BuiltIn, Settings::built_in())];
+        let mut profiles = Vec::new();
+        let mut profile_found = false;
+        for path in std::iter::once(&user_file).chain(&project_file) {
+            let mut file = ConfigFile::load(path)?;
+            profiles.extend(file.profiles.keys().cloned());
+            layers.push((
+                Source::File {
+                    path: path.clone(),
+                    profile: None,
+                },
+                file.settings,
+            ));
+            if let Some(settings) = profile.as_ref().and_then(|p| file.profiles.remove(p)) {
+                profile_found = true;
+                layers.push((
+                    Source::File {
+                        path: path.clone(),
+                        profile: profile.clone(),
+                    },
+                    settings,
+                ));
+            }
+        }
+        layers.push((
+            Source::Env {
+                variable: format!("{ENV_PREFIX}*"),
+            },
+            env,
+        ));
+        profiles.sort();
+        profiles.dedup();
+
+        // The highest layer that sets a key wins
+        let mut sources = BTreeMap::new();
+        for (source, settings) in layers {
+            for (key, value) in settings.to_json() {
+                if value.is_null() {
+                    continue;
+                }
+                let source = match &source {
+                    Source::Env { .. } => Source::Env {
+                        variable: env_variable(&key),
+                    },
+                    source => source.clone(),
+                };
+                sources.insert(key, (value, source));
+            }
+        }
+        let merged: Settings = serde_json::from_value(
+            sources
+                .iter()
+                .map(|(key, (value, _))| (key.clone(), value.clone()))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        )?;
+        let built_in = Settings::built_in();
+
+        Ok(Self {
+            avd_name: merged.avd_name.or(built_in.avd_name).unwrap_or_default(),
+            image: merged.image.or(built_in.image).unwrap_or_default(),
+            screen_size: merged
+                .screen_size
+                .or(built_in.screen_size)
+                .unwrap_or_default(),
+            fps: merged.fps.or(built_in.fps).unwrap_or_default(),
+            graph_app_id: merged
+                .graph_app_id
+                .or(built_in.graph_app_id)
+                .unwrap_or_default(),
+            emulator_args: merged
+                .emulator_args
+                .or(built_in.emulator_args)
+                .unwrap_or_default(),
+            default_flags: merged
+                .default_flags
+                .or(built_in.default_flags)
+                .unwrap_or_default(),
+            headless: merged.headless.or(built_in.headless).unwrap_or_default(),
+            gpu: merged.gpu,
+            memory: merged.memory,
+            cores: merged.cores,
+            no_audio: merged.no_audio.or(built_in.no_audio).unwrap_or_default(),
+            camera: merged.camera,
+            profile,
+            user_file,
+            project_file,
+            profiles,
+            profile_found,
+            sources,
+        })
+    }
+
+    /// Fails if the selected profile is not defined, so a typo does not silently use the defaults
+    pub fn check_profile(&self) -> crate::Result<()> {
+        match &self.profile {
+            Some(profile) if !self.profile_found => bail!(
+                "Profile {profile} not found, define it in a [profiles.{profile}] table of {CONFIG_FILE}"
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// The value of a setting and where it came from, `None` if it is not set anywhere
+    pub fn get(&self, key: &str) -> crate::Result<Option<&(serde_json::Value, Source)>> {
+        check_key(key)?;
+        Ok(self.sources.get(key))
+    }
+}
+
+/// Sets a setting in a config file, in the `[profiles.<profile>]` table if a profile is given.
+/// The rest of the file is kept as it is, comments included.
+pub fn set(path: &Path, profile: Option<&str>, key: &str, value: &str) -> crate::Result<()> {
+    check_key(key)?;
+    let value = parse_value(key, value)?;
+
+    let mut document = match path.exists() {
+        true => read_document(path)?,
+        false => DocumentMut::new(),
+    };
+    match profile {
+        Some(profile) => {
+            let profiles = document
+                .entry("profiles")
+                .or_insert_with(toml_edit::table)
+                .as_table_mut()
+                .context("profiles is not a table")?;
+            // Only the [profiles.<name>] headers are written, not an empty [profiles]
+            profiles.set_implicit(true);
+            profiles
+                .entry(profile)
+                .or_insert_with(toml_edit::table)
+                .as_table_mut()
+                .with_context(|| format!("profiles.{profile} is not a table"))?
+                .insert(key, toml_edit::Item::Value(value));
+        }
+        None => {
+            document.insert(key, toml_edit::Item::Value(value));
+        }
+    }
+
+    // Refuse to write a file that can no longer be loaded
+    ConfigFile::from_document(document.clone())
+        .with_context(|| format!("Invalid value for {key}"))?;
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, document.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The closest `quest_emu.toml` in `dir` or its parents
+pub fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Environment variable of a setting, e.g. `QUEST_EMU_AVD_NAME`
+pub fn env_variable(key: &str) -> String {
+    format!("{ENV_PREFIX}{}", key.to_ascii_uppercase())
+}
+
+fn check_key(key: &str) -> crate::Result<()> {
+    if !KEYS.contains(&key) {
+        bail!("Unknown setting {key}, expected one of {}", KEYS.join(", "));
+    }
+    Ok(())
+}
+
+/// Parses a value given on the command line or in the environment.
+/// `emulator_args` is split on whitespace, numbers and booleans must be valid.
+/// Other values are checked when the file is loaded.
+fn parse_value(key: &str, value: &str) -> crate::Result<toml_edit::Value> {
+    Ok(match key {
+        key if NUMBER_KEYS.contains(&key) => {
+            let number: u32 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("{key} must be a number, got {value}"))?;
+            toml_edit::Value::from(i64::from(number))
+        }
+        key if BOOL_KEYS.contains(&key) => {
+            let flag: bool = value
+                .trim()
+                .parse()
+                .with_context(|| format!("{key} must be true or false, got {value}"))?;
+            toml_edit::Value::from(flag)
+        }
+        "emulator_args" => value
+            .split_whitespace()
+            .collect::<toml_edit::Array>()
+            .into(),
+        _ => value.into(),
+    })
+}
+
+fn read_document(path: &Path) -> crate::Result<DocumentMut> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
@@ -16,6 +16,9 @@ pub const DEFAULT_AVD_NAME: &str = "android13desktop";
 
 pub const DEFAULT_AVD_IMAGE: &str = "system-images;android-33;android-desktop;x86_64";
 
+/// Oculus Graph ID of Beat Saber (com.beatgames.beatsaber)
+pub const DEFAULT_GRAPH_APP_ID: &str = "2448060205267927";
+
 /// Returns the path to the Android SDK
 /// Checks the ANDROID_SDK_ROOT and ANDROID_HOME environment variables
 /// If not set, defaults to {home}/Android/Sdk
@@ -103,3 +106,59 @@ pub fn adb_path() -> PathBuf {
     }
     path
 }
+
+/// Returns the quest_emu configuration directory
+/// {config}/quest_emu, e.g. ~/.config/quest_emu on Linux
+pub fn config_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|config| config.join("quest_emu"))
+        .expect("Could not find the user configuration directory.")
+}
+
+/// Returns the path of the user configuration file
+/// {config}/quest_emu/quest_emu.toml
+pub fn user_config_file() -> PathBuf {
+    config_path().join("quest_emu.toml")
+}
+
+/// Returns the quest_emu directory for state that does not outlive the session
+/// {runtime}/quest_emu, falling back to {cache}/quest_emu where there is no runtime directory
+pub fn runtime_path() -> PathBuf {
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .map(|dir| dir.join("quest_emu"))
+        .expect("Could not find the user runtime or cache directory.")
+}
+
+/// Returns the path of the state file listing the started emulators
+/// {runtime}/quest_emu/instances.json
+pub fn instances_file() -> PathBuf {
+    runtime_path().join("instances.json")
+}
+
+/// Returns the quest_emu directory for state that outlives the session
+/// {state}/quest_emu, falling back to {data_local}/quest_emu where there is no state directory
+pub fn state_path() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("quest_emu"))
+        .expect("Could not find the user state or local data directory.")
+}
+
+/// Returns the directory with the logs of detached emulators
+/// {state}/quest_emu/logs
+pub fn logs_path() -> PathBuf {
+    state_path().join("logs")
+}
+
+/// Returns the directory with the PID files of detached emulators
+/// {runtime}/quest_emu/pids
+pub fn pids_path() -> PathBuf {
+    runtime_path().join("pids")
+}
+
+/// Returns the path of the stored Oculus auth token
+/// {config}/quest_emu/oculus_token
+pub fn auth_token_path() -> PathBuf {
+    config_path().join("oculus_token")
+}
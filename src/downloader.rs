@@ -1,12 +1,13 @@
-use indicatif::{ProgressBar, ProgressStyle};
-
 use std::io::{self, Read, Write};
 
+use crate::progress::{ProgressReporter, Task};
+
 #[cfg(feature = "reqwest")]
 pub fn download_with_progress(
     client: Option<&reqwest::blocking::Client>,
     url: &str,
     dest: &mut impl Write,
+    reporter: &dyn ProgressReporter,
 ) -> io::Result<()> {
     use std::sync::LazyLock;
 
@@ -28,12 +29,10 @@ pub fn download_with_progress(
         .and_then(|ct_len| ct_len.parse().ok())
         .unwrap_or(0);
 
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
+    reporter.start(
+        Task::Download,
+        format!("Downloading {url}"),
+        (total_size > 0).then_some(total_size),
     );
 
     let mut downloaded: u64 = 0;
@@ -45,10 +44,10 @@ pub fn download_with_progress(
         }
         dest.write_all(&buffer[..n])?;
         downloaded += n as u64;
-        pb.set_position(downloaded);
+        reporter.progress(Task::Download, downloaded);
     }
 
-    pb.finish_with_message("Download complete");
+    reporter.finish(Task::Download, "Download complete");
     Ok(())
 }
 
@@ -57,6 +56,7 @@ pub fn download_with_progress(
     client: Option<&ureq::Agent>,
     url: &str,
     dest: &mut impl Write,
+    reporter: &dyn ProgressReporter,
 ) -> io::Result<()> {
     use ureq::{Agent, http::header::CONTENT_LENGTH};
 
@@ -74,12 +74,11 @@ pub fn download_with_progress(
         .get(CONTENT_LENGTH)
         .and_then(|ct_len| ct_len.to_str().unwrap().parse().ok())
         .unwrap_or(0);
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
+
+    reporter.start(
+        Task::Download,
+        format!("Downloading {url}"),
+        (total_size > 0).then_some(total_size),
     );
     let mut downloaded: u64 = 0;
     let mut reader = resp.body_mut().as_reader();
@@ -90,8 +89,8 @@ pub fn download_with_progress(
         }
         dest.write_all(&buffer[..n])?;
         downloaded += n as u64;
-        pb.set_position(downloaded);
+        reporter.progress(Task::Download, downloaded);
     }
-    pb.finish_with_message("Download complete");
+    reporter.finish(Task::Download, "Download complete");
     Ok(())
 }
@@ -0,0 +1,223 @@
+//! Starting the Android Emulator.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{constants::emulator_path, error::bail, runner::ToolCommand};
+
+/// Flags passed on every start unless disabled with [`LaunchOptions::default_flags`].
+/// The desktop image needs permissive SELinux, and raw keyboard input makes
+/// the keyboard work in Unity apps.
+pub const DEFAULT_FLAGS: &[&str] = &["-selinux", "permissive", "-feature", "QtRawKeyboardInput"];
+
+/// Console ports the emulator accepts, adb uses the port after each
+pub const PORT_RANGE: std::ops::RangeInclusive<u16> = 5554..=5682;
+
+/// How the emulator renders graphics, `-gpu <mode>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+// Spelled like the emulator's -gpu values, in config files and on the command line
+#[cfg_attr(feature = "clap", value(rename_all = "snake_case"))]
+pub enum GpuMode {
+    /// Let the emulator choose
+    Auto,
+    /// The host GPU, fastest but depends on the host drivers
+    Host,
+    /// Software rendering, works without a GPU
+    SwiftshaderIndirect,
+    /// ANGLE on top of the host GPU, Windows only
+    AngleIndirect,
+    /// Rendering in the guest, for images that support it
+    Guest,
+}
+
+impl GpuMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GpuMode::Auto => "auto",
+            GpuMode::Host => "host",
+            GpuMode::SwiftshaderIndirect => "swiftshader_indirect",
+            GpuMode::AngleIndirect => "angle_indirect",
+            GpuMode::Guest => "guest",
+        }
+    }
+}
+
+/// What the back camera shows, `-camera-back <mode>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum CameraMode {
+    /// No camera
+    None,
+    /// A fake camera with a test pattern
+    Emulated,
+    /// A virtual room to move around in
+    Virtualscene,
+    /// The first webcam of the host
+    Webcam0,
+}
+
+impl CameraMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CameraMode::None => "none",
+            CameraMode::Emulated => "emulated",
+            CameraMode::Virtualscene => "virtualscene",
+            CameraMode::Webcam0 => "webcam0",
+        }
+    }
+}
+
+/// How to start an AVD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchOptions {
+    /// Pass [`DEFAULT_FLAGS`]
+    pub default_flags: bool,
+    /// Run without a window, `-no-window`
+    pub headless: bool,
+    pub gpu: Option<GpuMode>,
+    /// RAM of the device in MB, `-memory`
+    pub memory: Option<u32>,
+    /// Number of CPU cores, `-cores`
+    pub cores: Option<u32>,
+    /// Console port, must be even and within [`PORT_RANGE`], `-port`
+    pub port: Option<u16>,
+    /// `-no-audio`
+    pub no_audio: bool,
+    /// Reset the device to its initial state, `-wipe-data`
+    pub wipe_data: bool,
+    /// Keep the AVD unchanged so other instances can use it at the same time, `-read-only`
+    pub read_only: bool,
+    pub camera: Option<CameraMode>,
+    /// Cold boot instead of loading the quickboot snapshot, `-no-snapshot-load`
+    pub no_snapshot_load: bool,
+    /// Do not save the quickboot snapshot on exit, `-no-snapshot-save`
+    pub no_snapshot_save: bool,
+    /// Passed after all other flags
+    pub extra_args: Vec<String>,
+}
+
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        Self {
+            default_flags: true,
+            headless: false,
+            gpu: None,
+            memory: None,
+            cores: None,
+            port: None,
+            no_audio: false,
+            wipe_data: false,
+            read_only: false,
+            camera: None,
+            no_snapshot_load: false,
+            no_snapshot_save: false,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl LaunchOptions {
+    /// Fails on values the emulator would reject or misbehave with
+    pub fn validate(&self) -> crate::Result<()> {
+        if let Some(memory) = self.memory
+            && memory < 512
+        {
+            bail!("Memory must be at least 512 MB, got {memory}");
+        }
+        if self.cores == Some(0) {
+            bail!("Cores must be at least 1");
+        }
+        if let Some(port) = self.port
+            && (!PORT_RANGE.contains(&port) || port % 2 != 0)
+        {
+            bail!(
+                "Port must be an even number from {} to {}, got {port}",
+                PORT_RANGE.start(),
+                PORT_RANGE.end()
+            );
+        }
+        if self.read_only && self.wipe_data {
+            bail!("A read-only AVD cannot be wiped");
+        }
+        Ok(())
+    }
+
+    /// The emulator arguments to start the AVD with
+    pub fn args(&self, avd: &str) -> crate::Result<Vec<String>> {
+        self.validate()?;
+
+        let mut args = vec![format!("@{avd}")];
+        if self.default_flags {
+            args.extend(DEFAULT_FLAGS.iter().map(ToString::to_string));
+        }
+        let mut flag = |name: &str, value: Option<String>| {
+            args.push(name.to_string());
+            args.extend(value);
+        };
+        if self.headless {
+            flag("-no-window", None);
+        }
+        if let Some(gpu) = self.gpu {
+            flag("-gpu", Some(gpu.as_str().to_string()));
+        }
+        if let Some(memory) = self.memory {
+            flag("-memory", Some(memory.to_string()));
+        }
+        if let Some(cores) = self.cores {
+            flag("-cores", Some(cores.to_string()));
+        }
+        if let Some(port) = self.port {
+            flag("-port", Some(port.to_string()));
+        }
+        if self.no_audio {
+            flag("-no-audio", None);
+        }
+        if self.wipe_data {
+            flag("-wipe-data", None);
+        }
+        if self.read_only {
+            flag("-read-only", None);
+        }
+        if let Some(camera) = self.camera {
+            flag("-camera-back", Some(camera.as_str().to_string()));
+        }
+        if self.no_snapshot_load {
+            flag("-no-snapshot-load", None);
+        }
+        if self.no_snapshot_save {
+            flag("-no-snapshot-save", None);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        Ok(args)
+    }
+
+    /// The emulator invocation to start the AVD with
+    pub fn command(&self, avd: &str) -> crate::Result<ToolCommand> {
+        Ok(ToolCommand::new("emulator", emulator_path()).args(self.args(avd)?))
+    }
+}
+
+/// Picks `count` console ports whose port pair is free, skipping the ports in `taken`.
+/// The emulator checks again when it starts, so a port grabbed in between makes it fail
+/// instead of colliding.
+pub fn free_ports(count: usize, taken: &[u16]) -> crate::Result<Vec<u16>> {
+    let ports: Vec<u16> = PORT_RANGE
+        .step_by(2)
+        .filter(|port| !taken.contains(port))
+        .filter(|&port| is_free(port) && is_free(port + 1))
+        .take(count)
+        .collect();
+    if ports.len() < count {
+        bail!(
+            "Only {} of {count} emulator ports are free, stop some emulators first",
+            ports.len()
+        );
+    }
+    Ok(ports)
+}
+
+fn is_free(port: u16) -> bool {
+    std::net::TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
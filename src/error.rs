@@ -0,0 +1,210 @@
+//! Error type of the library API.
+
+use std::{fmt::Display, path::PathBuf, process::ExitStatus, time::Duration};
+
+use crate::apk::verify::VerifyReport;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A tool from the Android SDK is not installed
+    #[error("{tool} not found at {}, run `setup` to install it", path.display())]
+    ToolNotFound { tool: String, path: PathBuf },
+
+    /// A tool such as adb or avdmanager exited with an error
+    #[error("{tool} exited with status {status}{}", format_stderr(stderr))]
+    ToolFailed {
+        tool: String,
+        status: ExitStatus,
+        /// Captured stderr, empty if it was shown to the user directly
+        stderr: String,
+    },
+
+    #[error("{tool} did not finish within {timeout:?}")]
+    ToolTimedOut { tool: String, timeout: Duration },
+
+    /// A detached emulator exited while starting, with the end of its log
+    #[error(
+        "Emulator {serial} exited while starting with {status}, the end of {}:\n{}",
+        log.display(),
+        tail.join("\n")
+    )]
+    EmulatorExited {
+        serial: String,
+        status: ExitStatus,
+        log: PathBuf,
+        tail: Vec<String>,
+    },
+
+    #[error("System image {0} is not installed, run `setup` to install it")]
+    ImageNotInstalled(String),
+
+    #[error("AVD {0} already exists")]
+    AvdExists(String),
+
+    #[error("Package {0} is not installed")]
+    PackageNotInstalled(String),
+
+    /// Not a Java package name like `com.beatgames.beatsaber`, rejected before it reaches a shell
+    #[error("{0:?} is not a valid package name")]
+    InvalidPackageName(String),
+
+    #[error("{0} has no launcher or VR activity")]
+    NoLaunchActivity(String),
+
+    /// `am` or `pm` on the device refused a command, e.g. `DELETE_FAILED_DEVICE_POLICY_MANAGER`
+    #[error("{command} failed: {reason}")]
+    AppCommandFailed { command: String, reason: String },
+
+    #[error("No device connected, start the emulator or connect a device")]
+    NoDevice,
+
+    #[error("Multiple devices connected ({}), set ANDROID_SERIAL to pick one", .0.join(", "))]
+    MultipleDevices(Vec<String>),
+
+    /// ANDROID_SERIAL names a device that is not connected
+    #[error("Device {0} not found, check ANDROID_SERIAL")]
+    DeviceNotFound(String),
+
+    /// The device cannot run the native libraries of the APK, with a diagnosis
+    #[error("{0}")]
+    AbiIncompatible(String),
+
+    /// The APK signature or alignment is invalid after signing
+    #[error("APK verification failed for {}", path.display())]
+    VerificationFailed {
+        path: PathBuf,
+        report: Box<VerifyReport>,
+    },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+
+    /// An error with a description of what was being done
+    #[error("{message}")]
+    Context {
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+
+    /// An error from a dependency without a dedicated variant
+    #[error(transparent)]
+    External(BoxError),
+
+    #[error("{0}")]
+    Message(String),
+}
+
+impl Error {
+    pub fn msg(message: impl Display) -> Self {
+        Error::Message(message.to_string())
+    }
+
+    pub fn external(error: impl Into<BoxError>) -> Self {
+        Error::External(error.into())
+    }
+
+    /// Stable identifier of the kind of error, for scripts reading `--json` output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ToolNotFound { .. } => "tool_not_found",
+            Error::ToolFailed { .. } => "tool_failed",
+            Error::ToolTimedOut { .. } => "tool_timed_out",
+            Error::EmulatorExited { .. } => "emulator_exited",
+            Error::ImageNotInstalled(_) => "image_not_installed",
+            Error::AvdExists(_) => "avd_exists",
+            Error::PackageNotInstalled(_) => "package_not_installed",
+            Error::InvalidPackageName(_) => "invalid_package_name",
+            Error::NoLaunchActivity(_) => "no_launch_activity",
+            Error::AppCommandFailed { .. } => "app_command_failed",
+            Error::NoDevice => "no_device",
+            Error::MultipleDevices(_) => "multiple_devices",
+            Error::DeviceNotFound(_) => "device_not_found",
+            Error::AbiIncompatible(_) => "abi_incompatible",
+            Error::VerificationFailed { .. } => "verification_failed",
+            Error::Io(_) => "io",
+            Error::Json(_) => "json",
+            Error::Zip(_) => "zip",
+            // The code of what went wrong, not of what was being done
+            Error::Context { source, .. } => match source.downcast_ref::<Error>() {
+                Some(error) => error.code(),
+                None if source.is::<std::io::Error>() => "io",
+                None => "other",
+            },
+            Error::External(_) => "external",
+            Error::Message(_) => "other",
+        }
+    }
+
+    /// A tool that exited with an error after showing its output to the user
+    pub fn tool_failed(tool: &str, status: ExitStatus) -> Self {
+        Error::ToolFailed {
+            tool: tool.to_string(),
+            status,
+            stderr: String::new(),
+        }
+    }
+}
+
+fn format_stderr(stderr: &str) -> String {
+    match stderr.trim() {
+        "" => String::new(),
+        stderr => format!("\n{stderr}"),
+    }
+}
+
+/// Adds a description of what was being done to an error,
+/// or turns a missing value into an error
+pub trait Context<T> {
+    fn context<C: Display>(self, context: C) -> Result<T>;
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T, E> Context<T> for std::result::Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn context<C: Display>(self, context: C) -> Result<T> {
+        self.map_err(|source| Error::Context {
+            message: context.to_string(),
+            source: Box::new(source),
+        })
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|source| Error::Context {
+            message: context().to_string(),
+            source: Box::new(source),
+        })
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context<C: Display>(self, context: C) -> Result<T> {
+        self.ok_or_else(|| Error::msg(context))
+    }
+
+    fn with_context<C: Display, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.ok_or_else(|| Error::msg(context()))
+    }
+}
+
+/// Returns early with an [`Error::Message`]
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::Error::Message(format!($($arg)*)))
+    };
+}
+pub(crate) use bail;
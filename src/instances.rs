@@ -0,0 +1,204 @@
+//! Tracks the emulators `start` launched, so they can be found and stopped later.
+//!
+//! The state file lives in the runtime directory and lists one entry per emulator.
+//! Entries of emulators that are no longer running are dropped whenever it is written.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adb::device_command,
+    constants::instances_file,
+    error::{Context, bail},
+    logs,
+    runner::{ToolCommand, ToolRunner},
+};
+
+/// How long `adb emu kill` may take before the process is terminated instead
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An emulator started by `start`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instance {
+    pub avd: String,
+    /// Console port, adb connects to the next one
+    pub port: u16,
+    pub pid: u32,
+    /// Started with `-read-only`, sharing the AVD with other instances
+    pub read_only: bool,
+    /// The log file of an emulator started with `--detach`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log: Option<PathBuf>,
+}
+
+impl Instance {
+    /// The adb serial of the emulator, e.g. `emulator-5554`
+    pub fn serial(&self) -> String {
+        format!("emulator-{}", self.port)
+    }
+
+    pub fn is_running(&self) -> bool {
+        process_exists(self.pid, self.port)
+    }
+
+    /// Whether `target` is the serial or AVD name of this instance
+    pub fn matches(&self, target: &str) -> bool {
+        self.serial() == target || self.avd == target
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InstanceState {
+    pub instances: Vec<Instance>,
+}
+
+impl InstanceState {
+    /// Loads the state file with only the instances that are still running
+    pub fn load() -> crate::Result<Self> {
+        Self::load_from(&instances_file())
+    }
+
+    pub fn load_from(path: &Path) -> crate::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut state: Self = serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        state.instances.retain(Instance::is_running);
+        Ok(state)
+    }
+
+    pub fn save(&self) -> crate::Result<()> {
+        self.save_to(&instances_file())
+    }
+
+    pub fn save_to(&self, path: &Path) -> crate::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        // Renamed into place, so concurrent starts never read a half written file
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Console ports in use by the tracked instances
+    pub fn ports(&self) -> Vec<u16> {
+        self.instances
+            .iter()
+            .map(|instance| instance.port)
+            .collect()
+    }
+
+    /// Records started instances
+    pub fn add(instances: &[Instance]) -> crate::Result<()> {
+        Self::update(&instances_file(), |state| {
+            state
+                .instances
+                .retain(|existing| instances.iter().all(|i| i.port != existing.port));
+            state.instances.extend(instances.iter().cloned());
+        })
+    }
+
+    /// Forgets the instance on a console port
+    pub fn remove(port: u16) -> crate::Result<()> {
+        Self::update(&instances_file(), |state| {
+            state.instances.retain(|instance| instance.port != port)
+        })
+    }
+
+    /// Loads, changes and saves the state file while holding a lock on it,
+    /// so concurrent `start` and `stop` runs do not drop each other's changes
+    pub fn update(path: &Path, change: impl FnOnce(&mut Self)) -> crate::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let lock_path = path.with_extension("lock");
+        let lock = File::create(&lock_path)
+            .with_context(|| format!("Failed to create {}", lock_path.display()))?;
+        lock.lock()
+            .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+
+        let mut state = Self::load_from(path)?;
+        change(&mut state);
+        // The lock is released when the file is closed
+        state.save_to(path)
+    }
+}
+
+/// Asks the emulator to shut down, terminates its process if it does not respond,
+/// and removes it from the state file along with its PID file
+pub fn stop(runner: &dyn ToolRunner, instance: &Instance) -> crate::Result<()> {
+    let serial = instance.serial();
+    let command = device_command(&serial, ["emu", "kill"]).timeout(KILL_TIMEOUT);
+    // adb cannot reach an emulator that is still booting
+    if runner.run(&command).is_err() && instance.is_running() {
+        runner
+            .run(&terminate_command(instance.pid))
+            .with_context(|| format!("Failed to stop {serial}"))?;
+    }
+    InstanceState::remove(instance.port)?;
+    logs::remove_pid_file(&serial)
+}
+
+/// The instances matching the serials or AVD names, fails if a target matches nothing
+pub fn find(state: &InstanceState, targets: &[String]) -> crate::Result<Vec<Instance>> {
+    let mut found: Vec<Instance> = Vec::new();
+    for target in targets {
+        let matching: Vec<_> = state
+            .instances
+            .iter()
+            .filter(|instance| instance.matches(target))
+            .collect();
+        if matching.is_empty() {
+            bail!("No running emulator started by quest_emu matches {target}");
+        }
+        for instance in matching {
+            if !found.contains(instance) {
+                found.push(instance.clone());
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32, _port: u16) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 only checks whether the process exists, EPERM means it belongs to someone else
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a cheap process check, a running emulator is one that listens on its console port
+#[cfg(not(unix))]
+fn process_exists(_pid: u32, port: u16) -> bool {
+    let address = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    std::net::TcpStream::connect_timeout(&address, Duration::from_millis(200)).is_ok()
+}
+
+#[cfg(unix)]
+fn terminate_command(pid: u32) -> ToolCommand {
+    ToolCommand::new("kill", "kill").arg(pid.to_string())
+}
+
+#[cfg(not(unix))]
+fn terminate_command(pid: u32) -> ToolCommand {
+    ToolCommand::new("taskkill", "taskkill").args(["/PID", &pid.to_string(), "/T", "/F"])
+}
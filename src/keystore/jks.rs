@@ -0,0 +1,301 @@
+//! Minimal reader and writer for Java keystores (JKS).
+//!
+//! Only what is needed for APK signing is supported: private key entries with
+//! Sun's proprietary key protection and trusted certificate entries are read,
+//! and a single private key entry is written.
+
+use std::io::{Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rsa::rand_core::{OsRng, RngCore};
+use sha1::{Digest, Sha1};
+
+use crate::error::{Context, bail};
+
+const MAGIC: u32 = 0xFEED_FEED;
+const VERSION: u32 = 2;
+
+const TAG_PRIVATE_KEY: u32 = 1;
+const TAG_TRUSTED_CERT: u32 = 2;
+
+/// DER encoded OID 1.3.6.1.4.1.42.2.17.1.1, Sun's JKS key protection algorithm
+const KEY_PROTECTOR_OID: &[u8] = &[
+    0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x2a, 0x02, 0x11, 0x01, 0x01,
+];
+
+/// Salt appended to the password when computing the keystore integrity digest
+const INTEGRITY_SALT: &[u8] = b"Mighty Aphrodite";
+
+const DIGEST_LEN: usize = 20;
+
+pub struct PrivateKeyEntry {
+    pub cert_der: Vec<u8>,
+    pub pkcs8_der: Vec<u8>,
+}
+
+/// Reads a private key entry from a JKS keystore.
+/// If `alias` is `None`, the first private key entry is used.
+pub fn read_private_key(
+    data: &[u8],
+    alias: Option<&str>,
+    store_password: &str,
+    key_password: &str,
+) -> crate::Result<PrivateKeyEntry> {
+    if data.len() < DIGEST_LEN {
+        bail!("Keystore is too small to be a JKS file");
+    }
+    let (body, digest) = data.split_at(data.len() - DIGEST_LEN);
+    if integrity_digest(store_password, body).as_slice() != digest {
+        bail!("Keystore integrity check failed, is the keystore password correct?");
+    }
+
+    let mut reader = Cursor::new(body);
+    if reader.read_u32::<BigEndian>()? != MAGIC {
+        bail!("Not a JKS keystore");
+    }
+    let version = reader.read_u32::<BigEndian>()?;
+    if version != 1 && version != 2 {
+        bail!("Unsupported JKS version {version}");
+    }
+
+    let count = reader.read_u32::<BigEndian>()?;
+    for _ in 0..count {
+        let tag = reader.read_u32::<BigEndian>()?;
+        let entry_alias = read_utf(&mut reader)?;
+        let _timestamp = reader.read_u64::<BigEndian>()?;
+
+        match tag {
+            TAG_PRIVATE_KEY => {
+                let protected_key = read_bytes(&mut reader)?;
+                let chain_len = reader.read_u32::<BigEndian>()?;
+                let chain = (0..chain_len)
+                    .map(|_| read_cert(&mut reader, version))
+                    .collect::<crate::Result<Vec<_>>>()?;
+
+                if alias.is_some_and(|a| a != entry_alias) {
+                    continue;
+                }
+
+                let pkcs8_der = recover_key(&protected_key, key_password)
+                    .with_context(|| format!("Failed to decrypt key '{entry_alias}'"))?;
+                let cert_der = chain
+                    .into_iter()
+                    .next()
+                    .with_context(|| format!("Key '{entry_alias}' has no certificate"))?;
+
+                return Ok(PrivateKeyEntry {
+                    cert_der,
+                    pkcs8_der,
+                });
+            }
+            TAG_TRUSTED_CERT => {
+                read_cert(&mut reader, version)?;
+                if alias.is_some_and(|a| a == entry_alias) {
+                    bail!("Keystore entry '{entry_alias}' is a certificate without a private key");
+                }
+            }
+            _ => bail!("Unknown JKS entry type {tag}"),
+        }
+    }
+
+    match alias {
+        Some(alias) => bail!("No entry named '{alias}' found in keystore"),
+        None => bail!("No private key entry found in keystore"),
+    }
+}
+
+/// Writes a JKS keystore containing a single private key entry.
+/// The same password protects both the keystore and the key.
+pub fn write_private_key(
+    alias: &str,
+    password: &str,
+    pkcs8_der: &[u8],
+    cert_der: &[u8],
+) -> crate::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.write_u32::<BigEndian>(MAGIC)?;
+    out.write_u32::<BigEndian>(VERSION)?;
+    out.write_u32::<BigEndian>(1)?;
+
+    out.write_u32::<BigEndian>(TAG_PRIVATE_KEY)?;
+    write_utf(&mut out, alias)?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    out.write_u64::<BigEndian>(timestamp)?;
+
+    let protected_key = protect_key(pkcs8_der, password);
+    write_bytes(&mut out, &protected_key)?;
+
+    out.write_u32::<BigEndian>(1)?;
+    write_utf(&mut out, "X.509")?;
+    write_bytes(&mut out, cert_der)?;
+
+    let digest = integrity_digest(password, &out);
+    out.extend_from_slice(&digest);
+    Ok(out)
+}
+
+fn read_cert(reader: &mut Cursor<&[u8]>, version: u32) -> crate::Result<Vec<u8>> {
+    if version == 2 {
+        let cert_type = read_utf(reader)?;
+        if cert_type != "X.509" {
+            bail!("Unsupported certificate type {cert_type}");
+        }
+    }
+    read_bytes(reader)
+}
+
+fn read_utf(reader: &mut Cursor<&[u8]>) -> crate::Result<String> {
+    let len = reader.read_u16::<BigEndian>()?;
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    // Java's modified UTF-8 only differs for NUL and supplementary characters,
+    // neither of which show up in aliases in practice
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn write_utf(out: &mut Vec<u8>, s: &str) -> crate::Result<()> {
+    let len = u16::try_from(s.len()).context("Alias is too long")?;
+    out.write_u16::<BigEndian>(len)?;
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn read_bytes(reader: &mut Cursor<&[u8]>) -> crate::Result<Vec<u8>> {
+    let len = reader.read_u32::<BigEndian>()?;
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> crate::Result<()> {
+    out.write_u32::<BigEndian>(u32::try_from(bytes.len()).context("JKS entry is too large")?)?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Java encodes passwords as UTF-16BE for all JKS digests
+fn password_bytes(password: &str) -> Vec<u8> {
+    password
+        .encode_utf16()
+        .flat_map(|c| c.to_be_bytes())
+        .collect()
+}
+
+fn integrity_digest(password: &str, body: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha1::new();
+    hasher.update(password_bytes(password));
+    hasher.update(INTEGRITY_SALT);
+    hasher.update(body);
+    hasher.finalize().into()
+}
+
+/// XORs `data` with the key protector keystream derived from the password and salt
+fn apply_keystream(data: &[u8], password: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut digest: [u8; DIGEST_LEN] = salt.try_into().expect("salt is a SHA-1 digest");
+    data.chunks(DIGEST_LEN)
+        .flat_map(|chunk| {
+            digest = Sha1::new()
+                .chain_update(password)
+                .chain_update(digest)
+                .finalize()
+                .into();
+            chunk
+                .iter()
+                .zip(digest)
+                .map(|(b, k)| b ^ k)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn recover_key(protected_key: &[u8], password: &str) -> crate::Result<Vec<u8>> {
+    // EncryptedPrivateKeyInfo ::= SEQUENCE { AlgorithmIdentifier, OCTET STRING }
+    let (info, _) = read_der(protected_key, 0x30)?;
+    let (algorithm, rest) = read_der(info, 0x30)?;
+    if !algorithm.starts_with(KEY_PROTECTOR_OID) {
+        bail!("Unsupported key protection algorithm, only JKS key protection is supported");
+    }
+    let (protected, _) = read_der(rest, 0x04)?;
+    if protected.len() < DIGEST_LEN * 2 {
+        bail!("Protected key is too short");
+    }
+
+    let password = password_bytes(password);
+    let (salt, rest) = protected.split_at(DIGEST_LEN);
+    let (encrypted, check) = rest.split_at(rest.len() - DIGEST_LEN);
+    let key = apply_keystream(encrypted, &password, salt);
+
+    let expected = Sha1::new()
+        .chain_update(&password)
+        .chain_update(&key)
+        .finalize();
+    if expected.as_slice() != check {
+        bail!("Key password is incorrect");
+    }
+    Ok(key)
+}
+
+fn protect_key(pkcs8_der: &[u8], password: &str) -> Vec<u8> {
+    let password = password_bytes(password);
+    let mut salt = [0; DIGEST_LEN];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut protected = salt.to_vec();
+    protected.extend(apply_keystream(pkcs8_der, &password, &salt));
+    protected.extend(
+        Sha1::new()
+            .chain_update(&password)
+            .chain_update(pkcs8_der)
+            .finalize(),
+    );
+
+    let mut algorithm = KEY_PROTECTOR_OID.to_vec();
+    algorithm.extend([0x05, 0x00]);
+
+    let mut info = der(0x30, &algorithm);
+    info.extend(der(0x04, &protected));
+    der(0x30, &info)
+}
+
+/// Reads a single DER element with the expected tag, returning its contents and the remaining bytes
+fn read_der(data: &[u8], expected_tag: u8) -> crate::Result<(&[u8], &[u8])> {
+    let (&tag, rest) = data.split_first().context("Unexpected end of DER data")?;
+    if tag != expected_tag {
+        bail!("Unexpected DER tag {tag:#04x}, expected {expected_tag:#04x}");
+    }
+    let (&first, mut rest) = rest.split_first().context("Unexpected end of DER data")?;
+    let len = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let num_bytes = (first & 0x7f) as usize;
+        if num_bytes > size_of::<usize>() || rest.len() < num_bytes {
+            bail!("Invalid DER length");
+        }
+        let (len_bytes, after) = rest.split_at(num_bytes);
+        rest = after;
+        len_bytes.iter().fold(0, |acc, &b| (acc << 8) | b as usize)
+    };
+    if rest.len() < len {
+        bail!("Unexpected end of DER data");
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Encodes a DER element
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let skip = len_bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (len_bytes.len() - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
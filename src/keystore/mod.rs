@@ -0,0 +1,269 @@
+use std::path::Path;
+
+use itertools::Itertools;
+use rsa::{
+    RsaPrivateKey,
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
+};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    auth::write_private_file,
+    error::{Context, Error, bail},
+};
+
+mod jks;
+
+/// The debug certificate used when no keystore is provided
+const DEBUG_CERT_PEM: &[u8] = include_bytes!("../debug_cert.pem");
+
+/// Key size used for generated signing keys
+const GENERATED_KEY_BITS: usize = 2048;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum KeystoreFormat {
+    /// PEM file containing a certificate and an unencrypted RSA private key
+    Pem,
+    /// PKCS#12 keystore (.p12, .pfx), the default format of keytool
+    Pkcs12,
+    /// Java keystore (.jks, .keystore)
+    Jks,
+}
+
+impl KeystoreFormat {
+    /// Guesses the keystore format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "pem" | "crt" | "key" => Some(Self::Pem),
+            "p12" | "pfx" => Some(Self::Pkcs12),
+            "jks" | "keystore" => Some(Self::Jks),
+            _ => None,
+        }
+    }
+}
+
+/// A certificate and RSA private key used to sign APKs
+#[derive(Clone)]
+pub struct SigningKey {
+    /// DER encoded X.509 certificate
+    pub cert_der: Vec<u8>,
+    pub private_key: RsaPrivateKey,
+}
+
+// Only the fingerprint, so keys do not end up in logs
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("sha256", &self.sha256_fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    /// The debug key embedded in the binary.
+    /// Every user of this tool shares it, so it should not be used for APKs distributed to many devices.
+    pub fn debug() -> Self {
+        Self::from_pem(DEBUG_CERT_PEM).expect("Embedded debug certificate is invalid")
+    }
+
+    /// Loads a signing key from a keystore file.
+    /// `alias` selects the entry for PKCS#12 and JKS keystores, defaulting to the first private key entry.
+    /// `key_password` is only used by JKS and defaults to `store_password`.
+    pub fn load(
+        path: &Path,
+        format: KeystoreFormat,
+        alias: Option<&str>,
+        store_password: &str,
+        key_password: Option<&str>,
+    ) -> crate::Result<Self> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read keystore {}", path.display()))?;
+
+        match format {
+            KeystoreFormat::Pem => Self::from_pem(&data),
+            KeystoreFormat::Pkcs12 => Self::from_pkcs12(&data, alias, store_password),
+            KeystoreFormat::Jks => {
+                let entry = jks::read_private_key(
+                    &data,
+                    alias,
+                    store_password,
+                    key_password.unwrap_or(store_password),
+                )?;
+                Self::from_pkcs8_der(entry.cert_der, &entry.pkcs8_der)
+            }
+        }
+        .with_context(|| format!("Failed to load signing key from {}", path.display()))
+    }
+
+    /// Generates a new self-signed RSA signing key
+    pub fn generate(common_name: &str) -> crate::Result<Self> {
+        let private_key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, GENERATED_KEY_BITS)
+            .context("Failed to generate RSA key")?;
+        let pkcs8_der = private_key
+            .to_pkcs8_der()
+            .context("Failed to encode private key")?;
+
+        let key_pair = rcgen::KeyPair::try_from(pkcs8_der.as_bytes())
+            .context("Failed to load generated key for certificate signing")?;
+        let mut params = rcgen::CertificateParams::default();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let cert = params
+            .self_signed(&key_pair)
+            .context("Failed to create self-signed certificate")?;
+
+        Ok(Self {
+            cert_der: cert.der().to_vec(),
+            private_key,
+        })
+    }
+
+    /// Writes the key to a keystore file only the current user can read
+    pub fn save(
+        &self,
+        path: &Path,
+        format: KeystoreFormat,
+        alias: &str,
+        password: &str,
+    ) -> crate::Result<()> {
+        let data = match format {
+            KeystoreFormat::Pem => self.to_pem()?,
+            KeystoreFormat::Pkcs12 => self.to_pkcs12(alias, password)?,
+            KeystoreFormat::Jks => {
+                jks::write_private_key(alias, password, &self.pkcs8_der()?, &self.cert_der)?
+            }
+        };
+
+        // PEM keys are not encrypted at all, and keystore passwords are often weak
+        write_private_file(path, &data)
+            .with_context(|| format!("Failed to write keystore {}", path.display()))
+    }
+
+    /// SHA-256 fingerprint of the certificate, formatted like keytool does
+    pub fn sha256_fingerprint(&self) -> String {
+        fingerprint(&Sha256::digest(&self.cert_der))
+    }
+
+    /// PEM encoding with the certificate followed by a PKCS#1 private key,
+    /// the layout `mbf_zip::signing::load_cert_and_priv_key` expects
+    pub fn to_pem(&self) -> crate::Result<Vec<u8>> {
+        let mut pem = pem::encode(&pem::Pem::new("CERTIFICATE", self.cert_der.clone()));
+        pem.push_str(
+            &self
+                .private_key
+                .to_pkcs1_pem(LineEnding::LF)
+                .context("Failed to encode private key")?,
+        );
+        Ok(pem.into_bytes())
+    }
+
+    fn pkcs8_der(&self) -> crate::Result<Vec<u8>> {
+        Ok(self
+            .private_key
+            .to_pkcs8_der()
+            .context("Failed to encode private key")?
+            .as_bytes()
+            .to_vec())
+    }
+
+    fn from_pem(data: &[u8]) -> crate::Result<Self> {
+        let blocks = pem::parse_many(data).context("Failed to parse PEM file")?;
+
+        let cert_der = blocks
+            .iter()
+            .find(|block| block.tag() == "CERTIFICATE")
+            .context("No CERTIFICATE block found in PEM file")?
+            .contents()
+            .to_vec();
+
+        let private_key = blocks
+            .iter()
+            .find_map(|block| match block.tag() {
+                "RSA PRIVATE KEY" => Some(
+                    RsaPrivateKey::from_pkcs1_der(block.contents())
+                        .context("Failed to parse RSA PRIVATE KEY"),
+                ),
+                "PRIVATE KEY" => Some(
+                    RsaPrivateKey::from_pkcs8_der(block.contents())
+                        .context("Failed to parse PRIVATE KEY, only RSA keys are supported"),
+                ),
+                "ENCRYPTED PRIVATE KEY" => Some(Err(Error::msg(
+                    "Encrypted PEM private keys are not supported, use a PKCS#12 or JKS keystore instead"
+                ))),
+                _ => None,
+            })
+            .context("No private key block found in PEM file")??;
+
+        Ok(Self {
+            cert_der,
+            private_key,
+        })
+    }
+
+    fn from_pkcs12(data: &[u8], alias: Option<&str>, password: &str) -> crate::Result<Self> {
+        use p12_keystore::{KeyStore, KeyStoreEntry};
+
+        let keystore = KeyStore::from_pkcs12(data, password)
+            .map_err(Error::external)
+            .context("Failed to open PKCS#12 keystore, is the password correct?")?;
+
+        let chain = match alias {
+            Some(alias) => match keystore.entry(alias) {
+                Some(KeyStoreEntry::PrivateKeyChain(chain)) => chain,
+                Some(KeyStoreEntry::Certificate(_)) => {
+                    bail!("Keystore entry '{alias}' is a certificate without a private key")
+                }
+                None => bail!("No entry named '{alias}' found in keystore"),
+            },
+            None => {
+                keystore
+                    .private_key_chain()
+                    .context("No private key entry found in keystore")?
+                    .1
+            }
+        };
+
+        let cert = chain
+            .chain()
+            .first()
+            .context("Keystore entry has no certificate")?;
+        Self::from_pkcs8_der(cert.as_der().to_vec(), chain.key())
+    }
+
+    fn from_pkcs8_der(cert_der: Vec<u8>, pkcs8_der: &[u8]) -> crate::Result<Self> {
+        let private_key = RsaPrivateKey::from_pkcs8_der(pkcs8_der)
+            .context("Failed to parse private key, only RSA keys are supported")?;
+
+        Ok(Self {
+            cert_der,
+            private_key,
+        })
+    }
+
+    fn to_pkcs12(&self, alias: &str, password: &str) -> crate::Result<Vec<u8>> {
+        use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+
+        let cert = Certificate::from_der(&self.cert_der).map_err(Error::external)?;
+        let local_key_id = Sha1::digest(&self.cert_der);
+        let chain = PrivateKeyChain::new(self.pkcs8_der()?, local_key_id, [cert]);
+
+        let mut keystore = KeyStore::new();
+        keystore.add_entry(alias, KeyStoreEntry::PrivateKeyChain(chain));
+        keystore
+            .writer(password)
+            .write()
+            .map_err(Error::external)
+            .context("Failed to write PKCS#12 keystore")
+    }
+}
+
+/// Formats a digest as colon separated uppercase hex
+pub fn fingerprint(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02X}")).join(":")
+}
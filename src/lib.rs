@@ -1,3 +1,52 @@
+//! Set up a Quest-like Android emulator and patch Quest APKs to run in it.
+//!
+//! The CLI is built on the library API, which can be used without the `clap` feature:
+//!
+//! ```no_run
+//! use quest_emu::{apk, avd, progress::SilentReporter, runner::SystemRunner, sdk};
+//!
+//! # fn main() -> quest_emu::Result<()> {
+//! // Long running operations report their progress, e.g. to draw progress bars
+//! let reporter = SilentReporter;
+//! // External tools run as child processes, tests can script them with a ScriptedRunner
+//! let runner = SystemRunner;
+//!
+//! let spec = avd::AvdSpec::default();
+//! if !sdk::is_image_installed(&spec.image) {
+//!     sdk::install(&runner, &spec.image, &reporter)?;
+//! }
+//! if !avd::exists(&spec.name) {
+//!     avd::create(&runner, &spec)?;
+//! }
+//!
+//! let options = apk::PatchOptions::default();
+//! let report = apk::patch("beatsaber.apk".as_ref(), &options, &reporter)?;
+//! apk::install(&runner, &report.apk_set, &apk::InstallOptions::default(), &reporter)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod adb;
+pub mod apk;
+pub mod app;
+pub mod auth;
+pub mod avd;
+pub mod backup;
+#[cfg(feature = "clap")]
 pub mod commands;
+pub mod config;
 pub mod constants;
+mod date;
 pub mod downloader;
+pub mod emulator;
+pub mod error;
+pub mod instances;
+pub mod keystore;
+pub mod logcat;
+pub mod logs;
+pub mod progress;
+pub mod runner;
+pub mod sdk;
+pub mod tombstone;
+
+pub use error::{Error, Result};
@@ -0,0 +1,345 @@
+//! Reading the device log from `adb logcat -B`.
+//!
+//! logcat's binary output is parsed instead of its text, so the PID, tag and priority of
+//! every entry are exact and multi-line messages such as stack traces stay in one entry.
+
+use serde::Serialize;
+
+use crate::{
+    adb::device_command,
+    date::civil_from_days,
+    error::Context,
+    runner::{ToolCommand, ToolRunner},
+};
+
+/// Log ID of the crash buffer, where native crashes and fatal exceptions are logged
+pub const CRASH_LOG_ID: u32 = 4;
+
+/// Header size of `logger_entry` v1, which has no header size field
+const V1_HEADER_SIZE: usize = 20;
+
+/// Importance of a log entry, as in `android.util.Log`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Priority {
+    Verbose = 2,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Priority {
+    pub fn from_u8(priority: u8) -> Option<Self> {
+        Some(match priority {
+            2 => Priority::Verbose,
+            3 => Priority::Debug,
+            4 => Priority::Info,
+            5 => Priority::Warn,
+            6 => Priority::Error,
+            7 => Priority::Fatal,
+            _ => return None,
+        })
+    }
+
+    /// The letter logcat shows for the priority, e.g. `E`
+    pub fn letter(self) -> char {
+        match self {
+            Priority::Verbose => 'V',
+            Priority::Debug => 'D',
+            Priority::Info => 'I',
+            Priority::Warn => 'W',
+            Priority::Error => 'E',
+            Priority::Fatal => 'F',
+        }
+    }
+}
+
+/// Why an entry deserves attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Highlight {
+    /// Part of the tombstone crash_dump logs for a native crash
+    NativeCrash,
+    /// A `FATAL EXCEPTION` that killed an app
+    JavaCrash,
+    /// A logged exception with a stack trace
+    Exception,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogEntry {
+    pub pid: i32,
+    pub tid: u32,
+    /// Seconds since the Unix epoch
+    pub sec: u32,
+    pub nsec: u32,
+    /// The buffer the entry was logged to, e.g. [`CRASH_LOG_ID`]
+    pub log_id: u32,
+    pub priority: Priority,
+    pub tag: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn highlight(&self) -> Option<Highlight> {
+        match (self.tag.as_str(), self.priority) {
+            ("DEBUG", Priority::Fatal) => Some(Highlight::NativeCrash),
+            ("AndroidRuntime", Priority::Error) => Some(Highlight::JavaCrash),
+            _ if self.message.lines().any(is_stack_frame) => Some(Highlight::Exception),
+            _ => None,
+        }
+    }
+
+    /// Starts the tombstone of a native crash, the line of asterisks crash_dump logs first
+    pub fn starts_native_crash(&self) -> bool {
+        self.highlight() == Some(Highlight::NativeCrash) && self.message.starts_with("*** ***")
+    }
+
+    /// Time of the entry like logcat shows it, in UTC, e.g. `10-18 14:03:07.251`
+    pub fn timestamp(&self) -> String {
+        let days = self.sec / 86400;
+        let seconds = self.sec % 86400;
+        // logcat leaves out the year
+        let (_, month, day) = civil_from_days(days.into());
+        format!(
+            "{month:02}-{day:02} {:02}:{:02}:{:02}.{:03}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.nsec / 1_000_000
+        )
+    }
+
+    /// The entry in logcat's `threadtime` format, one line per line of the message
+    pub fn format(&self) -> String {
+        let header = format!(
+            "{} {:5} {:5} {} {}: ",
+            self.timestamp(),
+            self.pid,
+            self.tid,
+            self.priority.letter(),
+            self.tag
+        );
+        self.message
+            .lines()
+            .map(|line| format!("{header}{line}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// `at com.example.Foo.bar(Foo.java:12)` in a Java stack trace
+fn is_stack_frame(line: &str) -> bool {
+    line.trim_start()
+        .strip_prefix("at ")
+        .is_some_and(|frame| frame.contains('(') && frame.ends_with(')'))
+}
+
+/// Splits the output of `logcat -B` into entries. Output arrives in arbitrary chunks,
+/// so an incomplete entry is kept until the rest of it arrives.
+#[derive(Debug, Default)]
+pub struct LogParser {
+    buffer: Vec<u8>,
+}
+
+impl LogParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entries completed by `bytes`. Binary entries, e.g. of the events buffer, are skipped
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<LogEntry> {
+        self.buffer.extend_from_slice(bytes);
+        let mut entries = Vec::new();
+        let mut start = 0;
+        while let Some(header) = self.buffer.get(start..start + 4) {
+            let payload_len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+            let header_size = match u16::from_le_bytes([header[2], header[3]]) {
+                0 => V1_HEADER_SIZE,
+                size => usize::from(size).max(V1_HEADER_SIZE),
+            };
+            let end = start + header_size + payload_len;
+            if self.buffer.len() < end {
+                break;
+            }
+            entries.extend(parse_entry(&self.buffer[start..end], header_size));
+            start = end;
+        }
+        self.buffer.drain(..start);
+        entries
+    }
+}
+
+fn parse_entry(entry: &[u8], header_size: usize) -> Option<LogEntry> {
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(entry[offset..offset + 4].try_into().expect("4 bytes"));
+    let payload = &entry[header_size..];
+    let (&priority, rest) = payload.split_first()?;
+    let priority = Priority::from_u8(priority)?;
+    let mut parts = rest.splitn(2, |&byte| byte == 0);
+    let tag = parts.next()?;
+    let message = parts.next().unwrap_or_default();
+    let message = message.strip_suffix(&[0]).unwrap_or(message);
+
+    Some(LogEntry {
+        pid: u32_at(4) as i32,
+        tid: u32_at(8),
+        sec: u32_at(12),
+        nsec: u32_at(16),
+        log_id: if header_size >= 24 { u32_at(20) } else { 0 },
+        priority,
+        tag: String::from_utf8_lossy(tag).into_owned(),
+        message: String::from_utf8_lossy(message)
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+    })
+}
+
+/// Which entries to show. Entries of the package include the tombstones of its native crashes,
+/// which crash_dump logs from its own process, whatever their tag and priority
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Only show entries of this package's processes
+    pub package: Option<String>,
+    /// PIDs of the package, processes started later are added from ActivityManager's log
+    pub pids: Vec<i32>,
+    /// Only show entries with one of these tags, all if empty
+    pub tags: Vec<String>,
+    pub min_priority: Option<Priority>,
+    /// The tombstone being logged, kept until it names the crashed process
+    pending_crash: Vec<LogEntry>,
+}
+
+impl LogFilter {
+    /// Shows the entries of a package's processes, or of all processes without a package
+    pub fn new(package: Option<String>, pids: Vec<i32>) -> Self {
+        Self {
+            package,
+            pids,
+            ..Self::default()
+        }
+    }
+
+    /// The entries to show now that `entry` was logged, usually none or `entry` itself
+    pub fn filter(&mut self, entry: LogEntry) -> Vec<LogEntry> {
+        if let Some(package) = self.package.clone() {
+            self.track_process_start(&package, &entry);
+            if entry.highlight() == Some(Highlight::NativeCrash) {
+                return self.filter_crash(&package, entry);
+            }
+            if !self.pids.contains(&entry.pid) {
+                return Vec::new();
+            }
+        }
+        match self.shows(&entry) {
+            true => vec![entry],
+            false => Vec::new(),
+        }
+    }
+
+    fn shows(&self, entry: &LogEntry) -> bool {
+        (self.tags.is_empty() || self.tags.contains(&entry.tag))
+            && self.min_priority.is_none_or(|min| entry.priority >= min)
+    }
+
+    /// `Start proc 4321:com.beatgames.beatsaber/u0a123 for top-activity ...`
+    fn track_process_start(&mut self, package: &str, entry: &LogEntry) {
+        if entry.tag != "ActivityManager" {
+            return;
+        }
+        let started = entry
+            .message
+            .strip_prefix("Start proc ")
+            .and_then(|rest| rest.split_once(':'))
+            .filter(|(_, rest)| rest.starts_with(&format!("{package}/")))
+            .and_then(|(pid, _)| pid.parse().ok());
+        if let Some(pid) = started
+            && !self.pids.contains(&pid)
+        {
+            self.pids.push(pid);
+        }
+    }
+
+    /// Holds tombstone lines back until `pid: 4321, tid: ..., name: ...  >>> package <<<`
+    /// tells whether the crash is the package's
+    fn filter_crash(&mut self, package: &str, entry: LogEntry) -> Vec<LogEntry> {
+        if entry.starts_native_crash()
+            || self
+                .pending_crash
+                .first()
+                .is_some_and(|first| first.pid != entry.pid)
+        {
+            self.pending_crash.clear();
+        }
+        let names_process = entry.message.starts_with("pid: ");
+        let ours = entry.message.contains(&format!(">>> {package} <<<"))
+            || self
+                .pids
+                .iter()
+                .any(|pid| entry.message.starts_with(&format!("pid: {pid},")));
+        self.pending_crash.push(entry);
+        match (names_process, ours) {
+            (true, true) => {
+                // The rest of this tombstone comes from the same crash_dump process
+                let crash_dump = self.pending_crash[0].pid;
+                if !self.pids.contains(&crash_dump) {
+                    self.pids.push(crash_dump);
+                }
+                std::mem::take(&mut self.pending_crash)
+            }
+            (true, false) => {
+                self.pending_crash.clear();
+                Vec::new()
+            }
+            (false, _) if self.pids.contains(&self.pending_crash[0].pid) => {
+                std::mem::take(&mut self.pending_crash)
+            }
+            (false, _) => Vec::new(),
+        }
+    }
+}
+
+/// `adb exec-out logcat -B` on the main, system and crash buffers, exiting after the
+/// current log with `dump`. exec-out keeps the binary output intact on every adb version
+pub fn logcat_command(serial: &str, dump: bool) -> ToolCommand {
+    let mut command = device_command(
+        serial,
+        ["exec-out", "logcat", "-B", "-b", "main,system,crash"],
+    );
+    if dump {
+        command = command.arg("-d");
+    }
+    command
+}
+
+/// PIDs of the running processes of a package, empty if it is not running
+pub fn pids(runner: &dyn ToolRunner, serial: &str, package: &str) -> crate::Result<Vec<i32>> {
+    // pidof exits with 1 when nothing matches
+    let output = runner.output(&device_command(serial, ["shell", "pidof", package]))?;
+    Ok(output
+        .stdout_lossy()
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .collect())
+}
+
+/// Runs logcat and passes every entry to `on_entry` until logcat exits
+pub fn stream(
+    runner: &dyn ToolRunner,
+    command: &ToolCommand,
+    on_entry: &mut dyn FnMut(LogEntry),
+) -> crate::Result<()> {
+    let mut parser = LogParser::new();
+    let output = runner.stream(command, &mut |bytes| {
+        for entry in parser.push(bytes) {
+            on_entry(entry);
+        }
+    })?;
+    output
+        .check(&command.tool)
+        .context("Failed to read the device log")?;
+    Ok(())
+}
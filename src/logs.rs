@@ -0,0 +1,243 @@
+//! Log and PID files of emulators started with `start --detach`.
+//!
+//! Each detached emulator has its own log, `<avd>.<serial>.log`, written by a
+//! [`RotatingWriter`] that outlives `start`: once the log grows past [`MAX_LOG_SIZE`]
+//! it is moved to `<avd>.<serial>.log.1`, keeping the [`KEPT_LOGS`] most recent ones.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    constants::{logs_path, pids_path},
+    error::Context,
+};
+
+/// Size after which a log is rotated
+pub const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Number of rotated logs kept next to the current one
+pub const KEPT_LOGS: u32 = 3;
+
+/// How often [`follow`] checks the log for new output
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The log of a detached emulator, e.g. `quest.emulator-5554.log`
+pub fn log_file(avd: &str, serial: &str) -> PathBuf {
+    logs_path().join(format!("{avd}.{serial}.log"))
+}
+
+/// The most recently written log of an AVD or serial, for emulators that are no longer running
+pub fn latest_log(target: &str) -> crate::Result<Option<PathBuf>> {
+    let dir = logs_path();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+        let path = entry.path();
+        if !parse_log_name(&path).is_some_and(|(avd, serial)| avd == target || serial == target) {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            latest = Some((modified, path));
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// The AVD name and serial of a log named by [`log_file`]
+pub fn parse_log_name(path: &Path) -> Option<(&str, &str)> {
+    path.file_name()?
+        .to_str()?
+        .strip_suffix(".log")?
+        .rsplit_once('.')
+}
+
+/// The PID file of a detached emulator, e.g. `emulator-5554.pid`
+pub fn pid_file(serial: &str) -> PathBuf {
+    pids_path().join(format!("{serial}.pid"))
+}
+
+pub fn write_pid_file(serial: &str, pid: u32) -> crate::Result<()> {
+    let path = pid_file(serial);
+    create_parent(&path)?;
+    std::fs::write(&path, format!("{pid}\n"))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Removes the PID file of a stopped emulator, if there is one
+pub fn remove_pid_file(serial: &str) -> crate::Result<()> {
+    let path = pid_file(serial);
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Moves `path` to `path.1` if it is larger than `max_size`, shifting older logs
+/// up to `path.<keep>` and deleting the oldest
+pub fn rotate(path: &Path, max_size: u64, keep: u32) -> crate::Result<()> {
+    create_parent(path)?;
+    let size = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    if size <= max_size {
+        return Ok(());
+    }
+    if keep == 0 {
+        return std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()));
+    }
+
+    for index in (1..keep).rev() {
+        let from = rotated(path, index);
+        if from.exists() {
+            let to = rotated(path, index + 1);
+            std::fs::rename(&from, &to)
+                .with_context(|| format!("Failed to rename {}", from.display()))?;
+        }
+    }
+    std::fs::rename(path, rotated(path, 1))
+        .with_context(|| format!("Failed to rename {}", path.display()))
+}
+
+/// Appends to a log, rotating it with [`rotate`] before it grows past `max_size`
+pub struct RotatingWriter {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingWriter {
+    pub fn new(path: impl Into<PathBuf>, max_size: u64, keep: u32) -> Self {
+        Self {
+            path: path.into(),
+            max_size,
+            keep,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.file = None;
+            // Anything already in the log is over the limit of 0
+            rotate(&self.path, 0, self.keep).map_err(std::io::Error::other)?;
+            self.open()?;
+        }
+        let file = self.file.as_mut().expect("log is open");
+        let written = file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn rotated(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// The last `count` lines of a log
+pub fn tail(path: &Path, count: usize) -> crate::Result<Vec<String>> {
+    let log = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let log = String::from_utf8_lossy(&log);
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines.len().saturating_sub(count);
+    Ok(lines[start..].iter().map(ToString::to_string).collect())
+}
+
+/// Passes every line appended to the log after the call to `on_line`, until `running`
+/// returns false. Starts from the beginning again when the log is rotated.
+pub fn follow(
+    path: &Path,
+    mut running: impl FnMut() -> bool,
+    mut on_line: impl FnMut(&str),
+) -> crate::Result<()> {
+    let mut offset = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    let mut partial = Vec::new();
+    loop {
+        // Read once more after the emulator exited, for its last words
+        let done = !running();
+
+        let mut file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            // Between the rotation and the emulator creating a new log
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !done => {
+                offset = 0;
+                std::thread::sleep(FOLLOW_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let size = file.metadata()?.len();
+        if size < offset {
+            offset = 0;
+            partial.clear();
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        offset += appended.len() as u64;
+
+        partial.extend_from_slice(&appended);
+        while let Some(end) = partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = partial.drain(..=end).collect();
+            on_line(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']));
+        }
+
+        if done {
+            if !partial.is_empty() {
+                on_line(&String::from_utf8_lossy(&partial));
+            }
+            return Ok(());
+        }
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+fn create_parent(path: &Path) -> crate::Result<()> {
+    match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display())),
+        None => Ok(()),
+    }
+}
@@ -1,10 +1,10 @@
 use clap::Parser;
 
-use crate::commands::{Command, GlobalContext};
-
-mod commands;
-mod constants;
-mod downloader;
+use quest_emu::{
+    commands::{self, Command, GlobalContext, ProgressFormat},
+    config::Config,
+    runner::SystemRunner,
+};
 
 #[derive(clap::Parser)]
 struct Args {
@@ -12,6 +12,19 @@ struct Args {
     #[arg(long, default_value_t = false, global = true)]
     yes: bool,
 
+    /// Print the result as a single JSON document, messages go to stderr
+    #[arg(long, default_value_t = false, global = true)]
+    json: bool,
+
+    /// How to show the progress of downloads, installs and patching
+    #[arg(long, value_enum, default_value_t, global = true)]
+    progress: ProgressFormat,
+
+    /// Use the defaults of a [profiles.<name>] table in quest_emu.toml.
+    /// Defaults to QUEST_EMU_PROFILE
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: commands::MainCommand,
 }
@@ -21,9 +34,33 @@ fn main() -> color_eyre::Result<()> {
 
     let args = Args::parse();
 
-    let ctx = GlobalContext { yes: args.yes };
+    let json = args.json;
+
+    let result = Config::load(args.profile.as_deref())
+        .map_err(color_eyre::Report::from)
+        .and_then(|config| {
+            let ctx = GlobalContext {
+                yes: args.yes,
+                json,
+                reporter: args.progress.reporter(json),
+                config,
+                runner: Box::new(SystemRunner),
+            };
+            args.command.execute(&ctx)
+        });
 
-    args.command.execute(&ctx)?;
+    match result {
+        Ok(result) if json => println!("{}", serde_json::to_string_pretty(&result)?),
+        Ok(_) => {}
+        Err(report) if json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&commands::error_json(&report))?
+            );
+            std::process::exit(1);
+        }
+        Err(report) => return Err(report),
+    }
 
     Ok(())
 }
@@ -0,0 +1,216 @@
+//! Progress reporting of long running operations.
+//!
+//! Operations report [`ProgressEvent`]s to a [`ProgressReporter`], so the CLI can draw
+//! progress bars while other frontends render their own UI from the same events.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+/// The kind of work an event belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    Download,
+    Extract,
+    SdkInstall,
+    Patch,
+    Install,
+    Push,
+    Pull,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// A task started, `total` is the amount of work in bytes or items if known
+    Started {
+        task: Task,
+        message: String,
+        total: Option<u64>,
+    },
+    /// Work done so far of a started task
+    Progress {
+        task: Task,
+        current: u64,
+    },
+    Finished {
+        task: Task,
+        message: String,
+    },
+    /// Output worth showing to the user, e.g. from sdkmanager
+    Message {
+        message: String,
+    },
+}
+
+/// Receives the progress of operations
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+/// Helpers to report events through a `&dyn ProgressReporter`
+impl dyn ProgressReporter + '_ {
+    pub fn start(&self, task: Task, message: impl Into<String>, total: Option<u64>) {
+        self.report(ProgressEvent::Started {
+            task,
+            message: message.into(),
+            total,
+        });
+    }
+
+    pub fn progress(&self, task: Task, current: u64) {
+        self.report(ProgressEvent::Progress { task, current });
+    }
+
+    pub fn finish(&self, task: Task, message: impl Into<String>) {
+        self.report(ProgressEvent::Finished {
+            task,
+            message: message.into(),
+        });
+    }
+
+    pub fn message(&self, message: impl Into<String>) {
+        self.report(ProgressEvent::Message {
+            message: message.into(),
+        });
+    }
+}
+
+/// Ignores all events
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SilentReporter;
+
+impl ProgressReporter for SilentReporter {
+    fn report(&self, _event: ProgressEvent) {}
+}
+
+/// Draws progress bars for tasks with a known total and prints messages
+#[derive(Default)]
+pub struct IndicatifReporter {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<Task, ProgressBar>>,
+    /// Print messages to stderr, e.g. when stdout is reserved for JSON output
+    stderr: bool,
+}
+
+impl IndicatifReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prints messages to stderr instead of stdout
+    pub fn stderr() -> Self {
+        Self {
+            stderr: true,
+            ..Self::default()
+        }
+    }
+
+    fn print(&self, message: &str) {
+        self.multi.suspend(|| match self.stderr {
+            true => eprintln!("{message}"),
+            false => println!("{message}"),
+        });
+    }
+}
+
+impl ProgressReporter for IndicatifReporter {
+    fn report(&self, event: ProgressEvent) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            ProgressEvent::Started {
+                task,
+                message,
+                total,
+            } => {
+                self.print(&message);
+                if let Some(total) = total {
+                    // sdkmanager only reports a percentage, everything else counts bytes
+                    let amount = match task {
+                        Task::SdkInstall => "{pos}%",
+                        _ => "{bytes}/{total_bytes} ({eta})",
+                    };
+                    let bar = self.multi.add(ProgressBar::new(total));
+                    bar.set_style(
+                        ProgressStyle::default_bar()
+                            .template(&format!(
+                                "{{spinner:.green}} [{{elapsed_precise}}] [{{bar:40.cyan/blue}}] {amount}"
+                            ))
+                            .unwrap()
+                            .progress_chars("#>-"),
+                    );
+                    bars.insert(task, bar);
+                }
+            }
+            ProgressEvent::Progress { task, current } => {
+                if let Some(bar) = bars.get(&task) {
+                    bar.set_position(current);
+                }
+            }
+            ProgressEvent::Finished { task, message } => {
+                if let Some(bar) = bars.remove(&task) {
+                    bar.finish_and_clear();
+                    self.multi.remove(&bar);
+                }
+                self.print(&message);
+            }
+            ProgressEvent::Message { message } => self.print(&message),
+        }
+    }
+}
+
+/// Writes every event as a line of JSON, for frontends reading the CLI output
+pub struct JsonReporter<W: Write + Send> {
+    writer: Mutex<W>,
+    /// Last time a progress event was written per task, to limit the output
+    last_progress: Mutex<HashMap<Task, Instant>>,
+}
+
+/// Minimum time between two progress events of the same task
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+impl<W: Write + Send> JsonReporter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            last_progress: Mutex::default(),
+        }
+    }
+}
+
+impl JsonReporter<std::io::Stderr> {
+    /// Reports to stderr, keeping stdout free for command output
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+}
+
+impl<W: Write + Send> ProgressReporter for JsonReporter<W> {
+    fn report(&self, event: ProgressEvent) {
+        if let ProgressEvent::Progress { task, .. } = &event {
+            let mut last_progress = self.last_progress.lock().unwrap();
+            let now = Instant::now();
+            if last_progress
+                .get(task)
+                .is_some_and(|last| now.duration_since(*last) < JSON_PROGRESS_INTERVAL)
+            {
+                return;
+            }
+            last_progress.insert(*task, now);
+        }
+
+        let Ok(line) = serde_json::to_string(&event) else {
+            return;
+        };
+        let mut writer = self.writer.lock().unwrap();
+        // Progress is best effort, a closed pipe must not fail the operation
+        let _ = writeln!(writer, "{line}").and_then(|_| writer.flush());
+    }
+}
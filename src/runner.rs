@@ -0,0 +1,595 @@
+//! Running the external tools of the Android SDK.
+//!
+//! Every invocation of adb, avdmanager, sdkmanager and the emulator goes through a
+//! [`ToolRunner`], so it can be replaced by a [`ScriptedRunner`] in tests.
+
+use std::{
+    collections::VecDeque,
+    ffi::{OsStr, OsString},
+    io::Read,
+    path::PathBuf,
+    process::{Child, ExitStatus, Stdio},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::error::{Context, Error};
+
+/// How long a spawned tool's output reader may keep running after the tool exited
+const PIPE_READER_GRACE: Duration = Duration::from_secs(1);
+
+/// Where the stdout of a started tool goes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// The terminal, like our own stdout
+    #[default]
+    Inherit,
+    /// Our stderr, keeping stdout free for results
+    Stderr,
+    /// Appended to a file, together with stderr
+    File(PathBuf),
+    /// Piped, together with stderr, into the stdin of another tool started alongside,
+    /// e.g. a log writer that rotates the file it appends to
+    Pipe(Box<ToolCommand>),
+}
+
+/// An invocation of a tool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCommand {
+    /// Name of the tool in errors, e.g. "adb"
+    pub tool: String,
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+    /// Kill the tool if it runs longer, only applies to captured and streamed runs
+    pub timeout: Option<Duration>,
+    /// Where stdout goes when the tool is spawned
+    pub stdout: OutputTarget,
+    /// Spawn the tool in the background, without stdin and in its own process group,
+    /// so it outlives us and does not get the terminal's Ctrl+C
+    pub detach: bool,
+}
+
+impl ToolCommand {
+    pub fn new(tool: &str, program: impl Into<PathBuf>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            program: program.into(),
+            args: Vec::new(),
+            timeout: None,
+            stdout: OutputTarget::default(),
+            detach: false,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn stdout(mut self, stdout: OutputTarget) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    pub fn detach(mut self) -> Self {
+        self.detach = true;
+        self
+    }
+
+    /// The command line, for showing to the user
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.as_os_str())
+            .chain(self.args.iter().map(OsString::as_os_str))
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn to_process(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
+
+/// Output of a tool that ran to completion
+#[derive(Debug, Clone)]
+pub struct ToolOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ToolOutput {
+    /// A successful run with the given stdout, for scripted runs
+    pub fn success(stdout: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: exit_status(0),
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// A failed run with the given exit code and stderr, for scripted runs
+    pub fn failure(code: i32, stderr: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: exit_status(code),
+            stdout: Vec::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    pub fn stdout_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Fails with [`Error::ToolFailed`] and the captured output if the tool failed
+    pub fn check(self, tool: &str) -> crate::Result<Self> {
+        if self.status.success() {
+            return Ok(self);
+        }
+        // Some tools, like avdmanager, print their errors to stdout
+        let output = match self.stderr.is_empty() {
+            true => &self.stdout,
+            false => &self.stderr,
+        };
+        Err(Error::ToolFailed {
+            tool: tool.to_string(),
+            status: self.status,
+            stderr: String::from_utf8_lossy(output).trim().to_string(),
+        })
+    }
+}
+
+/// A started tool
+pub trait ToolProcess: Send {
+    fn id(&self) -> u32;
+
+    fn wait(&mut self) -> crate::Result<ExitStatus>;
+
+    /// The exit status if the tool exited, without waiting
+    fn try_wait(&mut self) -> crate::Result<Option<ExitStatus>>;
+
+    fn kill(&mut self) -> crate::Result<()>;
+}
+
+/// Runs external tools
+pub trait ToolRunner: Send + Sync {
+    /// Runs a tool to completion with its stdout and stderr captured
+    fn output(&self, command: &ToolCommand) -> crate::Result<ToolOutput>;
+
+    /// Runs a tool to completion with stdin attached, so it can prompt the user.
+    /// Stdout is passed to `on_stdout` as it arrives, stderr is captured.
+    fn stream(
+        &self,
+        command: &ToolCommand,
+        on_stdout: &mut dyn FnMut(&[u8]),
+    ) -> crate::Result<ToolOutput>;
+
+    /// Starts a tool without waiting for it, attached to the terminal unless it is detached
+    fn spawn(&self, command: &ToolCommand) -> crate::Result<Box<dyn ToolProcess>>;
+}
+
+/// Helpers for running through a `&dyn ToolRunner`
+impl dyn ToolRunner + '_ {
+    /// Runs a tool and returns its stdout, fails with its stderr if it exits with an error
+    pub fn run(&self, command: &ToolCommand) -> crate::Result<String> {
+        Ok(self.output(command)?.check(&command.tool)?.stdout_lossy())
+    }
+}
+
+/// Runs tools as child processes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl ToolRunner for SystemRunner {
+    fn output(&self, command: &ToolCommand) -> crate::Result<ToolOutput> {
+        let mut child = command
+            .to_process()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", command.tool))?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+        let status = wait_with_timeout(&mut child, command)?;
+        Ok(ToolOutput {
+            status,
+            stdout: join_output(stdout),
+            stderr: join_output(stderr),
+        })
+    }
+
+    fn stream(
+        &self,
+        command: &ToolCommand,
+        on_stdout: &mut dyn FnMut(&[u8]),
+    ) -> crate::Result<ToolOutput> {
+        let mut child = command
+            .to_process()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", command.tool))?;
+        let stderr = read_in_background(child.stderr.take());
+        let chunks = read_chunks_in_background(child.stdout.take().expect("stdout is piped"));
+
+        // Reading blocks until the tool writes, so the deadline is checked between chunks
+        let deadline = command.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let chunk = match deadline {
+                Some(deadline) => {
+                    chunks.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => chunks.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match chunk {
+                Ok(chunk) => on_stdout(
+                    &chunk.with_context(|| format!("Failed to read {} output", command.tool))?,
+                ),
+                Err(RecvTimeoutError::Disconnected) => break,
+                // Not waiting for the readers, something the tool started may hold its pipes
+                Err(RecvTimeoutError::Timeout) => return Err(kill_timed_out(&mut child, command)),
+            }
+        }
+
+        let status = wait_until(&mut child, command, deadline)?;
+        Ok(ToolOutput {
+            status,
+            stdout: Vec::new(),
+            stderr: join_output(stderr),
+        })
+    }
+
+    fn spawn(&self, command: &ToolCommand) -> crate::Result<Box<dyn ToolProcess>> {
+        let mut process = command.to_process();
+        let mut reader = None;
+        match &command.stdout {
+            OutputTarget::Inherit => {}
+            OutputTarget::Stderr => {
+                process.stdout(std::io::stderr());
+            }
+            OutputTarget::File(path) => {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                process.stdout(file.try_clone()?).stderr(file);
+            }
+            OutputTarget::Pipe(reader_command) => {
+                let (pipe_reader, pipe_writer) = std::io::pipe()?;
+                process.stdout(pipe_writer.try_clone()?).stderr(pipe_writer);
+                let mut reader_process = reader_command.to_process();
+                reader_process
+                    .stdin(pipe_reader)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                if reader_command.detach {
+                    detach(&mut reader_process);
+                }
+                // Exits by itself once the tool closes its end of the pipe
+                reader = Some(
+                    reader_process
+                        .spawn()
+                        .with_context(|| format!("Failed to start {}", reader_command.tool))?,
+                );
+            }
+        }
+        if command.detach {
+            process.stdin(Stdio::null());
+            detach(&mut process);
+        }
+        let child = process
+            .spawn()
+            .with_context(|| format!("Failed to start {}", command.tool))?;
+        Ok(Box::new(SystemProcess {
+            child,
+            reader,
+            exited: None,
+        }))
+    }
+}
+
+struct SystemProcess {
+    child: Child,
+    /// The tool reading the output of [`OutputTarget::Pipe`]
+    reader: Option<Child>,
+    /// When the tool exited while the reader was still writing its output
+    exited: Option<(ExitStatus, Instant)>,
+}
+
+impl SystemProcess {
+    /// Whether the reader is done with the output, or took too long because something
+    /// the tool started still holds the pipe open
+    fn reader_done(&mut self, exited_at: Instant) -> crate::Result<bool> {
+        Ok(match &mut self.reader {
+            Some(reader) => {
+                reader.try_wait()?.is_some() || exited_at.elapsed() >= PIPE_READER_GRACE
+            }
+            None => true,
+        })
+    }
+}
+
+impl ToolProcess for SystemProcess {
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn wait(&mut self) -> crate::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        let exited_at = Instant::now();
+        while !self.reader_done(exited_at)? {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Ok(status)
+    }
+
+    /// Only reports the tool as exited once the reader of its output is done, so the
+    /// output is complete
+    fn try_wait(&mut self) -> crate::Result<Option<ExitStatus>> {
+        let (status, exited_at) = match self.exited {
+            Some(exited) => exited,
+            None => match self.child.try_wait()? {
+                Some(status) => *self.exited.insert((status, Instant::now())),
+                None => return Ok(None),
+            },
+        };
+        Ok(self.reader_done(exited_at)?.then_some(status))
+    }
+
+    fn kill(&mut self) -> crate::Result<()> {
+        Ok(self.child.kill()?)
+    }
+}
+
+#[cfg(unix)]
+fn detach(process: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    process.process_group(0);
+}
+
+#[cfg(windows)]
+fn detach(process: &mut std::process::Command) {
+    use std::os::windows::process::CommandExt;
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    process.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+/// Reads a pipe on another thread, so a tool filling both pipes cannot block
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> Option<JoinHandle<Vec<u8>>> {
+    pipe.map(|mut pipe| {
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = pipe.read_to_end(&mut output);
+            output
+        })
+    })
+}
+
+fn join_output(handle: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
+/// Reads a pipe on another thread and sends what arrives, so the reader can stop waiting
+fn read_chunks_in_background<R: Read + Send + 'static>(
+    mut pipe: R,
+) -> Receiver<std::io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        loop {
+            let chunk = match pipe.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(n) => Ok(buffer[..n].to_vec()),
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.send(chunk).is_err() || failed {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn wait_with_timeout(child: &mut Child, command: &ToolCommand) -> crate::Result<ExitStatus> {
+    let deadline = command.timeout.map(|timeout| Instant::now() + timeout);
+    wait_until(child, command, deadline)
+}
+
+fn wait_until(
+    child: &mut Child,
+    command: &ToolCommand,
+    deadline: Option<Instant>,
+) -> crate::Result<ExitStatus> {
+    let Some(deadline) = deadline else {
+        return Ok(child.wait()?);
+    };
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            return Err(kill_timed_out(child, command));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn kill_timed_out(child: &mut Child, command: &ToolCommand) -> Error {
+    // Already exited if killing fails, the status is not needed either way
+    let _ = child.kill();
+    let _ = child.wait();
+    Error::ToolTimedOut {
+        tool: command.tool.clone(),
+        timeout: command.timeout.unwrap_or_default(),
+    }
+}
+
+/// Answers tool invocations from a script instead of running them, and records them
+#[derive(Debug, Default)]
+pub struct ScriptedRunner {
+    responses: Mutex<VecDeque<ScriptedResponse>>,
+    calls: Mutex<Vec<ToolCommand>>,
+}
+
+#[derive(Debug)]
+struct ScriptedResponse {
+    tool: String,
+    /// The invocation must start with these arguments
+    args: Vec<String>,
+    output: ToolOutput,
+    /// How many times a spawned process reports that it is still running
+    running_polls: usize,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a response for the next invocation of `tool` whose arguments start with `args`.
+    /// Responses are used once, in the order they were added.
+    pub fn respond(self, tool: &str, args: &[&str], output: ToolOutput) -> Self {
+        self.respond_running(tool, args, 0, output)
+    }
+
+    /// Like [`respond`](Self::respond), for a spawned tool that keeps running for the first
+    /// `polls` calls of [`ToolProcess::try_wait`] before it exits with the output's status.
+    /// `usize::MAX` keeps it running for good, like a healthy detached emulator.
+    pub fn respond_running(
+        self,
+        tool: &str,
+        args: &[&str],
+        polls: usize,
+        output: ToolOutput,
+    ) -> Self {
+        self.responses.lock().unwrap().push_back(ScriptedResponse {
+            tool: tool.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            output,
+            running_polls: polls,
+        });
+        self
+    }
+
+    /// The invocations so far, in order
+    pub fn calls(&self) -> Vec<ToolCommand> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Whether every scripted response was used
+    pub fn is_done(&self) -> bool {
+        self.responses.lock().unwrap().is_empty()
+    }
+
+    fn next(&self, command: &ToolCommand) -> crate::Result<ScriptedResponse> {
+        self.calls.lock().unwrap().push(command.clone());
+
+        let mut responses = self.responses.lock().unwrap();
+        let matches = |response: &ScriptedResponse| {
+            response.tool == command.tool
+                && response.args.len() <= command.args.len()
+                && response
+                    .args
+                    .iter()
+                    .zip(&command.args)
+                    .all(|(expected, arg)| OsStr::new(expected) == arg)
+        };
+        let index = responses
+            .iter()
+            .position(matches)
+            .with_context(|| format!("Unexpected call: {}", command.command_line()))?;
+        Ok(responses.remove(index).expect("index is in bounds"))
+    }
+}
+
+impl ToolRunner for ScriptedRunner {
+    fn output(&self, command: &ToolCommand) -> crate::Result<ToolOutput> {
+        Ok(self.next(command)?.output)
+    }
+
+    fn stream(
+        &self,
+        command: &ToolCommand,
+        on_stdout: &mut dyn FnMut(&[u8]),
+    ) -> crate::Result<ToolOutput> {
+        let mut output = self.next(command)?.output;
+        on_stdout(&std::mem::take(&mut output.stdout));
+        Ok(output)
+    }
+
+    fn spawn(&self, command: &ToolCommand) -> crate::Result<Box<dyn ToolProcess>> {
+        let response = self.next(command)?;
+        Ok(Box::new(ScriptedProcess {
+            status: response.output.status,
+            running_polls: response.running_polls,
+        }))
+    }
+}
+
+struct ScriptedProcess {
+    status: ExitStatus,
+    running_polls: usize,
+}
+
+impl ToolProcess for ScriptedProcess {
+    fn id(&self) -> u32 {
+        0
+    }
+
+    fn wait(&mut self) -> crate::Result<ExitStatus> {
+        Ok(self.status)
+    }
+
+    fn try_wait(&mut self) -> crate::Result<Option<ExitStatus>> {
+        if self.running_polls > 0 {
+            self.running_polls -= 1;
+            return Ok(None);
+        }
+        Ok(Some(self.status))
+    }
+
+    fn kill(&mut self) -> crate::Result<()> {
+        self.running_polls = 0;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
@@ -0,0 +1,111 @@
+//! Installing the Android SDK tools, emulator and system images.
+
+use std::io::Cursor;
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    constants::{
+        self, ANDROID_SDK_TOOLS, adb_path, android_sdk_path, cmdline_tools_path, emulator_path,
+    },
+    downloader,
+    error::{Context, Error},
+    progress::{ProgressReporter, Task},
+    runner::{ToolCommand, ToolRunner},
+};
+
+/// Whether the SDK command line tools (sdkmanager, avdmanager) are installed
+pub fn cmdline_tools_installed() -> bool {
+    constants::sdkmanager_path().exists()
+}
+
+/// Whether the emulator, adb and a system image are installed
+pub fn is_image_installed(image: &str) -> bool {
+    emulator_path().exists()
+        && adb_path().exists()
+        && android_sdk_path().join(image.replace(";", "/")).exists()
+}
+
+/// Downloads the SDK command line tools into `{sdk}/cmdline-tools/latest`
+pub fn install_cmdline_tools(reporter: &dyn ProgressReporter) -> crate::Result<()> {
+    let mut zip_tmp = BytesMut::new().writer();
+    downloader::download_with_progress(None, ANDROID_SDK_TOOLS, &mut zip_tmp, reporter)
+        .context("Failed to download Android SDK Tools")?;
+
+    let zip_cursor = Cursor::new(zip_tmp.into_inner());
+
+    let mut zip = zip::ZipArchive::new(zip_cursor).context("Failed to read downloaded zip file")?;
+    let dest = cmdline_tools_path();
+    reporter.start(
+        Task::Extract,
+        format!("Extracting Android SDK Tools to {}", dest.display()),
+        None,
+    );
+    zip.extract_unwrapped_root_dir(&dest, zip::read::root_dir_common_filter)
+        .context("Failed to extract Android SDK Tools")?;
+    reporter.finish(Task::Extract, "Extracted Android SDK Tools");
+    Ok(())
+}
+
+/// Installs the emulator, platform tools (adb) and a system image with sdkmanager
+pub fn install(
+    runner: &dyn ToolRunner,
+    image: &str,
+    reporter: &dyn ProgressReporter,
+) -> crate::Result<()> {
+    let sdk_manager = constants::sdkmanager_path();
+    if !sdk_manager.exists() {
+        return Err(Error::ToolNotFound {
+            tool: "sdkmanager".to_string(),
+            path: sdk_manager,
+        });
+    }
+
+    // sdkmanager draws its progress bars with carriage returns,
+    // stdin stays attached so licenses can still be accepted
+    let command =
+        ToolCommand::new("sdkmanager", sdk_manager).args(["emulator", "platform-tools", image]);
+    reporter.start(Task::SdkInstall, format!("Installing {image}"), Some(100));
+    let mut line = Vec::new();
+    runner
+        .stream(&command, &mut |output| {
+            for &byte in output {
+                if byte == b'\r' || byte == b'\n' {
+                    report_sdkmanager_line(&line, reporter);
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+            // Prompts such as license agreements wait for input without ending the line
+            if line.ends_with(b": ") {
+                report_sdkmanager_line(&line, reporter);
+                line.clear();
+            }
+        })?
+        .check("sdkmanager")?;
+    report_sdkmanager_line(&line, reporter);
+
+    reporter.finish(Task::SdkInstall, format!("Installed {image}"));
+    Ok(())
+}
+
+/// Reports the percentage of sdkmanager progress lines such as
+/// `[=====        ] 40% Downloading x.zip...`, other lines as messages
+fn report_sdkmanager_line(line: &[u8], reporter: &dyn ProgressReporter) {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+
+    let percent = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(_, rest)| rest.trim_start().split_once('%'))
+        .and_then(|(percent, _)| percent.parse().ok());
+    match percent {
+        Some(percent) => reporter.progress(Task::SdkInstall, percent),
+        None => reporter.message(line),
+    }
+}
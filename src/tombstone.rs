@@ -0,0 +1,385 @@
+//! Native crash reports and their symbolication.
+//!
+//! When a process crashes natively, Android writes a tombstone to `/data/tombstones`.
+//! Its backtrace only has addresses into the libraries, e.g. `libil2cpp.so`, which are
+//! stripped on the device. [`Symbols`] resolves them to functions and source lines using
+//! the unstripped libraries of the mod or game build.
+
+use std::{
+    borrow::Cow,
+    cell::OnceCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use object::{Object, read::ReadCache};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    adb::device_command,
+    error::{Context, Error, bail},
+    runner::ToolRunner,
+};
+
+/// Where Android writes tombstones, readable with `adb root`
+pub const TOMBSTONE_DIR: &str = "/data/tombstones";
+
+/// A crash report written by crash_dump
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Tombstone {
+    /// The file name on the device, e.g. `tombstone_03`
+    pub name: Option<String>,
+    /// The command line of the crashed process, usually the package name
+    pub process: Option<String>,
+    pub pid: Option<i32>,
+    /// The thread that crashed, e.g. `UnityMain`
+    pub thread: Option<String>,
+    /// e.g. `signal 11 (SIGSEGV), code 1 (SEGV_MAPERR), fault addr 0x0000000000000000`
+    pub signal: Option<String>,
+    pub abort_message: Option<String>,
+    /// e.g. `arm64`
+    pub abi: Option<String>,
+    /// The backtrace of the crashed thread
+    pub frames: Vec<Frame>,
+}
+
+/// A line of a tombstone backtrace, e.g.
+/// `#00 pc 0000000000042f10  /data/app/.../lib/arm64/libil2cpp.so (BuildId: 12ab)`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    pub index: u32,
+    /// Address relative to the start of the library
+    pub pc: u64,
+    /// Path of the library on the device
+    pub path: String,
+    /// The symbol crash_dump found in the stripped library, with its offset
+    pub symbol: Option<String>,
+    pub build_id: Option<String>,
+}
+
+impl Frame {
+    /// The library file name, also for libraries loaded from inside an APK
+    /// (`base.apk!libil2cpp.so`)
+    pub fn library(&self) -> &str {
+        let path = self.path.rsplit('!').next().unwrap_or(&self.path);
+        path.rsplit('/').next().unwrap_or(path)
+    }
+}
+
+impl Tombstone {
+    pub fn parse(text: &str) -> crate::Result<Self> {
+        let mut tombstone = Tombstone::default();
+        let mut lines = text.lines().map(str::trim);
+        while let Some(line) = lines.next() {
+            if let Some(rest) = line.strip_prefix("pid: ") {
+                parse_process_line(&mut tombstone, rest);
+            } else if line.starts_with("signal ") && tombstone.signal.is_none() {
+                tombstone.signal = Some(line.to_string());
+            } else if let Some(message) = line.strip_prefix("Abort message: ") {
+                tombstone.abort_message = Some(unquote(message).to_string());
+            } else if let Some(abi) = line.strip_prefix("ABI: ") {
+                tombstone.abi = Some(unquote(abi).to_string());
+            } else if line == "backtrace:" {
+                // Only the crashed thread, other threads follow after a blank line
+                tombstone.frames = lines
+                    .by_ref()
+                    .take_while(|line| !line.is_empty())
+                    .filter_map(parse_frame)
+                    .collect();
+                break;
+            }
+        }
+        if tombstone.frames.is_empty() {
+            bail!("The tombstone has no backtrace");
+        }
+        Ok(tombstone)
+    }
+}
+
+/// `4321, tid: 4390, name: UnityMain  >>> com.beatgames.beatsaber <<<`
+fn parse_process_line(tombstone: &mut Tombstone, line: &str) {
+    let mut fields = line.split(", ");
+    tombstone.pid = fields.next().and_then(|pid| pid.parse().ok());
+    for field in fields {
+        if let Some(name) = field.strip_prefix("name: ") {
+            let (thread, process) = name.split_once(">>>").unwrap_or((name, ""));
+            tombstone.thread = Some(thread.trim().to_string());
+            tombstone.process = process
+                .trim()
+                .strip_suffix("<<<")
+                .map(|process| process.trim().to_string());
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+        .unwrap_or(value)
+}
+
+fn parse_frame(line: &str) -> Option<Frame> {
+    let line = line.strip_prefix('#')?;
+    let (index, rest) = line.split_once(' ')?;
+    let rest = rest.trim_start().strip_prefix("pc ")?.trim_start();
+    let (pc, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let rest = rest.trim_start();
+    let (path, rest) = rest
+        .split_once(" (")
+        .map_or((rest, ""), |(path, rest)| (path, rest));
+
+    let mut frame = Frame {
+        index: index.parse().ok()?,
+        pc: u64::from_str_radix(pc, 16).ok()?,
+        path: path.trim().to_string(),
+        symbol: None,
+        build_id: None,
+    };
+    // Groups in parentheses after the path: the symbol, `offset 0x1000` and `BuildId: ...`
+    let rest = format!("({rest}");
+    for group in parenthesized(&rest) {
+        if let Some(build_id) = group.strip_prefix("BuildId: ") {
+            frame.build_id = Some(build_id.to_string());
+        } else if !group.starts_with("offset ") {
+            frame.symbol = Some(group.to_string());
+        }
+    }
+    Some(frame)
+}
+
+/// The top level `(...)` groups in `text`, allowing parentheses in C++ signatures
+fn parenthesized(text: &str) -> Vec<&str> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, char) in text.char_indices() {
+        match char {
+            '(' => {
+                if depth == 0 {
+                    start = index + 1;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push(&text[start..index]);
+                }
+            }
+            _ => {}
+        }
+    }
+    groups
+}
+
+/// Names of the text tombstones on the device, newest first.
+/// Android 12 and later also write `.pb` protobuf versions, which are skipped
+pub fn list(runner: &dyn ToolRunner, serial: &str) -> crate::Result<Vec<String>> {
+    let output = runner
+        .run(&device_command(
+            serial,
+            ["shell", "ls", "-t", TOMBSTONE_DIR],
+        ))
+        .with_context(|| {
+            format!("Failed to list {TOMBSTONE_DIR}, it is only readable after `adb root`")
+        })?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|name| name.starts_with("tombstone_") && !name.ends_with(".pb"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads a tombstone from the device
+pub fn pull(runner: &dyn ToolRunner, serial: &str, name: &str) -> crate::Result<String> {
+    let path = format!("{TOMBSTONE_DIR}/{name}");
+    runner
+        .run(&device_command(serial, ["exec-out", "cat", &path]))
+        .with_context(|| format!("Failed to read {path}"))
+}
+
+/// How a frame was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolStatus {
+    Symbolicated,
+    /// No local library has the frame's file name
+    MissingLibrary,
+    /// The local library is from a different build than the one that crashed
+    BuildIdMismatch,
+    /// The local library has no symbol or debug info for the address
+    NoDebugInfo,
+}
+
+/// A function at a frame's address. One address has several when functions were inlined,
+/// innermost first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SymbolicatedFrame {
+    #[serde(flatten)]
+    pub frame: Frame,
+    pub status: SymbolStatus,
+    /// The local library the frame was resolved with
+    pub symbols: Option<PathBuf>,
+    pub locations: Vec<SourceLocation>,
+}
+
+/// A local library with symbols, loaded on first use since unstripped game libraries
+/// can be hundreds of megabytes
+struct Library {
+    path: PathBuf,
+    build_id: Option<String>,
+    loader: OnceCell<addr2line::Loader>,
+}
+
+/// Unstripped libraries to resolve frames with, found by file name
+#[derive(Default)]
+pub struct Symbols {
+    libraries: HashMap<String, Vec<Library>>,
+}
+
+impl Symbols {
+    /// Indexes library files, and the `.so` files in folders
+    pub fn load(paths: &[PathBuf]) -> crate::Result<Self> {
+        let mut symbols = Self::default();
+        for path in paths {
+            if path.is_dir() {
+                for entry in WalkDir::new(path) {
+                    let entry = entry.map_err(Error::external)?;
+                    if entry.file_type().is_file()
+                        && entry.path().extension().is_some_and(|ext| ext == "so")
+                    {
+                        symbols.add(entry.path())?;
+                    }
+                }
+            } else {
+                symbols.add(path)?;
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn add(&mut self, path: &Path) -> crate::Result<()> {
+        let name = path
+            .file_name()
+            .with_context(|| format!("{} is not a file", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let library = Library {
+            path: path.to_path_buf(),
+            build_id: read_build_id(path)?,
+            loader: OnceCell::new(),
+        };
+        self.libraries.entry(name).or_default().push(library);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.libraries.is_empty()
+    }
+
+    /// Resolves a frame with the library of the same name and build
+    pub fn symbolicate(&self, frame: &Frame) -> crate::Result<SymbolicatedFrame> {
+        let mut result = SymbolicatedFrame {
+            frame: frame.clone(),
+            status: SymbolStatus::MissingLibrary,
+            symbols: None,
+            locations: Vec::new(),
+        };
+        let Some(candidates) = self.libraries.get(frame.library()) else {
+            return Ok(result);
+        };
+        let matching = candidates.iter().find(|library| {
+            frame.build_id.is_none()
+                || library.build_id.is_none()
+                || library.build_id == frame.build_id
+        });
+        let Some(library) = matching else {
+            result.status = SymbolStatus::BuildIdMismatch;
+            result.symbols = Some(candidates[0].path.clone());
+            return Ok(result);
+        };
+        result.symbols = Some(library.path.clone());
+
+        let loader = match library.loader.get() {
+            Some(loader) => loader,
+            None => {
+                let loader = addr2line::Loader::new(&library.path).map_err(|e| {
+                    Error::msg(format!(
+                        "Failed to load symbols from {}: {e}",
+                        library.path.display()
+                    ))
+                })?;
+                library.loader.get_or_init(|| loader)
+            }
+        };
+        // Frames above the crashing one are return addresses, the call is the instruction before
+        let probe = match frame.index {
+            0 => frame.pc,
+            _ => frame.pc.saturating_sub(1),
+        };
+        result.locations = locations(loader, probe).map_err(|e| {
+            Error::msg(format!(
+                "Failed to read debug info of {}: {e}",
+                library.path.display()
+            ))
+        })?;
+        result.status = match result.locations.is_empty() {
+            true => SymbolStatus::NoDebugInfo,
+            false => SymbolStatus::Symbolicated,
+        };
+        Ok(result)
+    }
+}
+
+/// The functions and source lines at an address, from DWARF or else from the symbol table
+fn locations(
+    loader: &addr2line::Loader,
+    probe: u64,
+) -> Result<Vec<SourceLocation>, Box<dyn std::error::Error>> {
+    let mut locations = Vec::new();
+    let mut frames = loader.find_frames(probe)?;
+    while let Some(frame) = frames.next()? {
+        let function = frame
+            .function
+            .as_ref()
+            .and_then(|function| function.demangle().ok())
+            .map(Cow::into_owned);
+        let location = frame.location.as_ref();
+        locations.push(SourceLocation {
+            function,
+            file: location.and_then(|l| l.file).map(str::to_string),
+            line: location.and_then(|l| l.line),
+        });
+    }
+    if locations.is_empty()
+        && let Some(symbol) = loader.find_symbol(probe)
+    {
+        locations.push(SourceLocation {
+            function: Some(addr2line::demangle_auto(Cow::Borrowed(symbol), None).into_owned()),
+            file: None,
+            line: None,
+        });
+    }
+    Ok(locations)
+}
+
+/// The GNU build ID of an ELF file as hex, like tombstones show it
+fn read_build_id(path: &Path) -> crate::Result<Option<String>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let cache = ReadCache::new(file);
+    let object = object::File::parse(&cache)
+        .map_err(|e| Error::msg(format!("{} is not a library: {e}", path.display())))?;
+    let build_id = object.build_id().map_err(Error::external)?;
+    Ok(build_id.map(|id| id.iter().map(|byte| format!("{byte:02x}")).collect()))
+}
@@ -0,0 +1,115 @@
+//! adb queries answered by a [`ScriptedRunner`].
+
+use quest_emu::{
+    adb::{self, AbiCompatibility, DeviceAbis},
+    error::Error,
+    runner::{ScriptedRunner, ToolOutput},
+};
+
+const SERIAL: &str = "emulator-5554";
+const DEVICES: &str =
+    "List of devices attached\nemulator-5554\tdevice\nR3CN80XYZ\tunauthorized\n\n";
+
+#[test]
+fn lists_only_ready_devices() {
+    let runner = ScriptedRunner::new().respond("adb", &["devices"], ToolOutput::success(DEVICES));
+
+    assert_eq!(adb::connected_devices(&runner).unwrap(), ["emulator-5554"]);
+    assert!(runner.is_done());
+}
+
+#[test]
+fn no_device() {
+    let runner = ScriptedRunner::new().respond(
+        "adb",
+        &["devices"],
+        ToolOutput::success("List of devices attached\n\n"),
+    );
+
+    assert!(matches!(adb::device(&runner), Err(Error::NoDevice)));
+}
+
+#[test]
+fn requested_device_must_be_connected() {
+    let devices = ["emulator-5554".to_string(), "emulator-5556".to_string()];
+
+    assert_eq!(
+        adb::pick_device(&devices, Some("emulator-5556")).unwrap(),
+        "emulator-5556"
+    );
+    // Never falls back to the only connected device
+    match adb::pick_device(&devices[..1], Some("R3CN80XYZ")) {
+        Err(Error::DeviceNotFound(serial)) => assert_eq!(serial, "R3CN80XYZ"),
+        result => panic!("expected DeviceNotFound, got {result:?}"),
+    }
+    assert!(matches!(
+        adb::pick_device(&devices, None),
+        Err(Error::MultipleDevices(_))
+    ));
+}
+
+#[test]
+fn queries_abis_and_native_bridge() {
+    let runner = ScriptedRunner::new()
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "getprop", "ro.product.cpu.abilist"],
+            ToolOutput::success("x86_64,arm64-v8a\n"),
+        )
+        .respond(
+            "adb",
+            &[
+                "-s",
+                SERIAL,
+                "shell",
+                "getprop",
+                "ro.dalvik.vm.native.bridge",
+            ],
+            ToolOutput::success("0\n"),
+        );
+
+    let abis = DeviceAbis::query(&runner, SERIAL).unwrap();
+
+    assert_eq!(abis.abilist, ["x86_64", "arm64-v8a"]);
+    assert_eq!(abis.native_bridge, None);
+    assert_eq!(runner.calls().len(), 2);
+}
+
+#[test]
+fn disabled_bridge_diagnosis_shows_the_property() {
+    for property in ["0", ""] {
+        let device = DeviceAbis {
+            abilist: vec!["x86_64".to_string(), "arm64-v8a".to_string()],
+            native_bridge: None,
+            native_bridge_property: property.to_string(),
+        };
+        let compatibility = AbiCompatibility::check(&["arm64-v8a".to_string()], &device);
+
+        assert!(matches!(
+            compatibility,
+            AbiCompatibility::BridgeDisabled { .. }
+        ));
+        let diagnosis = compatibility.diagnosis(&device);
+        assert!(
+            diagnosis.contains(&format!("ro.dalvik.vm.native.bridge=\"{property}\"")),
+            "{diagnosis}"
+        );
+    }
+}
+
+#[test]
+fn failure_keeps_stderr() {
+    let runner = ScriptedRunner::new().respond(
+        "adb",
+        &["-s", SERIAL, "shell"],
+        ToolOutput::failure(1, "adb: device offline\n"),
+    );
+
+    match adb::getprop(&runner, SERIAL, "ro.product.model") {
+        Err(Error::ToolFailed { tool, stderr, .. }) => {
+            assert_eq!(tool, "adb");
+            assert_eq!(stderr, "adb: device offline");
+        }
+        result => panic!("expected ToolFailed, got {result:?}"),
+    }
+}
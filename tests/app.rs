@@ -0,0 +1,255 @@
+//! am and pm output answered by a [`ScriptedRunner`].
+
+use quest_emu::{
+    app::{self, Package},
+    error::Error,
+    runner::{ScriptedRunner, ToolOutput},
+};
+
+const SERIAL: &str = "emulator-5554";
+const PACKAGE: &str = "com.beatgames.beatsaber";
+
+fn installed(runner: ScriptedRunner) -> ScriptedRunner {
+    runner.respond(
+        "adb",
+        &["-s", SERIAL, "shell", "pm", "path", PACKAGE],
+        ToolOutput::success("package:/data/app/~~x/com.beatgames.beatsaber-1/base.apk\n"),
+    )
+}
+
+fn resolve(category: &str, output: &str) -> ScriptedRunner {
+    installed(ScriptedRunner::new()).respond(
+        "adb",
+        &[
+            "-s",
+            SERIAL,
+            "shell",
+            "cmd",
+            "package",
+            "resolve-activity",
+            "--brief",
+            "-a",
+            "android.intent.action.MAIN",
+            "-c",
+            category,
+        ],
+        ToolOutput::success(output),
+    )
+}
+
+#[test]
+fn launches_vr_activity() {
+    let component = "com.beatgames.beatsaber/com.unity3d.player.UnityPlayerActivity";
+    let runner = resolve("android.intent.category.LAUNCHER", "No activity found\n")
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "cmd", "package", "resolve-activity"],
+            ToolOutput::success(format!("priority=0 preferredOrder=0\n{component}\n")),
+        )
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "am", "start", "-n", component],
+            ToolOutput::success(format!("Starting: Intent {{ cmp={component} }}\n")),
+        );
+
+    assert_eq!(app::launch(&runner, SERIAL, PACKAGE).unwrap(), component);
+    assert!(runner.is_done());
+    let calls = runner.calls();
+    assert!(
+        calls[2]
+            .command_line()
+            .contains("com.oculus.intent.category.VR")
+    );
+}
+
+#[test]
+fn launch_reports_am_error() {
+    let runner = resolve(
+        "android.intent.category.LAUNCHER",
+        "com.beatgames.beatsaber/.Missing\n",
+    )
+    .respond(
+        "adb",
+        &["-s", SERIAL, "shell", "am", "start"],
+        ToolOutput::success(
+            "Starting: Intent { cmp=com.beatgames.beatsaber/.Missing }\n\
+             Error type 3\n\
+             Error: Activity class {com.beatgames.beatsaber/.Missing} does not exist.\n",
+        ),
+    );
+
+    match app::launch(&runner, SERIAL, PACKAGE) {
+        Err(Error::AppCommandFailed { command, reason }) => {
+            assert_eq!(command, "am start");
+            assert_eq!(
+                reason,
+                "Activity class {com.beatgames.beatsaber/.Missing} does not exist."
+            );
+        }
+        result => panic!("expected AppCommandFailed, got {result:?}"),
+    }
+}
+
+#[test]
+fn no_launch_activity() {
+    let runner = resolve("android.intent.category.LAUNCHER", "No activity found\n").respond(
+        "adb",
+        &["-s", SERIAL, "shell", "cmd", "package", "resolve-activity"],
+        ToolOutput::success("No activity found\n"),
+    );
+
+    assert!(matches!(
+        app::launch(&runner, SERIAL, PACKAGE),
+        Err(Error::NoLaunchActivity(package)) if package == PACKAGE
+    ));
+}
+
+#[test]
+fn not_installed() {
+    let runner = ScriptedRunner::new().respond(
+        "adb",
+        &["-s", SERIAL, "shell", "pm", "path"],
+        ToolOutput::failure(1, ""),
+    );
+
+    assert!(matches!(
+        app::stop(&runner, SERIAL, PACKAGE),
+        Err(Error::PackageNotInstalled(package)) if package == PACKAGE
+    ));
+    assert_eq!(runner.calls().len(), 1);
+}
+
+#[test]
+fn offline_device_is_not_a_missing_package() {
+    let runner = ScriptedRunner::new().respond(
+        "adb",
+        &["-s", SERIAL],
+        ToolOutput::failure(1, "error: device offline\n"),
+    );
+
+    assert!(matches!(
+        app::is_installed(&runner, SERIAL, PACKAGE),
+        Err(Error::ToolFailed { .. })
+    ));
+}
+
+#[test]
+fn uninstall_reports_pm_failure() {
+    let runner = installed(ScriptedRunner::new()).respond(
+        "adb",
+        &["-s", SERIAL, "shell", "pm", "uninstall", "-k", PACKAGE],
+        ToolOutput::success("Failure [DELETE_FAILED_DEVICE_POLICY_MANAGER]\n"),
+    );
+
+    match app::uninstall(&runner, SERIAL, PACKAGE, true) {
+        Err(Error::AppCommandFailed { command, reason }) => {
+            assert_eq!(command, "pm uninstall");
+            assert_eq!(reason, "DELETE_FAILED_DEVICE_POLICY_MANAGER");
+        }
+        result => panic!("expected AppCommandFailed, got {result:?}"),
+    }
+}
+
+#[test]
+fn clear_succeeds_and_fails() {
+    let runner = installed(installed(ScriptedRunner::new()))
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "pm", "clear", PACKAGE],
+            ToolOutput::success("Success\n"),
+        )
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "pm", "clear", PACKAGE],
+            ToolOutput::failure(1, "Failed\n"),
+        );
+
+    app::clear(&runner, SERIAL, PACKAGE).unwrap();
+    assert!(matches!(
+        app::clear(&runner, SERIAL, PACKAGE),
+        Err(Error::AppCommandFailed { reason, .. }) if reason == "Failed"
+    ));
+}
+
+#[test]
+fn lists_third_party_packages() {
+    let runner = ScriptedRunner::new()
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "pm", "list", "packages", "-3"],
+            ToolOutput::success(
+                "package:com.beatgames.beatsaber versionCode:1130\n\
+                 package:com.example.modloader versionCode:7\n",
+            ),
+        )
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "dumpsys", "package", PACKAGE],
+            ToolOutput::success("Packages:\n  Package [com.beatgames.beatsaber]\n    versionCode=1130 minSdk=29\n    versionName=1.40.8_7379\n"),
+        )
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "dumpsys", "package", "com.example.modloader"],
+            ToolOutput::success("Unable to find package: com.example.modloader\n"),
+        );
+
+    assert_eq!(
+        app::list(&runner, SERIAL).unwrap(),
+        [
+            Package {
+                name: PACKAGE.to_string(),
+                version_code: Some(1130),
+                version_name: Some("1.40.8_7379".to_string()),
+            },
+            Package {
+                name: "com.example.modloader".to_string(),
+                version_code: Some(7),
+                version_name: None,
+            },
+        ]
+    );
+    assert!(runner.is_done());
+}
+
+#[test]
+fn quotes_inner_class_component() {
+    let component = "com.beatgames.beatsaber/.Outer$Inner";
+    let runner = resolve(
+        "android.intent.category.LAUNCHER",
+        &format!("{component}\n"),
+    )
+    .respond(
+        "adb",
+        &[
+            "-s",
+            SERIAL,
+            "shell",
+            "am",
+            "start",
+            "-n",
+            &format!("'{component}'"),
+        ],
+        ToolOutput::success(format!("Starting: Intent {{ cmp={component} }}\n")),
+    );
+
+    assert_eq!(app::launch(&runner, SERIAL, PACKAGE).unwrap(), component);
+    assert!(runner.is_done());
+}
+
+#[test]
+fn rejects_invalid_package_names() {
+    for package in [
+        "com.example;reboot",
+        "x /data",
+        "beatsaber",
+        "com..example",
+        "com.it's",
+    ] {
+        let runner = ScriptedRunner::new();
+        assert!(matches!(
+            app::stop(&runner, SERIAL, package),
+            Err(Error::InvalidPackageName(name)) if name == package
+        ));
+        assert!(runner.calls().is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+//! App backups with adb answered by a [`ScriptedRunner`].
+
+use quest_emu::{
+    backup::{self, BackupManifest, BackupPart, MANIFEST_FILE},
+    error::Error,
+    progress::SilentReporter,
+    runner::{ScriptedRunner, ToolOutput},
+};
+
+const SERIAL: &str = "emulator-5554";
+const PACKAGE: &str = "com.beatgames.beatsaber";
+
+fn shell(runner: ScriptedRunner, args: &[&str], output: ToolOutput) -> ScriptedRunner {
+    let args: Vec<&str> = ["-s", SERIAL, "shell"]
+        .into_iter()
+        .chain(args.iter().copied())
+        .collect();
+    runner.respond("adb", &args, output)
+}
+
+/// `pm path` and `am force-stop` of `app::stop`
+fn stopped(runner: ScriptedRunner) -> ScriptedRunner {
+    let runner = shell(
+        runner,
+        &["pm", "path", PACKAGE],
+        ToolOutput::success("package:/data/app/base.apk\n"),
+    );
+    shell(
+        runner,
+        &["am", "force-stop", PACKAGE],
+        ToolOutput::success(""),
+    )
+}
+
+#[test]
+fn backs_up_external_folders_without_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let backup_dir = dir.path().join("backup");
+    let runner = shell(
+        ScriptedRunner::new(),
+        &["pm", "path", PACKAGE],
+        ToolOutput::success("package:/data/app/base.apk\n"),
+    );
+    let runner = shell(
+        runner,
+        &["dumpsys", "package", PACKAGE],
+        ToolOutput::success(
+            "    versionCode=1130 minSdk=29 targetSdk=32\n    versionName=1.40.8_7379\n",
+        ),
+    );
+    let runner = stopped(runner);
+    let runner = shell(
+        runner,
+        &["test", "-d", "/sdcard/Android/data/com.beatgames.beatsaber"],
+        ToolOutput::success(""),
+    )
+    .respond(
+        "adb",
+        &[
+            "-s",
+            SERIAL,
+            "pull",
+            "/sdcard/Android/data/com.beatgames.beatsaber",
+        ],
+        ToolOutput::success("1 file pulled\n"),
+    );
+    let runner = shell(
+        runner,
+        &["test", "-d", "/sdcard/Android/obb/com.beatgames.beatsaber"],
+        ToolOutput::failure(1, ""),
+    );
+    let runner = shell(runner, &["id -u"], ToolOutput::success("2000\n"));
+    let runner = shell(
+        runner,
+        &["su 0 sh -c 'id -u'"],
+        ToolOutput::failure(127, "/system/bin/sh: su: inaccessible or not found\n"),
+    );
+    let runner = shell(
+        runner,
+        &["run-as com.beatgames.beatsaber sh -c 'id -u'"],
+        ToolOutput::failure(
+            1,
+            "run-as: package not debuggable: com.beatgames.beatsaber\n",
+        ),
+    );
+
+    let manifest = backup::backup(&runner, SERIAL, PACKAGE, &backup_dir, &SilentReporter).unwrap();
+
+    assert!(runner.is_done());
+    assert_eq!(manifest.parts, [BackupPart::Data]);
+    assert_eq!(manifest.version_code, Some(1130));
+    assert_eq!(manifest.version_name.as_deref(), Some("1.40.8_7379"));
+    let skipped: Vec<_> = manifest.skipped.iter().map(|s| s.part).collect();
+    assert_eq!(skipped, [BackupPart::Obb, BackupPart::Internal]);
+
+    let pull = &runner.calls()[5];
+    assert_eq!(
+        pull.args.last().unwrap(),
+        backup_dir.join("data").as_os_str()
+    );
+    let saved = BackupManifest::load(&backup_dir).unwrap();
+    assert_eq!(saved.parts, manifest.parts);
+}
+
+#[test]
+fn refuses_non_empty_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "keep me").unwrap();
+    let runner = shell(
+        ScriptedRunner::new(),
+        &["pm", "path", PACKAGE],
+        ToolOutput::success("package:/data/app/base.apk\n"),
+    );
+    let runner = shell(runner, &["dumpsys", "package"], ToolOutput::success(""));
+
+    assert!(matches!(
+        backup::backup(&runner, SERIAL, PACKAGE, dir.path(), &SilentReporter),
+        Err(Error::Message(_))
+    ));
+    assert!(!dir.path().join(MANIFEST_FILE).exists());
+}
+
+#[test]
+fn restores_internal_data_as_the_app() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("data/files")).unwrap();
+    std::fs::write(dir.path().join("internal.tar"), b"tar").unwrap();
+    BackupManifest {
+        package: PACKAGE.to_string(),
+        version_code: Some(1130),
+        version_name: None,
+        serial: SERIAL.to_string(),
+        created: 0,
+        parts: vec![BackupPart::Data, BackupPart::Internal],
+        skipped: Vec::new(),
+    }
+    .save(dir.path())
+    .unwrap();
+
+    let data_dir = "/sdcard/Android/data/com.beatgames.beatsaber";
+    let staged = "/data/local/tmp/com.beatgames.beatsaber.backup.tar";
+    let runner = stopped(ScriptedRunner::new());
+    let runner = shell(runner, &["id -u"], ToolOutput::success("0\n"));
+    let runner = shell(runner, &[], ToolOutput::success(""))
+        .respond("adb", &["-s", SERIAL, "push"], ToolOutput::success(""))
+        .respond("adb", &["-s", SERIAL, "push"], ToolOutput::success(""));
+    let runner = shell(
+        runner,
+        &["pm", "list", "packages", "-U", PACKAGE],
+        ToolOutput::success(
+            "package:com.beatgames.beatsaber.extra uid:10200\npackage:com.beatgames.beatsaber uid:10123\n",
+        ),
+    );
+    let runner = shell(runner, &["chmod", "644", staged], ToolOutput::success(""));
+    let runner = shell(runner, &[], ToolOutput::success(""));
+    let runner = shell(runner, &["rm", "-f", staged], ToolOutput::success(""));
+
+    let manifest = backup::restore(&runner, SERIAL, dir.path(), &SilentReporter).unwrap();
+
+    assert!(runner.is_done());
+    assert_eq!(manifest.package, PACKAGE);
+    let calls: Vec<String> = runner.calls().iter().map(|c| c.command_line()).collect();
+    assert!(calls[3].contains(&format!("find {data_dir} -mindepth 1 -delete")));
+    assert!(calls[4].contains("files") && calls[4].ends_with(data_dir));
+    assert!(calls[5].ends_with(staged));
+    assert!(calls[8].contains("tar -xf"));
+    assert!(calls[8].contains("chown -R 10123:10123 /data/data/com.beatgames.beatsaber"));
+}
+
+#[test]
+fn restore_needs_internal_access() {
+    let dir = tempfile::tempdir().unwrap();
+    BackupManifest {
+        package: PACKAGE.to_string(),
+        version_code: None,
+        version_name: None,
+        serial: SERIAL.to_string(),
+        created: 0,
+        parts: vec![BackupPart::Data, BackupPart::Internal],
+        skipped: Vec::new(),
+    }
+    .save(dir.path())
+    .unwrap();
+
+    let runner = stopped(ScriptedRunner::new());
+    let runner = shell(runner, &["id -u"], ToolOutput::success("2000\n"));
+    let runner = shell(
+        runner,
+        &["su 0 sh -c 'id -u'"],
+        ToolOutput::failure(127, "su: not found\n"),
+    );
+    let runner = shell(
+        runner,
+        &["run-as com.beatgames.beatsaber sh -c 'id -u'"],
+        ToolOutput::failure(1, "not debuggable\n"),
+    );
+
+    assert!(backup::restore(&runner, SERIAL, dir.path(), &SilentReporter).is_err());
+    // Nothing was pushed
+    assert!(
+        runner
+            .calls()
+            .iter()
+            .all(|c| !c.command_line().contains("push"))
+    );
+}
+
+#[test]
+fn rejects_hostile_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    for package in ["x /data", "com.example' ; rm -rf / ; '"] {
+        let manifest = serde_json::json!({
+            "package": package,
+            "version_code": null,
+            "version_name": null,
+            "serial": SERIAL,
+            "created": 0,
+            "parts": ["data", "internal"],
+        });
+        std::fs::write(
+            dir.path().join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        let runner = ScriptedRunner::new();
+
+        assert!(matches!(
+            backup::restore(&runner, SERIAL, dir.path(), &SilentReporter),
+            Err(Error::InvalidPackageName(name)) if name == package
+        ));
+        assert!(runner.calls().is_empty());
+    }
+}
+
+#[test]
+fn backup_rejects_invalid_package() {
+    let dir = tempfile::tempdir().unwrap();
+    let runner = ScriptedRunner::new();
+
+    assert!(matches!(
+        backup::backup(
+            &runner,
+            SERIAL,
+            "com.example$(reboot)",
+            dir.path(),
+            &SilentReporter
+        ),
+        Err(Error::InvalidPackageName(_))
+    ));
+    assert!(runner.calls().is_empty());
+}
@@ -0,0 +1,979 @@
+//! End to end runs of the CLI against the fake SDK in `common`.
+#![cfg(unix)]
+
+mod common;
+
+use std::{fs, io::Write, path::Path};
+
+use common::{DEFAULT_IMAGE, FakeSdk, fixture, logcat, take_port};
+
+#[test]
+fn setup_installs_emulator_and_image() {
+    let sdk = FakeSdk::new();
+
+    let result = sdk.run_ok(&["setup", "--yes"]);
+
+    assert_eq!(
+        sdk.calls("sdkmanager"),
+        [["emulator", "platform-tools", DEFAULT_IMAGE]]
+    );
+    assert_eq!(result["image"], DEFAULT_IMAGE);
+    assert_eq!(result["image_installed"], true);
+    assert!(sdk.sdk_root().join("emulator/emulator").exists());
+    assert!(sdk.sdk_root().join("platform-tools/adb").exists());
+}
+
+#[test]
+fn setup_skips_installed_image() {
+    let sdk = FakeSdk::installed();
+
+    let result = sdk.run_ok(&["setup", "--yes"]);
+
+    assert!(sdk.calls("sdkmanager").is_empty());
+    assert_eq!(result["image_installed"], true);
+}
+
+#[test]
+fn create_writes_display_settings() {
+    let sdk = FakeSdk::installed();
+
+    let result = sdk.run_ok(&[
+        "create",
+        "--yes",
+        "--name",
+        "quest",
+        "--screen-size",
+        "1280x720",
+        "--fps",
+        "72",
+    ]);
+
+    assert_eq!(
+        sdk.calls("avdmanager"),
+        [["create", "avd", "-n", "quest", "-k", DEFAULT_IMAGE]]
+    );
+    assert_eq!(result["avd"], "quest");
+    assert_eq!(result["created"], true);
+    assert_eq!(result["width"], 1280);
+    assert_eq!(result["height"], 720);
+
+    let config = fs::read_to_string(sdk.avd_home().join("quest.avd/config.ini")).unwrap();
+    let lines: Vec<_> = config.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "AvdId=quest",
+            "image.sysdir.1=system-images/android-33/android-desktop/x86_64/",
+            "hw.lcd.width=1280",
+            "hw.lcd.height=720",
+            "hw.lcd.vsync=72",
+            "hw.gpu.enabled=yes",
+            "hw.gpu.mode=auto",
+        ]
+    );
+}
+
+#[test]
+fn create_replaces_existing_avd() {
+    let sdk = FakeSdk::installed();
+    sdk.run_ok(&["create", "--yes", "--name", "quest"]);
+
+    sdk.run_ok(&["create", "--yes", "--name", "quest", "--fps", "90"]);
+
+    assert_eq!(
+        sdk.calls("avdmanager"),
+        [
+            vec!["create", "avd", "-n", "quest", "-k", DEFAULT_IMAGE],
+            vec!["delete", "avd", "-n", "quest"],
+            vec!["create", "avd", "-n", "quest", "-k", DEFAULT_IMAGE],
+        ]
+    );
+    let config = fs::read_to_string(sdk.avd_home().join("quest.avd/config.ini")).unwrap();
+    assert!(config.contains("hw.lcd.vsync=90"));
+    assert!(!config.contains("hw.lcd.vsync=60"));
+}
+
+#[test]
+fn create_fails_without_image() {
+    let sdk = FakeSdk::new();
+
+    let output = sdk.run(&["create", "--yes"]);
+
+    assert!(!output.status.success());
+    assert!(sdk.calls("avdmanager").is_empty());
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let message = error["error"]["message"].as_str().unwrap();
+    assert!(message.contains("is not installed"), "{message}");
+}
+
+#[test]
+fn start_passes_settings_and_arguments() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk
+        .command()
+        .env("QUEST_EMU_EMULATOR_ARGS", "-gpu host")
+        .args(["start", "--name", "quest", "--fresh", "--", "-no-window"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let mut calls = sdk.calls("emulator");
+    let port = take_port(&mut calls[0]);
+    assert_eq!(
+        calls,
+        [[
+            "@quest",
+            "-selinux",
+            "permissive",
+            "-feature",
+            "QtRawKeyboardInput",
+            "-no-snapshot-load",
+            "-gpu",
+            "host",
+            "-no-window",
+        ]]
+    );
+    // The emulator's own output goes to stderr, leaving only the result on stdout
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["avd"], "quest");
+    assert_eq!(result["serial"], format!("emulator-{port}"));
+    assert_eq!(result["exit_code"], 0);
+    let instance = &result["instances"][0];
+    assert_eq!(instance["serial"], format!("emulator-{port}"));
+    assert_eq!(instance["read_only"], false);
+    assert_eq!(instance["exit_code"], 0);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Android emulator version"));
+}
+
+#[test]
+fn start_fails_when_emulator_fails() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk
+        .command()
+        .env("FAKE_EMULATOR_EXIT", "3")
+        .arg("start")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let mut calls = sdk.calls("emulator");
+    take_port(&mut calls[0]);
+    assert_eq!(
+        calls,
+        [[
+            "@android13desktop",
+            "-selinux",
+            "permissive",
+            "-feature",
+            "QtRawKeyboardInput"
+        ]]
+    );
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(error["error"]["message"].is_string());
+}
+
+#[test]
+fn start_translates_typed_flags() {
+    let sdk = FakeSdk::installed();
+
+    sdk.run_ok(&[
+        "start",
+        "--name",
+        "quest",
+        "--headless",
+        "--gpu",
+        "host",
+        "--memory",
+        "4096",
+        "--cores",
+        "4",
+        "--port",
+        "5556",
+        "--no-audio",
+        "--camera",
+        "none",
+        "--no-snapshot-save",
+        "--no-default-flags",
+    ]);
+
+    assert_eq!(
+        sdk.calls("emulator"),
+        [[
+            "@quest",
+            "-no-window",
+            "-gpu",
+            "host",
+            "-memory",
+            "4096",
+            "-cores",
+            "4",
+            "-port",
+            "5556",
+            "-no-audio",
+            "-camera-back",
+            "none",
+            "-no-snapshot-save",
+        ]]
+    );
+}
+
+#[test]
+fn start_combines_profile_and_flags() {
+    let sdk = FakeSdk::installed();
+    fs::write(
+        sdk.root().join("quest_emu.toml"),
+        "gpu = \"host\"\n\n[profiles.ci]\nheadless = true\ngpu = \"swiftshader_indirect\"\ndefault_flags = false\n",
+    )
+    .unwrap();
+
+    sdk.run_ok(&["start", "--profile", "ci", "--memory", "2048"]);
+    sdk.run_ok(&["start", "--gpu", "guest"]);
+    // The CLI turns settings of the profile off again
+    sdk.run_ok(&["start", "--profile", "ci", "--window", "--default-flags"]);
+    sdk.run_ok(&["start", "--no-audio", "--audio", "--headless"]);
+
+    let mut calls = sdk.calls("emulator");
+    for call in &mut calls {
+        take_port(call);
+    }
+    assert_eq!(
+        calls,
+        [
+            vec![
+                "@android13desktop",
+                "-no-window",
+                "-gpu",
+                "swiftshader_indirect",
+                "-memory",
+                "2048",
+            ],
+            vec![
+                "@android13desktop",
+                "-selinux",
+                "permissive",
+                "-feature",
+                "QtRawKeyboardInput",
+                "-gpu",
+                "guest",
+            ],
+            vec![
+                "@android13desktop",
+                "-selinux",
+                "permissive",
+                "-feature",
+                "QtRawKeyboardInput",
+                "-gpu",
+                "swiftshader_indirect",
+            ],
+            vec![
+                "@android13desktop",
+                "-selinux",
+                "permissive",
+                "-feature",
+                "QtRawKeyboardInput",
+                "-no-window",
+                "-gpu",
+                "host",
+            ],
+        ]
+    );
+}
+
+#[test]
+fn config_layers_in_precedence_order() {
+    let sdk = FakeSdk::installed();
+    let user_file = sdk.home().join(".config/quest_emu/quest_emu.toml");
+    let project_file = sdk.root().join("quest_emu.toml");
+    fs::create_dir_all(user_file.parent().unwrap()).unwrap();
+    fs::write(
+        &user_file,
+        "image = \"user-image\"\nfps = 72\nscreen_size = \"1280x720\"\nmemory = 1024\n\n\
+         [profiles.ci]\nfps = 90\ngpu = \"host\"\ncores = 2\n",
+    )
+    .unwrap();
+    fs::write(
+        &project_file,
+        "fps = 80\nscreen_size = \"1600x900\"\nmemory = 2048\n\n[profiles.ci]\ncores = 6\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        let output = sdk
+            .command()
+            .env("QUEST_EMU_MEMORY", "4096")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    let file = |path: &Path, profile: Option<&str>| serde_json::json!({ "type": "file", "path": path, "profile": profile });
+
+    let list = run(&["config", "list", "--profile", "ci"]);
+    let settings = &list["settings"];
+    let expected = [
+        (
+            "headless",
+            serde_json::json!(false),
+            serde_json::json!({ "type": "built_in" }),
+        ),
+        ("image", "user-image".into(), file(&user_file, None)),
+        ("gpu", "host".into(), file(&user_file, Some("ci"))),
+        // The project's top-level settings override the profiles of the user file
+        ("fps", 80.into(), file(&project_file, None)),
+        ("screen_size", "1600x900".into(), file(&project_file, None)),
+        ("cores", 6.into(), file(&project_file, Some("ci"))),
+        (
+            "memory",
+            4096.into(),
+            serde_json::json!({ "type": "env", "variable": "QUEST_EMU_MEMORY" }),
+        ),
+    ];
+    for (key, value, source) in expected {
+        assert_eq!(settings[key]["value"], value, "{key}");
+        assert_eq!(settings[key]["source"], source, "{key}");
+    }
+    assert_eq!(list["profiles"], serde_json::json!(["ci"]));
+    assert_eq!(list["user_file"], serde_json::json!(user_file));
+    assert_eq!(list["project_file"], serde_json::json!(project_file));
+
+    // Without the profile its settings fall through to the files' top-level settings
+    let fps = run(&["config", "get", "fps"]);
+    assert_eq!(fps["value"], 80);
+    assert_eq!(fps["source"], file(&project_file, None));
+    let gpu = run(&["config", "get", "gpu"]);
+    assert_eq!(gpu["value"], serde_json::Value::Null);
+    assert_eq!(gpu["source"], serde_json::Value::Null);
+
+    // Command line arguments override everything
+    run(&["start", "--profile", "ci"]);
+    run(&[
+        "start",
+        "--profile",
+        "ci",
+        "--memory",
+        "8192",
+        "--gpu",
+        "guest",
+    ]);
+    let calls: Vec<_> = sdk
+        .calls("emulator")
+        .into_iter()
+        .map(|mut call| {
+            take_port(&mut call);
+            // Past the AVD and the default flags
+            call.into_iter().skip(5).collect::<Vec<_>>()
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            ["-gpu", "host", "-memory", "4096", "-cores", "6"],
+            ["-gpu", "guest", "-memory", "8192", "-cores", "6"],
+        ]
+    );
+}
+
+#[test]
+fn start_rejects_invalid_options() {
+    let sdk = FakeSdk::installed();
+
+    for args in [
+        &["start", "--port", "5555"][..],
+        &["start", "--port", "80"],
+        &["start", "--memory", "128"],
+        &["start", "--read-only", "--wipe-data"],
+        &["start", "--gpu", "fast"],
+    ] {
+        let output = sdk.run(args);
+        assert!(!output.status.success(), "{args:?} succeeded");
+    }
+    assert!(sdk.calls("emulator").is_empty());
+
+    let output = sdk.run(&["config", "set", "gpu", "fast"]);
+    assert!(!output.status.success());
+    let output = sdk.run(&["config", "set", "headless", "yes"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn start_instances_share_avd_read_only() {
+    let sdk = FakeSdk::installed();
+
+    let result = sdk.run_ok(&[
+        "start",
+        "--name",
+        "quest",
+        "--instances",
+        "2",
+        "--port",
+        "auto",
+    ]);
+
+    let mut calls = sdk.calls("emulator");
+    // The instances run at the same time, so they log in any order
+    let mut ports: Vec<_> = calls.iter_mut().map(take_port).collect();
+    ports.sort();
+    assert_ne!(ports[0], ports[1]);
+    for call in &calls {
+        assert_eq!(
+            call,
+            &[
+                "@quest",
+                "-selinux",
+                "permissive",
+                "-feature",
+                "QtRawKeyboardInput",
+                "-read-only",
+            ]
+        );
+    }
+    let serials: Vec<_> = result["instances"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|instance| instance["serial"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        serials,
+        ports
+            .iter()
+            .map(|port| format!("emulator-{port}"))
+            .collect::<Vec<_>>()
+    );
+
+    // Exited instances are no longer tracked
+    let state: serde_json::Value =
+        serde_json::from_slice(&fs::read(sdk.instances_file()).unwrap()).unwrap();
+    assert_eq!(state["instances"], serde_json::json!([]));
+}
+
+#[test]
+fn start_rejects_fixed_port_for_instances() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk.run(&["start", "--instances", "2", "--port", "5556"]);
+
+    assert!(!output.status.success());
+    assert!(sdk.calls("emulator").is_empty());
+}
+
+/// Writes a state file with a running instance of `quest` and one that exited
+fn track_instances(sdk: &FakeSdk, running_pid: u32) {
+    let mut exited = std::process::Command::new("true").spawn().unwrap();
+    let exited_pid = exited.id();
+    exited.wait().unwrap();
+    let state = serde_json::json!({
+        "instances": [
+            { "avd": "quest", "port": 5570, "pid": running_pid, "read_only": true },
+            { "avd": "other", "port": 5572, "pid": exited_pid, "read_only": false },
+        ]
+    });
+    let path = sdk.instances_file();
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, state.to_string()).unwrap();
+}
+
+#[test]
+fn stop_all_stops_running_instances() {
+    let sdk = FakeSdk::installed();
+    let mut emulator = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    track_instances(&sdk, emulator.id());
+
+    let result = sdk.run_ok(&["stop", "--all"]);
+    emulator.kill().unwrap();
+    emulator.wait().unwrap();
+
+    assert_eq!(sdk.calls("adb"), [["-s", "emulator-5570", "emu", "kill"]]);
+    assert_eq!(result["stopped"][0]["serial"], "emulator-5570");
+    assert_eq!(result["stopped"].as_array().unwrap().len(), 1);
+    let state: serde_json::Value =
+        serde_json::from_slice(&fs::read(sdk.instances_file()).unwrap()).unwrap();
+    assert_eq!(state["instances"], serde_json::json!([]));
+}
+
+#[test]
+fn stop_by_serial_or_name() {
+    let sdk = FakeSdk::installed();
+    let mut emulator = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    track_instances(&sdk, emulator.id());
+
+    let missing = sdk.run(&["stop", "other"]);
+    let result = sdk.run_ok(&["stop", "quest"]);
+    emulator.kill().unwrap();
+    emulator.wait().unwrap();
+
+    assert!(!missing.status.success());
+    assert_eq!(result["stopped"][0]["avd"], "quest");
+    // Either targets or --all are required
+    assert_eq!(sdk.run(&["stop"]).status.code(), Some(2));
+}
+
+#[test]
+fn start_detach_writes_log_and_pid_file() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk
+        .command()
+        .env("FAKE_EMULATOR_SLEEP", "30")
+        .args(["start", "--detach"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let instance = &result["instances"][0];
+    let serial = instance["serial"].as_str().unwrap();
+    let pid = instance["pid"].as_u64().unwrap().to_string();
+
+    let pid_file = sdk.pids_dir().join(format!("{serial}.pid"));
+    let pid_file_contents = fs::read_to_string(&pid_file).unwrap_or_default();
+    let logs = sdk.run_ok(&["logs", "android13desktop"]);
+    let by_serial = sdk.run_ok(&["logs", serial, "--lines", "1"]);
+    sdk.run_ok(&["stop", serial]);
+    let pid_file_after_stop = pid_file.exists();
+    // The fake adb cannot stop the emulator
+    std::process::Command::new("kill")
+        .arg(&pid)
+        .status()
+        .unwrap();
+
+    assert_eq!(result["detached"], true);
+    assert_eq!(
+        instance["log"].as_str().unwrap(),
+        sdk.logs_dir()
+            .join(format!("android13desktop.{serial}.log"))
+            .to_str()
+            .unwrap()
+    );
+    assert_eq!(pid_file_contents, format!("{pid}\n"));
+    assert!(!pid_file_after_stop);
+    assert_eq!(logs["running"], true);
+    let lines = logs["lines"].as_array().unwrap();
+    assert!(
+        lines[0]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("--- Starting {serial}: "))
+    );
+    assert_eq!(lines[1], "INFO    | Android emulator version 35.1.0");
+    assert_eq!(by_serial["avd"], "android13desktop");
+    assert_eq!(by_serial["lines"], serde_json::json!([lines[1]]));
+}
+
+#[test]
+fn start_detach_reports_crash_and_rotates_log() {
+    let sdk = FakeSdk::installed();
+    let log = sdk.logs_dir().join("android13desktop.emulator-5554.log");
+    fs::create_dir_all(sdk.logs_dir()).unwrap();
+    fs::write(&log, "old line\n".repeat(600_000)).unwrap();
+
+    let output = sdk
+        .command()
+        .env("FAKE_EMULATOR_EXIT", "3")
+        .args(["start", "--detach"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(error["error"]["code"], "emulator_exited");
+    let details = &error["error"]["details"];
+    assert_eq!(details["exit_code"], 3);
+    assert_eq!(details["tail"][2], "FATAL   | No accelerator found");
+    assert!(
+        fs::read_to_string(log.with_extension("log.1"))
+            .unwrap()
+            .starts_with("old line")
+    );
+    assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 3);
+    assert_eq!(fs::read_dir(sdk.logs_dir()).unwrap().count(), 2);
+    assert_eq!(
+        fs::read_dir(sdk.pids_dir()).unwrap().count(),
+        0,
+        "no PID file is left"
+    );
+    let state: serde_json::Value =
+        serde_json::from_slice(&fs::read(sdk.instances_file()).unwrap()).unwrap();
+    assert_eq!(state["instances"], serde_json::json!([]));
+}
+
+#[test]
+fn logs_follow_until_emulator_exits() {
+    let sdk = FakeSdk::installed();
+    let mut emulator = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    track_instances(&sdk, emulator.id());
+    let log = sdk.logs_dir().join("quest.emulator-5570.log");
+    fs::create_dir_all(sdk.logs_dir()).unwrap();
+    fs::write(&log, "booting\n").unwrap();
+
+    let follow = sdk
+        .command()
+        .args(["logs", "quest", "--follow"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(b"boot completed\n")
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    emulator.kill().unwrap();
+    emulator.wait().unwrap();
+    let output = follow.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        result["lines"],
+        serde_json::json!(["booting", "boot completed"])
+    );
+    assert_eq!(result["running"], false);
+    assert!(!sdk.run(&["logs", "other"]).status.success());
+}
+
+#[test]
+fn logcat_shows_package_log_and_saves_it() {
+    let sdk = FakeSdk::installed();
+    let package = "com.beatgames.beatsaber";
+    let log = [
+        logcat::entry(
+            100,
+            logcat::INFO,
+            "ActivityManager",
+            &format!("Start proc 4321:{package}/u0a1"),
+        ),
+        logcat::entry(4321, logcat::INFO, "Unity", "Loaded"),
+        logcat::entry(4321, logcat::DEBUG, "Unity", "verbose"),
+        logcat::entry(555, logcat::INFO, "Other", "noise"),
+        logcat::entry(900, logcat::FATAL, "DEBUG", "*** *** *** *** *** *** ***"),
+        logcat::entry(
+            900,
+            logcat::FATAL,
+            "DEBUG",
+            &format!("pid: 4321, tid: 4390, name: UnityMain  >>> {package} <<<"),
+        ),
+        logcat::entry(
+            900,
+            logcat::FATAL,
+            "DEBUG",
+            "#00 pc 0000000000042f10  /data/app/lib/arm64/libil2cpp.so",
+        ),
+    ]
+    .concat();
+    fs::write(sdk.logcat_file(), log).unwrap();
+
+    let result = sdk.run_ok(&[
+        "logcat", package, "--level", "info", "--dump", "--output", "game.log",
+    ]);
+
+    assert_eq!(result["serial"], "emulator-5554");
+    assert_eq!(result["pids"], serde_json::json!([]));
+    assert_eq!(result["entries"], 4);
+    assert_eq!(result["crashes"].as_array().unwrap().len(), 1);
+    assert_eq!(result["crashes"][0]["kind"], "native_crash");
+    let saved = fs::read_to_string(sdk.root().join("game.log")).unwrap();
+    let saved: Vec<_> = saved.lines().collect();
+    assert_eq!(saved.len(), 4);
+    assert_eq!(saved[0], "10-18 14:03:07.251  4321  4322 I Unity: Loaded");
+    assert!(saved[3].ends_with("libil2cpp.so"), "{}", saved[3]);
+    assert_eq!(
+        sdk.calls("adb")[1..],
+        [
+            vec!["-s", "emulator-5554", "shell", "pidof", package],
+            vec![
+                "-s",
+                "emulator-5554",
+                "exec-out",
+                "logcat",
+                "-B",
+                "-b",
+                "main,system,crash",
+                "-d"
+            ],
+        ]
+    );
+}
+
+#[test]
+fn crash_pulls_newest_tombstone() {
+    let sdk = FakeSdk::installed();
+    let tombstones = sdk.device_dir().join("data/tombstones");
+    fs::create_dir_all(&tombstones).unwrap();
+    let text = String::from_utf8(fixture("tombstones/tombstone_00")).unwrap();
+    let hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    for (name, contents) in [
+        ("tombstone_00", text.replace("4321", "1111")),
+        ("tombstone_01", text.clone()),
+        ("tombstone_01.pb", String::new()),
+    ] {
+        fs::write(tombstones.join(name), contents).unwrap();
+    }
+    fs::File::options()
+        .write(true)
+        .open(tombstones.join("tombstone_00"))
+        .unwrap()
+        .set_modified(hour_ago)
+        .unwrap();
+
+    let result = sdk.run_ok(&["crash", "--save", "saved"]);
+
+    let tombstone = &result["tombstones"][0];
+    assert_eq!(result["tombstones"].as_array().unwrap().len(), 1);
+    assert_eq!(tombstone["name"], "tombstone_01");
+    assert_eq!(tombstone["pid"], 4321);
+    assert_eq!(tombstone["frames"][2]["status"], "missing_library");
+    assert_eq!(
+        tombstone["frames"][2]["symbol"],
+        "GameManager::Update(float)+88"
+    );
+    assert_eq!(
+        fs::read_to_string(sdk.root().join("saved/tombstone_01")).unwrap(),
+        text
+    );
+    assert_eq!(
+        sdk.calls("adb")[1..],
+        [
+            vec![
+                "-s",
+                "emulator-5554",
+                "shell",
+                "ls",
+                "-t",
+                "/data/tombstones"
+            ],
+            vec![
+                "-s",
+                "emulator-5554",
+                "exec-out",
+                "cat",
+                "/data/tombstones/tombstone_01"
+            ],
+        ]
+    );
+}
+
+/// An APK folder like `apk download` leaves behind, with an arm64 library and a main OBB
+fn write_apk_folder(dir: &Path) {
+    let file = fs::File::create(dir.join("com.example.quest.apk")).unwrap();
+    let mut apk = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default();
+    apk.start_file("AndroidManifest.xml", options).unwrap();
+    apk.write_all(&fixture("manifests/minimal.axml")).unwrap();
+    apk.start_file("lib/arm64-v8a/libmain.so", options).unwrap();
+    apk.write_all(b"\x7fELF").unwrap();
+    apk.finish().unwrap();
+
+    fs::write(dir.join("main.1.com.example.quest.obb"), b"obb").unwrap();
+}
+
+#[test]
+fn apk_install_installs_and_pushes_obbs() {
+    let sdk = FakeSdk::installed();
+    let folder = sdk.root().join("download");
+    fs::create_dir(&folder).unwrap();
+    write_apk_folder(&folder);
+    let apk = folder.join("com.example.quest.apk");
+    let obb = folder.join("main.1.com.example.quest.obb");
+
+    let result = sdk.run_ok(&[
+        "apk",
+        "install",
+        "com.example.quest",
+        folder.to_str().unwrap(),
+    ]);
+
+    let obb_dir = "/sdcard/Android/obb/com.example.quest";
+    let calls = sdk.calls("adb");
+    assert_eq!(calls[0], ["devices"]);
+    // Everything after finding the device goes to it
+    assert!(
+        calls[1..]
+            .iter()
+            .all(|call| call[..2] == ["-s", "emulator-5554"])
+    );
+    assert_eq!(
+        calls[1..].iter().map(|call| &call[2..]).collect::<Vec<_>>(),
+        [
+            vec!["shell", "getprop", "ro.product.cpu.abilist"],
+            vec!["shell", "getprop", "ro.dalvik.vm.native.bridge"],
+            vec!["install", apk.to_str().unwrap()],
+            vec!["shell", "mkdir", "-p", obb_dir],
+            vec!["push", obb.to_str().unwrap(), obb_dir],
+        ]
+    );
+    let install = &result["install"];
+    assert_eq!(install["serial"], "emulator-5554");
+    assert_eq!(install["package"], "com.example.quest");
+    assert_eq!(install["obbs"], serde_json::json!([obb]));
+    assert!(install["abi_diagnosis"].is_string());
+}
+
+#[test]
+fn apk_install_skips_abi_check() {
+    let sdk = FakeSdk::installed();
+    let folder = sdk.root().join("download");
+    fs::create_dir(&folder).unwrap();
+    write_apk_folder(&folder);
+
+    let result = sdk.run_ok(&[
+        "apk",
+        "install",
+        "com.example.quest",
+        folder.to_str().unwrap(),
+        "--skip-abi-check",
+    ]);
+
+    assert!(
+        sdk.calls("adb")
+            .iter()
+            .all(|call| !call.contains(&"getprop".to_string()))
+    );
+    assert!(result["install"]["abi_diagnosis"].is_null());
+}
+
+#[test]
+fn apk_install_ignores_obbs_of_other_packages() {
+    let sdk = FakeSdk::installed();
+    let folder = sdk.root().join("download");
+    fs::create_dir(&folder).unwrap();
+    write_apk_folder(&folder);
+    fs::write(folder.join("main.7.com.example.other.obb"), b"obb").unwrap();
+    let obb = folder.join("main.1.com.example.quest.obb");
+
+    let result = sdk.run_ok(&[
+        "apk",
+        "install",
+        "com.example.quest",
+        folder.to_str().unwrap(),
+        "--skip-abi-check",
+    ]);
+
+    assert_eq!(result["install"]["obbs"], serde_json::json!([obb]));
+    let pushes: Vec<_> = sdk
+        .calls("adb")
+        .into_iter()
+        .filter(|call| call.contains(&"push".to_string()))
+        .collect();
+    assert_eq!(pushes.len(), 1);
+}
+
+#[test]
+fn apk_install_rejects_cleanup_of_bundle() {
+    let sdk = FakeSdk::installed();
+    let bundle = sdk.root().join("quest.apks");
+    fs::write(&bundle, b"not opened").unwrap();
+
+    let output = sdk.run(&[
+        "apk",
+        "install",
+        "com.example.quest",
+        bundle.to_str().unwrap(),
+        "--cleanup",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(sdk.calls("adb").is_empty());
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let message = error["error"]["message"].as_str().unwrap();
+    assert!(message.contains("does not apply to bundles"), "{message}");
+    assert!(bundle.exists());
+}
+
+#[test]
+fn apk_keygen_requires_password_without_prompts() {
+    let sdk = FakeSdk::new();
+
+    for name in ["release.p12", "release.jks"] {
+        let output = sdk.run(&["--yes", "apk", "keygen", name]);
+
+        assert!(!output.status.success());
+        let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let message = error["error"]["message"].as_str().unwrap();
+        assert!(message.contains("QUEST_EMU_KEYSTORE_PASS"), "{message}");
+        assert!(!sdk.root().join(name).exists());
+    }
+}
+
+#[test]
+fn start_refuses_avd_running_writable() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk
+        .command()
+        .env("FAKE_EMULATOR_SLEEP", "30")
+        .args(["start", "--detach"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let second = sdk.run(&["start", "--read-only"]);
+    std::process::Command::new("kill")
+        .arg(result["instances"][0]["pid"].to_string())
+        .status()
+        .unwrap();
+
+    assert!(!second.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&second.stdout).unwrap();
+    let message = error["error"]["message"].as_str().unwrap();
+    let serial = result["instances"][0]["serial"].as_str().unwrap();
+    assert!(
+        message.contains(&format!("already running writable as {serial}")),
+        "{message}"
+    );
+    assert!(message.contains("--read-only"), "{message}");
+    assert_eq!(sdk.calls("emulator").len(), 1);
+}
+
+#[test]
+fn start_detach_instances_log_separately() {
+    let sdk = FakeSdk::installed();
+
+    let output = sdk
+        .command()
+        .env("FAKE_EMULATOR_SLEEP", "30")
+        .args(["start", "--detach", "--instances", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let instances = result["instances"].as_array().unwrap();
+    let by_avd = sdk.run(&["logs", "android13desktop"]);
+    let by_serial: Vec<_> = instances
+        .iter()
+        .map(|instance| sdk.run_ok(&["logs", instance["serial"].as_str().unwrap()]))
+        .collect();
+    for instance in instances {
+        std::process::Command::new("kill")
+            .arg(instance["pid"].to_string())
+            .status()
+            .unwrap();
+    }
+
+    assert_ne!(instances[0]["log"], instances[1]["log"]);
+    assert!(!by_avd.status.success());
+    for (instance, logs) in instances.iter().zip(&by_serial) {
+        assert_eq!(logs["log"], instance["log"]);
+        let serial = instance["serial"].as_str().unwrap();
+        let starting: Vec<_> = logs["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|line| line.as_str()?.strip_prefix("--- Starting "))
+            .collect();
+        assert_eq!(starting.len(), 1);
+        assert!(starting[0].starts_with(serial));
+    }
+}
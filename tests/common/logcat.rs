@@ -0,0 +1,36 @@
+//! Entries in the binary format of `logcat -B`.
+
+// Included by several test crates, each using only some of it
+#![allow(dead_code)]
+
+/// Priorities as logged by `android.util.Log`
+pub const DEBUG: u8 = 3;
+pub const INFO: u8 = 4;
+pub const ERROR: u8 = 6;
+pub const FATAL: u8 = 7;
+
+/// A text entry with a `logger_entry` v4 header, logged at 2025-10-18 14:03:07.251 UTC
+pub fn entry(pid: i32, priority: u8, tag: &str, message: &str) -> Vec<u8> {
+    let mut payload = vec![priority];
+    payload.extend_from_slice(tag.as_bytes());
+    payload.push(0);
+    payload.extend_from_slice(message.as_bytes());
+    payload.push(0);
+    with_header(28, pid, &payload)
+}
+
+/// An entry with a header of `header_size` bytes, 0 for the v1 header without the field
+pub fn with_header(header_size: u16, pid: i32, payload: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    entry.extend_from_slice(&header_size.to_le_bytes());
+    entry.extend_from_slice(&pid.to_le_bytes());
+    // tid, sec, nsec
+    entry.extend_from_slice(&(pid as u32 + 1).to_le_bytes());
+    entry.extend_from_slice(&1_760_796_187u32.to_le_bytes());
+    entry.extend_from_slice(&251_000_000u32.to_le_bytes());
+    // lid of the main buffer and uid
+    entry.resize(usize::from(header_size.max(20)), 0);
+    entry.extend_from_slice(payload);
+    entry
+}
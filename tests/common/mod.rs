@@ -0,0 +1,292 @@
+//! A fake Android SDK for running the CLI end to end.
+//!
+//! sdkmanager, avdmanager, the emulator and adb are shell scripts that append their
+//! arguments to a log and act just enough like the real tools for the CLI to continue.
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use tempfile::TempDir;
+
+pub mod logcat;
+
+pub const DEFAULT_IMAGE: &str = "system-images;android-33;android-desktop;x86_64";
+
+/// Appends `<tool>\t<arg>\t<arg>...` to the call log
+const RECORD: &str = r#"#!/bin/sh
+{
+    printf '%s' "$(basename "$0")"
+    for arg in "$@"; do printf '\t%s' "$arg"; done
+    printf '\n'
+} >> "$FAKE_SDK_LOG"
+"#;
+
+/// Puts the emulator and adb in place and creates the system image folder
+const SDKMANAGER: &str = r#"
+for package in "$@"; do :; done
+echo "[=======                                ] 20% Downloading emulator.zip..."
+mkdir -p "$ANDROID_SDK_ROOT/emulator" "$ANDROID_SDK_ROOT/platform-tools"
+cp "$FAKE_SDK_STUBS/emulator" "$ANDROID_SDK_ROOT/emulator/emulator"
+cp "$FAKE_SDK_STUBS/adb" "$ANDROID_SDK_ROOT/platform-tools/adb"
+mkdir -p "$ANDROID_SDK_ROOT/$(echo "$package" | tr ';' '/')"
+echo "[=======================================] 100% Unzipping..."
+"#;
+
+/// Creates the AVD folder with a config.ini like `avdmanager create avd` does
+const AVDMANAGER: &str = r#"
+action="$1 $2"
+shift 2
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -n) name="$2"; shift ;;
+        -k) image="$2"; shift ;;
+    esac
+    shift
+done
+case "$action" in
+    "create avd")
+        mkdir -p "$ANDROID_AVD_HOME/$name.avd"
+        printf 'AvdId=%s\nimage.sysdir.1=%s/\n' "$name" "$(echo "$image" | tr ';' '/')" \
+            > "$ANDROID_AVD_HOME/$name.avd/config.ini"
+        echo "Do you wish to create a custom hardware profile? [no]"
+        ;;
+    "delete avd")
+        rm -rf "$ANDROID_AVD_HOME/$name.avd"
+        echo "AVD '$name' deleted."
+        ;;
+esac
+"#;
+
+/// Logs to stdout like the real emulator, then keeps running for $FAKE_EMULATOR_SLEEP
+/// seconds or exits with $FAKE_EMULATOR_EXIT
+const EMULATOR: &str = r#"
+echo "INFO    | Android emulator version 35.1.0"
+if [ -n "$FAKE_EMULATOR_SLEEP" ]; then
+    exec sleep "$FAKE_EMULATOR_SLEEP"
+fi
+if [ "${FAKE_EMULATOR_EXIT:-0}" != 0 ]; then
+    echo "FATAL   | No accelerator found" >&2
+fi
+exit "${FAKE_EMULATOR_EXIT:-0}"
+"#;
+
+/// One emulator that runs arm64 apps through a native bridge. `pidof` prints $FAKE_PIDOF,
+/// logcat prints the binary log written to `FakeSdk::logcat_file`, and `ls` and `cat`
+/// read files below `FakeSdk::device_dir`. `-s <serial>` is accepted and ignored
+const ADB: &str = r#"
+if [ "$1" = "-s" ]; then
+    shift 2
+fi
+case "$1" in
+    devices) printf 'List of devices attached\nemulator-5554\tdevice\n\n' ;;
+    shell)
+        case "$2 $3" in
+            "getprop ro.product.cpu.abilist") echo "x86_64,arm64-v8a" ;;
+            "getprop ro.dalvik.vm.native.bridge") echo "libndk_translation.so" ;;
+            "pidof "*) [ -n "$FAKE_PIDOF" ] && echo "$FAKE_PIDOF" || exit 1 ;;
+            "ls -t") ls -t "$FAKE_DEVICE$4" ;;
+        esac
+        ;;
+    exec-out)
+        case "$2" in
+            logcat) cat "$FAKE_LOGCAT" 2>/dev/null ;;
+            cat) cat "$FAKE_DEVICE$3" ;;
+        esac
+        ;;
+    install|install-multiple) echo "Success" ;;
+    push) echo "1 file pushed, 0 skipped." ;;
+esac
+"#;
+
+pub struct FakeSdk {
+    root: TempDir,
+}
+
+impl FakeSdk {
+    /// An SDK with only the command line tools, like after `setup` downloaded them
+    pub fn new() -> Self {
+        let sdk = FakeSdk {
+            root: tempfile::tempdir().expect("Failed to create temporary folder"),
+        };
+        for (name, script) in [
+            ("sdkmanager", SDKMANAGER),
+            ("avdmanager", AVDMANAGER),
+            ("emulator", EMULATOR),
+            ("adb", ADB),
+        ] {
+            write_script(&sdk.stubs().join(name), script);
+        }
+        let bin = sdk.sdk_root().join("cmdline-tools/latest/bin");
+        for name in ["sdkmanager", "avdmanager"] {
+            copy_script(&sdk.stubs().join(name), &bin.join(name));
+        }
+        fs::create_dir_all(sdk.avd_home()).unwrap();
+        fs::create_dir_all(sdk.home()).unwrap();
+        sdk
+    }
+
+    /// An SDK with the emulator, adb and the default system image installed
+    pub fn installed() -> Self {
+        let sdk = Self::new();
+        copy_script(
+            &sdk.stubs().join("emulator"),
+            &sdk.sdk_root().join("emulator/emulator"),
+        );
+        copy_script(
+            &sdk.stubs().join("adb"),
+            &sdk.sdk_root().join("platform-tools/adb"),
+        );
+        fs::create_dir_all(sdk.sdk_root().join(DEFAULT_IMAGE.replace(';', "/"))).unwrap();
+        sdk
+    }
+
+    pub fn root(&self) -> &Path {
+        self.root.path()
+    }
+
+    pub fn sdk_root(&self) -> PathBuf {
+        self.root().join("sdk")
+    }
+
+    pub fn avd_home(&self) -> PathBuf {
+        self.root().join("avd")
+    }
+
+    pub fn home(&self) -> PathBuf {
+        self.root().join("home")
+    }
+
+    fn stubs(&self) -> PathBuf {
+        self.root().join("stubs")
+    }
+
+    fn log(&self) -> PathBuf {
+        self.root().join("calls.log")
+    }
+
+    /// The CLI with an environment that only points at the fake SDK,
+    /// so neither the real SDK nor the user's config files are used
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_quest_emu"));
+        command
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.home())
+            .env("XDG_CONFIG_HOME", self.home().join(".config"))
+            .env("XDG_RUNTIME_DIR", self.root().join("run"))
+            .env("ANDROID_SDK_ROOT", self.sdk_root())
+            .env("ANDROID_AVD_HOME", self.avd_home())
+            .env("FAKE_SDK_LOG", self.log())
+            .env("FAKE_SDK_STUBS", self.stubs())
+            .env("FAKE_LOGCAT", self.logcat_file())
+            .env("FAKE_DEVICE", self.device_dir())
+            .current_dir(self.root())
+            .args(["--json", "--progress", "none"]);
+        command
+    }
+
+    /// Runs the CLI and returns its output, whether it succeeded or not
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command()
+            .args(args)
+            .output()
+            .expect("Failed to run quest_emu")
+    }
+
+    /// Runs the CLI, expecting it to succeed, and returns its JSON result
+    pub fn run_ok(&self, args: &[&str]) -> serde_json::Value {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "quest_emu {} failed with {}\nstdout: {}\nstderr: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice(&output.stdout).expect("stdout is not a JSON document")
+    }
+
+    fn runtime(&self) -> PathBuf {
+        match cfg!(target_os = "macos") {
+            true => self.home().join("Library/Caches/quest_emu"),
+            false => self.root().join("run/quest_emu"),
+        }
+    }
+
+    /// The state file listing the emulators started by `start`
+    pub fn instances_file(&self) -> PathBuf {
+        self.runtime().join("instances.json")
+    }
+
+    /// The folder with the logs of detached emulators
+    pub fn logs_dir(&self) -> PathBuf {
+        match cfg!(target_os = "macos") {
+            true => self
+                .home()
+                .join("Library/Application Support/quest_emu/logs"),
+            false => self.home().join(".local/state/quest_emu/logs"),
+        }
+    }
+
+    /// The folder with the PID files of detached emulators
+    pub fn pids_dir(&self) -> PathBuf {
+        self.runtime().join("pids")
+    }
+
+    /// The device log adb prints for logcat, in the binary format of `logcat -B`
+    pub fn logcat_file(&self) -> PathBuf {
+        self.root().join("logcat.bin")
+    }
+
+    /// The root of the device's file system, as far as the fake adb reads it
+    pub fn device_dir(&self) -> PathBuf {
+        self.root().join("device")
+    }
+
+    /// The arguments of every invocation of `tool`, in order
+    pub fn calls(&self, tool: &str) -> Vec<Vec<String>> {
+        let log = fs::read_to_string(self.log()).unwrap_or_default();
+        log.lines()
+            .map(|line| line.split('\t').map(str::to_string).collect::<Vec<_>>())
+            .filter(|call| call[0] == tool)
+            .map(|call| call[1..].to_vec())
+            .collect()
+    }
+}
+
+fn write_script(path: &Path, body: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, format!("{RECORD}{body}")).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn copy_script(from: &Path, to: &Path) {
+    fs::create_dir_all(to.parent().unwrap()).unwrap();
+    fs::copy(from, to).unwrap();
+}
+
+/// Removes `-port <port>` from emulator arguments and returns the port,
+/// which depends on the ports that are free on this machine
+pub fn take_port(args: &mut Vec<String>) -> u16 {
+    let index = args
+        .iter()
+        .position(|arg| arg == "-port")
+        .unwrap_or_else(|| panic!("no -port in {args:?}"));
+    let port = args.remove(index + 1).parse().unwrap();
+    args.remove(index);
+    assert!(port % 2 == 0 && (5554..=5682).contains(&port), "{port}");
+    port
+}
+
+/// A fixture from tests/fixtures
+pub fn fixture(path: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path);
+    fs::read(&path).unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()))
+}
@@ -0,0 +1,42 @@
+//! Cleanup of the files `apk download` recorded.
+
+use std::path::Path;
+
+use quest_emu::apk::downloads::{DOWNLOADS_FILE, DownloadManifest, cleanup_downloads};
+
+fn download(dir: &Path, files: &[(&str, &str)]) {
+    let mut manifest = DownloadManifest::default();
+    for (name, data) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        manifest.record(dir, &path).unwrap();
+    }
+    manifest.save(dir).unwrap();
+}
+
+#[test]
+fn removes_only_recorded_files() {
+    let dir = tempfile::tempdir().unwrap();
+    download(dir.path(), &[("app.apk", "apk"), ("main.obb", "obb")]);
+    std::fs::write(dir.path().join("notes.txt"), "mine").unwrap();
+
+    let report = cleanup_downloads(dir.path()).unwrap();
+
+    assert_eq!(report.removed.len(), 2);
+    assert!(report.kept.is_empty());
+    assert!(dir.path().join("notes.txt").exists());
+    assert!(!dir.path().join(DOWNLOADS_FILE).exists());
+}
+
+#[test]
+fn keeps_files_edited_to_the_same_size() {
+    let dir = tempfile::tempdir().unwrap();
+    download(dir.path(), &[("app.apk", "apk"), ("main.obb", "obb")]);
+    std::fs::write(dir.path().join("main.obb"), "OBB").unwrap();
+
+    let report = cleanup_downloads(dir.path()).unwrap();
+
+    assert_eq!(report.removed, [dir.path().join("app.apk")]);
+    assert_eq!(report.kept, [dir.path().join("main.obb")]);
+    assert!(dir.path().join("main.obb").exists());
+}
@@ -0,0 +1,37 @@
+//! Concurrent changes to the instance state file.
+
+#![cfg(unix)]
+
+use quest_emu::instances::{Instance, InstanceState};
+
+#[test]
+fn concurrent_updates_keep_every_instance() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("instances.json");
+
+    let threads: Vec<_> = (0..16)
+        .map(|i| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                InstanceState::update(&path, |state| {
+                    state.instances.push(Instance {
+                        avd: format!("quest{i}"),
+                        port: 5554 + 2 * i,
+                        // This test process, so the instance counts as running
+                        pid: std::process::id(),
+                        read_only: false,
+                        log: None,
+                    })
+                })
+                .unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let mut ports = InstanceState::load_from(&path).unwrap().ports();
+    ports.sort();
+    assert_eq!(ports, (0..16).map(|i| 5554 + 2 * i).collect::<Vec<_>>());
+}
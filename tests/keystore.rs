@@ -0,0 +1,76 @@
+//! Saving signing keys and loading them back in every keystore format.
+
+use std::path::Path;
+
+use quest_emu::keystore::{KeystoreFormat, SigningKey};
+
+const ALIAS: &str = "quest_emu";
+const PASSWORD: &str = "correct horse";
+
+fn save(dir: &Path, format: KeystoreFormat) -> std::path::PathBuf {
+    let path = dir.join(format!("key.{format:?}"));
+    SigningKey::debug()
+        .save(&path, format, ALIAS, PASSWORD)
+        .unwrap();
+    path
+}
+
+#[test]
+fn round_trips_every_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = SigningKey::debug();
+    for format in [
+        KeystoreFormat::Pem,
+        KeystoreFormat::Pkcs12,
+        KeystoreFormat::Jks,
+    ] {
+        let path = save(dir.path(), format);
+
+        for alias in [None, Some(ALIAS)] {
+            let loaded = SigningKey::load(&path, format, alias, PASSWORD, None).unwrap();
+            assert_eq!(loaded.cert_der, key.cert_der, "{format:?}");
+            assert_eq!(loaded.private_key, key.private_key, "{format:?}");
+        }
+    }
+}
+
+#[test]
+fn rejects_wrong_password() {
+    let dir = tempfile::tempdir().unwrap();
+    for format in [KeystoreFormat::Pkcs12, KeystoreFormat::Jks] {
+        let path = save(dir.path(), format);
+
+        let result = SigningKey::load(&path, format, None, "wrong", None);
+        assert!(result.is_err(), "{format:?}");
+    }
+}
+
+#[test]
+fn rejects_unknown_alias() {
+    let dir = tempfile::tempdir().unwrap();
+    for format in [KeystoreFormat::Pkcs12, KeystoreFormat::Jks] {
+        let path = save(dir.path(), format);
+
+        let result = SigningKey::load(&path, format, Some("other"), PASSWORD, None);
+        assert!(result.is_err(), "{format:?}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn only_the_owner_can_read_saved_keys() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("key.pem");
+    // Overwriting a readable file restricts it too
+    std::fs::write(&path, "old").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    SigningKey::debug()
+        .save(&path, KeystoreFormat::Pem, ALIAS, "")
+        .unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
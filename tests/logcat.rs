@@ -0,0 +1,205 @@
+//! Parsing and filtering the binary output of `logcat -B`.
+
+#[path = "common/logcat.rs"]
+mod encode;
+
+use encode::{DEBUG, ERROR, FATAL, INFO, entry, with_header};
+use quest_emu::{
+    logcat::{self, Highlight, LogEntry, LogFilter, LogParser, Priority},
+    runner::{ScriptedRunner, ToolOutput},
+};
+
+const PACKAGE: &str = "com.beatgames.beatsaber";
+const SERIAL: &str = "emulator-5554";
+
+fn parse(bytes: &[u8]) -> Vec<LogEntry> {
+    LogParser::new().push(bytes)
+}
+
+/// Feeds the entries through a filter and returns the messages it lets through
+fn filtered(filter: &mut LogFilter, entries: &[Vec<u8>]) -> Vec<String> {
+    parse(&entries.concat())
+        .into_iter()
+        .flat_map(|entry| filter.filter(entry))
+        .map(|entry| entry.message)
+        .collect()
+}
+
+#[test]
+fn parses_entries_split_across_reads() {
+    let bytes = [
+        entry(4321, INFO, "Unity", "Loaded scene"),
+        entry(4321, DEBUG, "Unity", "second\n"),
+    ]
+    .concat();
+
+    let mut parser = LogParser::new();
+    let entries: Vec<_> = bytes
+        .chunks(7)
+        .flat_map(|chunk| parser.push(chunk))
+        .collect();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].pid, 4321);
+    assert_eq!(entries[0].tid, 4322);
+    assert_eq!(entries[0].priority, Priority::Info);
+    assert_eq!(entries[0].tag, "Unity");
+    assert_eq!(entries[0].message, "Loaded scene");
+    assert_eq!(entries[1].message, "second");
+    assert_eq!(
+        entries[0].format(),
+        "10-18 14:03:07.251  4321  4322 I Unity: Loaded scene"
+    );
+}
+
+#[test]
+fn parses_v1_headers_and_skips_binary_events() {
+    let event = with_header(24, 1000, &[0x10, 0x27, 0, 0, 0, 42, 0, 0, 0]);
+    let v1 = with_header(0, 1234, b"\x05tag\0old device\0");
+
+    let entries = parse(&[event, v1].concat());
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].priority, Priority::Warn);
+    assert_eq!(entries[0].message, "old device");
+    assert_eq!(entries[0].log_id, 0);
+}
+
+#[test]
+fn highlights_crashes_and_exceptions() {
+    let entries = parse(
+        &[
+            entry(900, FATAL, "DEBUG", "*** *** *** *** *** *** ***"),
+            entry(4321, ERROR, "AndroidRuntime", "FATAL EXCEPTION: main"),
+            entry(
+                4321,
+                ERROR,
+                "Mods",
+                "Failed to load\njava.io.IOException: gone\n\tat com.example.Loader.load(Loader.java:12)",
+            ),
+            entry(4321, ERROR, "Mods", "at the end (of the day)?"),
+        ]
+        .concat(),
+    );
+
+    let highlights: Vec<_> = entries.iter().map(LogEntry::highlight).collect();
+    assert_eq!(
+        highlights,
+        [
+            Some(Highlight::NativeCrash),
+            Some(Highlight::JavaCrash),
+            Some(Highlight::Exception),
+            None
+        ]
+    );
+    assert!(entries[0].starts_native_crash());
+}
+
+#[test]
+fn filters_by_tag_and_level() {
+    let mut filter = LogFilter::new(None, Vec::new());
+    filter.tags = vec!["Unity".to_string()];
+    filter.min_priority = Some(Priority::Info);
+
+    let shown = filtered(
+        &mut filter,
+        &[
+            entry(1, INFO, "Unity", "kept"),
+            entry(1, DEBUG, "Unity", "too verbose"),
+            entry(2, ERROR, "Other", "other tag"),
+        ],
+    );
+
+    assert_eq!(shown, ["kept"]);
+}
+
+#[test]
+fn follows_package_processes_and_their_native_crashes() {
+    let mut filter = LogFilter::new(Some(PACKAGE.to_string()), vec![4000]);
+
+    let shown = filtered(
+        &mut filter,
+        &[
+            entry(4000, INFO, "Unity", "first process"),
+            entry(555, INFO, "Other", "another app"),
+            entry(
+                100,
+                INFO,
+                "ActivityManager",
+                &format!("Start proc 4321:{PACKAGE}/u0a123 for top-activity"),
+            ),
+            entry(4321, INFO, "Unity", "restarted"),
+            // Another app crashing
+            entry(901, FATAL, "DEBUG", "*** *** *** *** *** *** ***"),
+            entry(
+                901,
+                FATAL,
+                "DEBUG",
+                "pid: 77, tid: 77, name: other  >>> com.other <<<",
+            ),
+            entry(901, FATAL, "DEBUG", "backtrace: other"),
+            // The package crashing
+            entry(900, FATAL, "DEBUG", "*** *** *** *** *** *** ***"),
+            entry(900, FATAL, "DEBUG", "Build fingerprint: 'google/sdk'"),
+            entry(
+                900,
+                FATAL,
+                "DEBUG",
+                &format!("pid: 4321, tid: 4390, name: UnityMain  >>> {PACKAGE} <<<"),
+            ),
+            entry(900, FATAL, "DEBUG", "backtrace: ours"),
+        ],
+    );
+
+    assert_eq!(
+        shown,
+        [
+            "first process",
+            "restarted",
+            "*** *** *** *** *** *** ***",
+            "Build fingerprint: 'google/sdk'",
+            &format!("pid: 4321, tid: 4390, name: UnityMain  >>> {PACKAGE} <<<"),
+            "backtrace: ours",
+        ]
+    );
+}
+
+#[test]
+fn streams_logcat_and_resolves_pids() {
+    let runner = ScriptedRunner::new()
+        .respond(
+            "adb",
+            &["-s", SERIAL, "shell", "pidof", PACKAGE],
+            ToolOutput::success("4321 4400\n"),
+        )
+        .respond(
+            "adb",
+            &["-s", SERIAL, "exec-out", "logcat", "-B"],
+            ToolOutput::success(entry(4321, INFO, "Unity", "hello")),
+        );
+
+    let pids = logcat::pids(&runner, SERIAL, PACKAGE).unwrap();
+    let mut entries = Vec::new();
+    logcat::stream(
+        &runner,
+        &logcat::logcat_command(SERIAL, true),
+        &mut |entry| entries.push(entry),
+    )
+    .unwrap();
+
+    assert_eq!(pids, [4321, 4400]);
+    assert_eq!(entries[0].message, "hello");
+    assert_eq!(
+        runner.calls()[1].args,
+        [
+            "-s",
+            SERIAL,
+            "exec-out",
+            "logcat",
+            "-B",
+            "-b",
+            "main,system,crash",
+            "-d"
+        ]
+    );
+}
@@ -0,0 +1,63 @@
+//! Rotation of the logs of detached emulators.
+
+use std::{fs, io::Write};
+
+use quest_emu::logs::{RotatingWriter, parse_log_name, rotate};
+
+#[test]
+fn writer_rotates_by_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("quest.emulator-5554.log");
+    let mut writer = RotatingWriter::new(&log, 10, 2);
+
+    for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+        writer.write_all(line.as_bytes()).unwrap();
+    }
+
+    assert_eq!(fs::read_to_string(&log).unwrap(), "fourth\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("quest.emulator-5554.log.1")).unwrap(),
+        "third\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("quest.emulator-5554.log.2")).unwrap(),
+        "second\n"
+    );
+    assert!(!dir.path().join("quest.emulator-5554.log.3").exists());
+}
+
+#[test]
+fn writer_appends_to_existing_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("quest.emulator-5554.log");
+    fs::write(&log, "--- Starting emulator-5554\n").unwrap();
+
+    let mut writer = RotatingWriter::new(&log, 1024, 1);
+    writer.write_all(b"booting\n").unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        "--- Starting emulator-5554\nbooting\n"
+    );
+}
+
+#[test]
+fn rotate_keeps_small_logs() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("quest.emulator-5554.log");
+    fs::write(&log, "short\n").unwrap();
+
+    rotate(&log, 1024, 3).unwrap();
+    rotate(&dir.path().join("missing.log"), 0, 3).unwrap();
+
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn parses_log_names() {
+    assert_eq!(
+        parse_log_name("logs/Quest.3.emulator-5556.log".as_ref()),
+        Some(("Quest.3", "emulator-5556"))
+    );
+    assert_eq!(parse_log_name("logs/quest.log.1".as_ref()), None);
+}
@@ -0,0 +1,114 @@
+//! Golden tests for the AndroidManifest.xml patch.
+//!
+//! Each `tests/fixtures/manifests/<name>.axml` is patched and decoded, then compared with
+//! `<name>.patched.xml`. Formatting and attribute order are ignored, only the elements and
+//! their attributes have to match. Run with `UPDATE_GOLDEN=1` to rewrite the golden files
+//! after an intended change to the patch.
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use quest_emu::apk::{
+    manifest::{ManifestInfo, decode_manifest},
+    patch::patch_manifest,
+};
+use xml::reader::XmlEvent;
+
+fn fixture_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/manifests")
+}
+
+fn patched(name: &str) -> String {
+    let axml = fs::read(fixture_dir().join(format!("{name}.axml"))).unwrap();
+    decode_manifest(patch_manifest(axml).unwrap(), true).unwrap()
+}
+
+/// Elements with their sorted attributes, one per line and indented by depth
+fn normalize(xml_str: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut depth = 0;
+    for event in xml::EventReader::from_str(xml_str) {
+        match event.unwrap() {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attributes: BTreeMap<_, _> = attributes
+                    .into_iter()
+                    .map(|a| (a.name.to_string(), a.value))
+                    .collect();
+                let attributes = attributes
+                    .iter()
+                    .map(|(name, value)| format!(" {name}={value:?}"))
+                    .collect::<String>();
+                lines.push(format!("{}<{name}{attributes}>", "  ".repeat(depth)));
+                depth += 1;
+            }
+            XmlEvent::EndElement { .. } => depth -= 1,
+            XmlEvent::Characters(text) => lines.push(format!("{}{text}", "  ".repeat(depth))),
+            _ => {}
+        }
+    }
+    lines
+}
+
+fn check_golden(name: &str) {
+    let actual = patched(name);
+    let golden_path = fixture_dir().join(format!("{name}.patched.xml"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, format!("{actual}\n")).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&golden_path).unwrap();
+    assert_eq!(
+        normalize(&actual),
+        normalize(&golden),
+        "{name} patched differently than {}:\n{actual}",
+        golden_path.display()
+    );
+}
+
+#[test]
+fn adds_horizon_query() {
+    check_golden("minimal");
+}
+
+#[test]
+fn keeps_existing_queries() {
+    check_golden("existing_queries");
+}
+
+#[test]
+fn already_patched_is_unchanged() {
+    check_golden("already_patched");
+
+    let axml = fs::read(fixture_dir().join("already_patched.axml")).unwrap();
+    let original = decode_manifest(axml, true).unwrap();
+    assert_eq!(normalize(&patched("already_patched")), normalize(&original));
+}
+
+#[test]
+fn patching_twice_adds_one_query() {
+    let axml = fs::read(fixture_dir().join("minimal.axml")).unwrap();
+    let once = patch_manifest(axml).unwrap();
+    let twice = patch_manifest(once.clone()).unwrap();
+
+    let once = decode_manifest(once, false).unwrap();
+    let twice = decode_manifest(twice, false).unwrap();
+    assert_eq!(normalize(&once), normalize(&twice));
+    assert_eq!(twice.matches("com.oculus.horizon").count(), 1);
+}
+
+#[test]
+fn patched_manifest_queries_horizon() {
+    for name in ["minimal", "existing_queries", "already_patched"] {
+        let info = ManifestInfo::parse(&patched(name)).unwrap();
+        assert!(info.queries_horizon, "{name} does not query horizon");
+        assert_eq!(info.package, "com.example.quest");
+        assert_eq!(info.min_sdk, Some(29));
+    }
+}
+
+#[test]
+fn rejects_invalid_axml() {
+    assert!(patch_manifest(b"not a manifest".to_vec()).is_err());
+}
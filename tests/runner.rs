@@ -0,0 +1,58 @@
+//! Timeouts of real tool runs and processes spawned by a [`ScriptedRunner`].
+
+use std::time::{Duration, Instant};
+
+use quest_emu::{
+    error::Error,
+    runner::{ScriptedRunner, SystemRunner, ToolCommand, ToolOutput, ToolRunner},
+};
+
+#[cfg(unix)]
+#[test]
+fn stream_kills_hung_tool() {
+    let command = ToolCommand::new("sh", "sh")
+        .args(["-c", "echo started; sleep 30"])
+        .timeout(Duration::from_millis(500));
+    let mut streamed = Vec::new();
+    let start = Instant::now();
+
+    let result = SystemRunner.stream(&command, &mut |chunk| streamed.extend_from_slice(chunk));
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(matches!(result, Err(Error::ToolTimedOut { tool, .. }) if tool == "sh"));
+    assert_eq!(streamed, b"started\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn stream_within_timeout() {
+    let command = ToolCommand::new("sh", "sh")
+        .args(["-c", "echo done"])
+        .timeout(Duration::from_secs(30));
+    let mut streamed = Vec::new();
+
+    let output = SystemRunner
+        .stream(&command, &mut |chunk| streamed.extend_from_slice(chunk))
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(streamed, b"done\n");
+}
+
+#[test]
+fn scripted_process_keeps_running() {
+    let runner = ScriptedRunner::new()
+        .respond_running("emulator", &["-avd"], 2, ToolOutput::failure(3, ""))
+        .respond_running("emulator", &["-avd"], usize::MAX, ToolOutput::success(""));
+    let command = ToolCommand::new("emulator", "emulator").args(["-avd", "quest"]);
+
+    let mut crashing = runner.spawn(&command).unwrap();
+    let mut healthy = runner.spawn(&command).unwrap();
+
+    assert!(crashing.try_wait().unwrap().is_none());
+    assert!(crashing.try_wait().unwrap().is_none());
+    assert_eq!(crashing.try_wait().unwrap().unwrap().code(), Some(3));
+    for _ in 0..100 {
+        assert!(healthy.try_wait().unwrap().is_none());
+    }
+}
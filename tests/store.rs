@@ -0,0 +1,117 @@
+//! Picking a store version for `apk download`.
+
+use quest_emu::apk::store::{StoreVersion, VersionQuery};
+use semver::Version;
+
+/// Store versions, newest first like `store_versions` returns them
+fn versions() -> Vec<StoreVersion> {
+    [
+        ("1.40.2_4102", "1.40.2", 1402),
+        ("1.37.0_9064817954", "1.37.0", 1370),
+        ("1.35.0", "1.35.0", 1350),
+        ("1.3.0", "1.3.0", 1130),
+        ("1.29.1", "1.29.1", 1291),
+        ("1.28.0", "1.28.0", 1280),
+        ("1.27.0", "1.27.0", 1270),
+    ]
+    .into_iter()
+    .map(|(version, semver, code)| StoreVersion {
+        version: version.to_string(),
+        semver: Version::parse(semver).unwrap(),
+        version_code: Some(code),
+        released: None,
+        size: None,
+    })
+    .collect()
+}
+
+fn resolve(query: &str) -> String {
+    VersionQuery::parse(query)
+        .resolve(&versions())
+        .unwrap()
+        .version
+        .clone()
+}
+
+#[test]
+fn bare_versions_only_match_themselves() {
+    assert!(matches!(VersionQuery::parse("1.3"), VersionQuery::Req(..)));
+    assert_eq!(resolve("1.3"), "1.3.0");
+    assert_eq!(resolve("1.37"), "1.37.0_9064817954");
+    assert_eq!(resolve("1.35.0"), "1.35.0");
+    assert_eq!(resolve("^1.3"), "1.40.2_4102");
+}
+
+#[test]
+fn joins_operators_across_spaces_and_commas() {
+    for query in [
+        ">=1.28 <1.30",
+        ">=1.28, <1.30",
+        ">= 1.28 < 1.30",
+        ">=1.28,<1.30",
+    ] {
+        assert_eq!(resolve(query), "1.29.1", "{query}");
+    }
+    assert_eq!(resolve("> 1.29, < 1.37"), "1.35.0");
+}
+
+#[test]
+fn matches_version_codes() {
+    assert!(matches!(
+        VersionQuery::parse("1280"),
+        VersionQuery::Code(1280)
+    ));
+    assert_eq!(resolve("1280"), "1.28.0");
+    assert_eq!(resolve(" 1130 "), "1.3.0");
+    assert_eq!(resolve("latest"), "1.40.2_4102");
+}
+
+#[test]
+fn non_semver_versions_match_exactly() {
+    let query = VersionQuery::parse("1.37.0_9064817954");
+    assert!(matches!(&query, VersionQuery::Exact(v) if v == "1.37.0_9064817954"));
+    assert_eq!(
+        query.resolve(&versions()).unwrap().version,
+        "1.37.0_9064817954"
+    );
+    assert_eq!(resolve("1.40.2_4102"), "1.40.2_4102");
+
+    let error = VersionQuery::parse("1.37.0_1")
+        .resolve(&versions())
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with("No version matches 1.37.0_1."), "{error}");
+}
+
+#[test]
+fn suggests_closest_versions() {
+    let error = |query: &str| {
+        VersionQuery::parse(query)
+            .resolve(&versions())
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(
+        error("1.36"),
+        "No version matches 1.36. Closest versions: \
+         1.37.0_9064817954, 1.35.0, 1.40.2_4102, 1.29.1, 1.28.0"
+    );
+    assert_eq!(
+        error("1.37.1_5"),
+        "No version matches 1.37.1_5. Closest versions: \
+         1.37.0_9064817954, 1.35.0, 1.40.2_4102, 1.29.1, 1.28.0"
+    );
+    assert_eq!(
+        error("1271"),
+        "No version matches version code 1271. Closest versions: \
+         1.27.0, 1.28.0, 1.29.1, 1.35.0, 1.37.0_9064817954"
+    );
+    assert_eq!(
+        VersionQuery::parse("latest")
+            .resolve(&[])
+            .unwrap_err()
+            .to_string(),
+        "No versions are available"
+    );
+}
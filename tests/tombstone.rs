@@ -0,0 +1,163 @@
+//! Parsing tombstones and symbolicating their frames against local libraries.
+
+use std::path::{Path, PathBuf};
+
+use quest_emu::tombstone::{Frame, SymbolStatus, Symbols, Tombstone};
+
+fn fixture() -> Tombstone {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tombstones/tombstone_00");
+    Tombstone::parse(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn parses_crashed_thread() {
+    let tombstone = fixture();
+
+    assert_eq!(
+        tombstone.process.as_deref(),
+        Some("com.beatgames.beatsaber")
+    );
+    assert_eq!(tombstone.pid, Some(4321));
+    assert_eq!(tombstone.thread.as_deref(), Some("UnityMain"));
+    assert_eq!(tombstone.abi.as_deref(), Some("arm64"));
+    assert_eq!(
+        tombstone.signal.as_deref(),
+        Some("signal 6 (SIGABRT), code -1 (SI_QUEUE), fault addr --------")
+    );
+    assert_eq!(
+        tombstone.abort_message.as_deref(),
+        Some("Mod songloader failed: null beatmap")
+    );
+    // Only the crashed thread
+    assert_eq!(tombstone.frames.len(), 4);
+}
+
+#[test]
+fn parses_frames() {
+    let frames = fixture().frames;
+
+    assert_eq!(frames[0].pc, 0x51a38);
+    assert_eq!(frames[0].library(), "libc.so");
+    assert_eq!(frames[0].symbol.as_deref(), Some("abort+164"));
+    assert_eq!(frames[1].symbol, None);
+    assert_eq!(frames[1].build_id.as_deref(), Some("4f2c9a1b7d3e5f60"));
+    // Loaded from inside the APK, with an offset and a symbol with parentheses
+    assert_eq!(frames[2].index, 2);
+    assert_eq!(frames[2].library(), "libil2cpp.so");
+    assert_eq!(
+        frames[2].symbol.as_deref(),
+        Some("GameManager::Update(float)+88")
+    );
+    assert_eq!(frames[2].build_id.as_deref(), Some("9b1f0e3c"));
+}
+
+#[test]
+fn rejects_text_without_backtrace() {
+    assert!(Tombstone::parse("pid: 1, tid: 1, name: init  >>> init <<<\n").is_err());
+}
+
+#[test]
+fn reports_missing_libraries() {
+    let symbols = Symbols::load(&[]).unwrap();
+
+    let frame = symbols.symbolicate(&fixture().frames[1]).unwrap();
+
+    assert_eq!(frame.status, SymbolStatus::MissingLibrary);
+    assert!(frame.locations.is_empty());
+}
+
+/// The test executable stands in for an unstripped mod library
+#[cfg(target_os = "linux")]
+mod symbolication {
+    use super::*;
+
+    #[inline(never)]
+    fn crashing_function() -> u32 {
+        std::hint::black_box(42)
+    }
+
+    fn executable() -> PathBuf {
+        std::env::current_exe().unwrap().canonicalize().unwrap()
+    }
+
+    /// Where the executable is loaded, from its first mapping in /proc/self/maps
+    fn load_bias() -> u64 {
+        let exe = executable();
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let line = maps
+            .lines()
+            .find(|line| line.ends_with(exe.to_str().unwrap()))
+            .unwrap();
+        let start = line.split('-').next().unwrap();
+        u64::from_str_radix(start, 16).unwrap()
+    }
+
+    fn frame(build_id: Option<&str>) -> Frame {
+        assert_eq!(crashing_function(), 42);
+        let address = crashing_function as *const () as usize as u64;
+        Frame {
+            index: 0,
+            pc: address - load_bias(),
+            path: format!(
+                "/data/app/mods/lib/arm64/{}",
+                executable().file_name().unwrap().to_str().unwrap()
+            ),
+            symbol: None,
+            build_id: build_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn resolves_function_and_source_line() {
+        let symbols = Symbols::load(&[executable()]).unwrap();
+
+        let frame = symbols.symbolicate(&frame(None)).unwrap();
+
+        assert_eq!(frame.status, SymbolStatus::Symbolicated);
+        assert_eq!(frame.symbols, Some(executable()));
+        let location = frame.locations.last().unwrap();
+        assert!(
+            location
+                .function
+                .as_deref()
+                .unwrap()
+                .ends_with("crashing_function"),
+            "{location:?}"
+        );
+        assert!(location.file.as_deref().unwrap().ends_with("tombstone.rs"));
+        assert!(location.line.is_some());
+    }
+
+    #[test]
+    fn only_indexes_shared_libraries_in_folders() {
+        let symbols = Symbols::load(&[executable().parent().unwrap().to_path_buf()]).unwrap();
+
+        // Folders are searched for .so files only
+        let frame = symbols.symbolicate(&frame(None)).unwrap();
+
+        assert_eq!(frame.status, SymbolStatus::MissingLibrary);
+    }
+
+    #[test]
+    fn refuses_symbols_of_another_build() {
+        let symbols = Symbols::load(&[executable()]).unwrap();
+
+        let frame = symbols.symbolicate(&frame(Some("0000"))).unwrap();
+
+        // Linkers without --build-id leave nothing to compare
+        let has_build_id = {
+            use object::Object;
+            let data = std::fs::read(executable()).unwrap();
+            object::File::parse(&*data)
+                .unwrap()
+                .build_id()
+                .unwrap()
+                .is_some()
+        };
+        let expected = match has_build_id {
+            true => SymbolStatus::BuildIdMismatch,
+            false => SymbolStatus::Symbolicated,
+        };
+        assert_eq!(frame.status, expected);
+    }
+}
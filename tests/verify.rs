@@ -0,0 +1,243 @@
+//! APK signature verification of crafted and truncated APKs.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use quest_emu::apk::verify::{SignatureScheme, verify_apk};
+
+/// An empty zip: just the end of central directory record
+fn eocd(cd_offset: u32, cd_size: u32) -> Vec<u8> {
+    let mut eocd = vec![0x50, 0x4b, 0x05, 0x06];
+    eocd.extend([0; 8]);
+    eocd.extend(cd_size.to_le_bytes());
+    eocd.extend(cd_offset.to_le_bytes());
+    eocd.extend([0; 2]);
+    eocd
+}
+
+fn write(dir: &Path, name: &str, data: &[u8]) -> std::path::PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let full = eocd(0, 0);
+    for len in [0, 3, 4, 21] {
+        let path = write(dir.path(), &format!("short{len}.apk"), &full[..len]);
+        assert!(verify_apk(&path).is_err(), "{len} bytes");
+    }
+}
+
+#[test]
+fn rejects_eocd_too_close_to_the_end() {
+    let dir = tempfile::tempdir().unwrap();
+    // Enough bytes in total, but the EOCD signature leaves no room for the record
+    let mut data = vec![0; 30];
+    data.extend([0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 0, 0]);
+    let path = write(dir.path(), "close.apk", &data);
+
+    assert!(verify_apk(&path).is_err());
+}
+
+#[test]
+fn rejects_central_directory_outside_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), "outside.apk", &eocd(1000, 46));
+
+    assert!(verify_apk(&path).is_err());
+}
+
+#[test]
+fn rejects_crafted_signing_block_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    for block_size in [u64::MAX, u64::MAX - 7, 0, 8, 23] {
+        let mut data = block_size.to_le_bytes().to_vec();
+        data.extend(b"APK Sig Block 42");
+        data.extend(eocd(24, 0));
+        let path = write(dir.path(), "crafted.apk", &data);
+
+        assert!(verify_apk(&path).is_err(), "block size {block_size}");
+    }
+}
+
+#[test]
+fn unsigned_zip_is_not_valid() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), "empty.apk", &eocd(0, 0));
+
+    let report = verify_apk(&path).unwrap();
+    assert!(report.schemes.is_empty());
+    assert!(!report.is_valid());
+}
+
+const V1_SIGNED: &str = "tests/fixtures/apks/v1_signed.apk";
+const V1_SIGNED_SHA1: &str = "tests/fixtures/apks/v1_signed_sha1.apk";
+/// Certificate the fixtures were signed with by `jarsigner`
+const FIXTURE_CERT_SHA256: &str = "08:09:0F:56:56:F9:63:C6:36:A4:F9:64:90:0A:30:3E:B7:6E:55:AB:9C:57:55:6A:EB:80:FB:28:38:B0:BC:C7";
+
+/// Copies `apk`, replacing or adding the entry `name`
+fn with_entry(apk: &str, dir: &Path, name: &str, data: &[u8]) -> std::path::PathBuf {
+    let mut source = zip::ZipArchive::new(std::fs::File::open(apk).unwrap()).unwrap();
+    let path = dir.join("modified.apk");
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    for i in 0..source.len() {
+        let entry = source.by_index_raw(i).unwrap();
+        if entry.name() != name {
+            writer.raw_copy_file(entry).unwrap();
+        }
+    }
+    writer
+        .start_file(name, zip::write::SimpleFileOptions::default())
+        .unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap();
+    path
+}
+
+fn v1_error(path: &Path) -> String {
+    let report = verify_apk(path).unwrap();
+    assert!(!report.is_valid());
+    let [scheme] = &report.schemes[..] else {
+        panic!("expected only a v1 signature, got {:?}", report.schemes);
+    };
+    assert_eq!(scheme.scheme, SignatureScheme::V1);
+    scheme.result.clone().unwrap_err()
+}
+
+#[test]
+fn verifies_v1_signatures() {
+    for (apk, algorithm) in [
+        (V1_SIGNED, "RSASSA-PKCS1-v1_5 with SHA2-256"),
+        (V1_SIGNED_SHA1, "RSASSA-PKCS1-v1_5 with SHA-1"),
+    ] {
+        let report = verify_apk(Path::new(apk)).unwrap();
+
+        assert!(report.is_valid(), "{apk}: {report:?}");
+        let signers = report.schemes[0].result.as_ref().unwrap();
+        assert_eq!(signers.len(), 1);
+        assert_eq!(signers[0].cert_sha256, FIXTURE_CERT_SHA256);
+        assert_eq!(signers[0].algorithm, algorithm);
+    }
+}
+
+#[test]
+fn rejects_tampered_v1_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = with_entry(V1_SIGNED, dir.path(), "classes.dex", b"dex\n036\0");
+
+    let error = v1_error(&path);
+    assert!(error.contains("classes.dex was modified"), "{error}");
+}
+
+#[test]
+fn rejects_unsigned_v1_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = with_entry(V1_SIGNED, dir.path(), "lib/arm64-v8a/libmod.so", b"ELF");
+
+    let error = v1_error(&path);
+    assert!(error.contains("libmod.so is not listed"), "{error}");
+}
+
+#[test]
+fn rejects_tampered_manifest_and_signature_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(V1_SIGNED).unwrap()).unwrap();
+    for name in ["META-INF/MANIFEST.MF", "META-INF/TEST.SF"] {
+        let mut data = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        let path = with_entry(
+            V1_SIGNED,
+            dir.path(),
+            name,
+            data.replace("Created-By: ", "Created-By: Mallory ")
+                .as_bytes(),
+        );
+
+        assert!(!v1_error(&path).is_empty());
+    }
+}
+
+#[test]
+fn rejects_truncated_v1_apk() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read(V1_SIGNED).unwrap();
+    for len in [data.len() / 2, data.len() - 10] {
+        let path = write(dir.path(), "truncated.apk", &data[..len]);
+
+        assert!(verify_apk(&path).is_err(), "{len} bytes");
+    }
+}
+
+/// v2 signed with the EC and DSA keys `apksigner` supports besides RSA
+const V2_SIGNED: [(&str, &str); 3] = [
+    (
+        "tests/fixtures/apks/v2_ecdsa_p256.apk",
+        "ECDSA with SHA2-256",
+    ),
+    (
+        "tests/fixtures/apks/v2_ecdsa_p384.apk",
+        "ECDSA with SHA2-512",
+    ),
+    ("tests/fixtures/apks/v2_dsa.apk", "DSA with SHA2-256"),
+];
+
+#[test]
+fn verifies_ecdsa_and_dsa_v2_signatures() {
+    for (apk, algorithm) in V2_SIGNED {
+        let report = verify_apk(Path::new(apk)).unwrap();
+
+        assert!(report.is_valid(), "{apk}: {report:?}");
+        let [scheme] = &report.schemes[..] else {
+            panic!(
+                "{apk}: expected only a v2 signature, got {:?}",
+                report.schemes
+            );
+        };
+        assert_eq!(scheme.scheme, SignatureScheme::V2);
+        assert_eq!(scheme.result.as_ref().unwrap()[0].algorithm, algorithm);
+    }
+}
+
+#[test]
+fn rejects_modified_ecdsa_and_dsa_v2_apks() {
+    let dir = tempfile::tempdir().unwrap();
+    for (apk, _) in V2_SIGNED {
+        let mut data = std::fs::read(apk).unwrap();
+        // Inside the first entry, which the content digest covers
+        data[40] ^= 0xff;
+        let path = write(dir.path(), "modified.apk", &data);
+
+        let report = verify_apk(&path).unwrap();
+        assert!(!report.is_valid(), "{apk}");
+        let error = report.schemes[0].result.as_ref().unwrap_err();
+        assert!(error.contains("modified after signing"), "{apk}: {error}");
+    }
+}
+
+#[test]
+fn rejects_tampered_ecdsa_and_dsa_signed_data() {
+    let dir = tempfile::tempdir().unwrap();
+    for (apk, _) in V2_SIGNED {
+        let mut data = std::fs::read(apk).unwrap();
+        // The certificate's common name, part of the signed data
+        let name = data
+            .windows(14)
+            .position(|window| window == b"Quest Emu Test")
+            .unwrap();
+        data[name] = b'q';
+        let path = write(dir.path(), "tampered.apk", &data);
+
+        let report = verify_apk(&path).unwrap();
+        let error = report.schemes[0].result.as_ref().unwrap_err();
+        assert!(error.contains("Signature does not match"), "{apk}: {error}");
+    }
+}